@@ -3,232 +3,486 @@
 #[macro_use]
 extern crate log;
 
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
 use std::process::exit;
-use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, sleep};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use boardconfig::*;
-use futures::sync::mpsc::channel;
+use futures::future::{select_all, select_ok};
 use serde_json::to_string as to_json_string;
-use stratum::{stratum::*, util::*, work::*};
+use stratum::{backend::*, board::*, stratum::*, util::*, work::*};
 use tokio::prelude::*;
 use tokio::runtime::current_thread;
+use tokio::timer::Interval;
 
-fn main_loop(boards: Arc<Mutex<Vec<u16>>>, i2c: Arc<Mutex<I2c>>) {
+type Boards = Arc<Mutex<BoardManager<I2c>>>;
+
+// brings up the chips of a board at `setting`
+fn init_chips(
+    i2c: Arc<Mutex<I2c>>,
+    setting: Setting,
+) -> impl FnMut(u16) -> io::Result<()> + Send + 'static {
+    move |id| {
+        init_board(
+            id,
+            setting.voltage as f32,
+            setting.param,
+            i2c.clone(),
+            Arc::default(),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+    }
+}
+
+fn main_loop(boards: Option<Boards>, i2c: Option<Arc<Mutex<I2c>>>) -> Result<(), Error> {
     let config = get_config();
 
+    // the settings tuned before are used from boot on
+    let profile = config
+        .tune
+        .as_ref()
+        .and_then(|tune| match Profile::load(&tune.profile) {
+            Ok(profile) if profile.target == tune.target => Some(profile),
+            Ok(_) => {
+                info!("=> the profile is tuned for another target, tune again!");
+                None
+            }
+            Err(e) => {
+                warn!("load tune profile err: {}!", e);
+                None
+            }
+        });
+
     // start init boards
-    boards.lock().unwrap().clear();
-    for id in &config.board.enabled {
-        let (voltage, param) = config.board.get_setting(*id);
-        init_board(*id, voltage, param, i2c.clone(), boards.clone()).expect("init board err!");
+    let mut settings = BTreeMap::new();
+    if let (Some(boards), Some(i2c)) = (&boards, &i2c) {
+        let mut boards = boards.lock().unwrap();
+        boards.power_down();
+        for id in &config.board.enabled {
+            let setting = match profile.as_ref().and_then(|x| x.boards.get(id)) {
+                Some(board) => board.setting,
+                None => {
+                    let (voltage, param) = config.board.get_setting(*id);
+                    Setting {
+                        voltage: f64::from(voltage),
+                        param,
+                    }
+                }
+            };
+            // a board failing is faulted, the others keep hashing
+            let result = boards.power_up(*id, setting.voltage);
+            let init = init_chips(i2c.clone(), setting);
+            match result.and_then(|_| boards.start(*id, init)) {
+                Ok(()) => {
+                    settings.insert(*id, setting);
+                }
+                Err(e) => error!("init board {} err: {}", id, e),
+            }
+        }
     }
 
-    let mut pool0 = Pool::new(&config.pool[0].addr);
-    let connect_pool0 = pool0.connect(&config, 0).select2(pool0.checker());
-
-    let pool_sender = Arc::new(Mutex::new(vec![pool0.sender()]));
-    let pool_diff = Arc::new(Mutex::new(vec![pool0.diff.clone()]));
-    let submitted_nonce = Arc::new(Mutex::new(vec![pool0.submitted_nonce.clone()]));
+    let subwork2_stream = Subwork2Stream::new(pool_strategy(&config.strategy));
+    let mut pool_sender = Vec::new();
+    let mut pool_diff = Vec::new();
+    let mut ledger = Vec::new();
+    let mut user = Vec::new();
+    let mut run_pools = Vec::new();
 
-    let subwork2_stream = Subwork2Stream::default();
-    let pool0_data = PoolData::from_pool(&mut pool0, Duration::from_secs(20));
-    subwork2_stream.pools.lock().unwrap().push(pool0_data);
+    for (i, pool_config) in config.pool.iter().enumerate() {
+        let mut pool = Pool::new(&pool_config.addr);
+        pool_sender.push(pool.sender());
+        pool_diff.push(pool.diff.clone());
+        ledger.push(pool.ledger.clone());
+        user.push(pool_config.user.clone());
 
-    let mut user = vec![config.pool[0].user.clone()];
-    let (pool1_data_sender, pool1_data_receiver) = channel(1);
-    if config.pool.get(1).is_some() {
-        user.push(config.pool[1].user.clone());
-        thread::spawn(move || loop {
-            let mut pool1 = Pool::new(&config.pool[1].addr);
-            let task = Some(pool1.connect(&config, 1).select2(pool1.checker()));
-
-            let pool1_data = PoolData::from_pool(&mut pool1, Duration::from_secs(10));
-            let pool1_sender = pool1.sender();
-            let pool1_diff = pool1.diff.clone();
-            let pool1_submitted_nonce = pool1.submitted_nonce.clone();
-            if let Err(e) = pool1_data_sender
-                .clone()
-                .send((pool1_data, pool1_sender, pool1_diff, pool1_submitted_nonce))
-                .wait()
-            {
-                error!("send pool data err: {:?}!", e)
-            };
+        let pool_data = PoolData::from_pool(&mut pool, pool_config.weight);
+        subwork2_stream.pools.lock().unwrap().push(pool_data);
 
-            let mut runtime = current_thread::Runtime::new().unwrap();
-            let _ = runtime.block_on(task);
-        });
+        run_pools.push(pool.run(config.clone(), i).map_err(move |e| {
+            error!("pool {} err: {}!", i, e);
+            e
+        }));
     }
-    let pools_data = subwork2_stream.pools.clone();
-    let pool_sender_clone = pool_sender.clone();
-    let pool_diff_clone = pool_diff.clone();
-    let submitted_nonce_clone = submitted_nonce.clone();
-    let get_pool1_data = pool1_data_receiver.for_each(|(data, sender, diff, nonce)| {
-        let mut pools_data = pools_data.lock().unwrap();
-        if pools_data.len() == 1 {
-            pools_data.push(data);
-            pool_sender_clone.lock().unwrap().push(sender);
-            pool_diff_clone.lock().unwrap().push(diff);
-            submitted_nonce_clone.lock().unwrap().push(nonce);
-        } else {
-            pools_data[1] = data;
-            pool_sender_clone.lock().unwrap()[1] = sender;
-            pool_diff_clone.lock().unwrap()[1] = diff;
-            submitted_nonce_clone.lock().unwrap()[1] = nonce;
+    let pool_sender = Arc::new(Mutex::new(pool_sender));
+    let pool_diff = Arc::new(Mutex::new(pool_diff));
+    let ledger = Arc::new(Mutex::new(ledger));
+
+    let mut receiver_stats = Vec::new();
+    let mut backend: Box<dyn Backend> = match (&config.cpu, &config.serial) {
+        (Some(cpu), _) => Box::new(CpuBackend::new(cpu.threads, cpu.difficulty)),
+        (None, Some(serial)) => {
+            let backend = SerialBackend::new(&serial.ports, serial.window);
+            receiver_stats = backend.receiver_stats();
+            Box::new(backend)
         }
-        Ok(())
-    });
+        (None, None) => {
+            let fpga = FpgaBackend::new()?;
+            if let Some(ref boards) = boards {
+                let writer = fpga.writer();
+                boards.lock().unwrap().on_sender(move |id, enable| {
+                    let mut writer = writer.lock().unwrap();
+                    if enable {
+                        writer.enable_sender(id as usize);
+                    } else {
+                        writer.disable_sender(id as usize);
+                    }
+                });
+            }
+            Box::new(fpga)
+        }
+    };
+    let capabilities = backend.capabilities();
+    let nonces = backend.nonces();
+    let hash_stats = backend.stats();
+
+    // the boards hashing are stepped to their best setting on the results of the backend
+    let tune = match (&config.tune, &hash_stats, &boards, &i2c) {
+        (Some(tune), Some(stats), Some(boards), Some(i2c)) => {
+            let (tune, stats, boards, i2c) =
+                (tune.clone(), stats.clone(), boards.clone(), i2c.clone());
+            let mut tuner = Tuner::new(tune.clone());
+            let mut saved = profile.unwrap_or_default();
+            saved.target = tune.target;
+            for (id, setting) in settings {
+                let now = Instant::now();
+                match saved.boards.get(&id) {
+                    Some(board) => tuner.add_tuned(id, board, &stats.lock().unwrap(), now),
+                    None => tuner.add(id, setting, &stats.lock().unwrap(), now),
+                }
+            }
 
-    let fpga_writer = Arc::new(Mutex::new(fpga::writer()));
+            let tune = Interval::new_interval(Duration::from_secs(10))
+                .map_err(|e| {
+                    error!("tune interval err: {:?}", e);
+                    Error::Timeout
+                })
+                .for_each(move |_| {
+                    let mut boards = boards.lock().unwrap();
+                    let hashing = boards.hashing();
+                    let actions = tuner.update(&stats.lock().unwrap(), &hashing, Instant::now());
+                    for action in actions {
+                        match action {
+                            TuneAction::Set { board, setting } => {
+                                let init = init_chips(i2c.clone(), setting);
+                                if let Err(e) = boards.retune(board, setting.voltage, init) {
+                                    error!("retune board {} err: {}", board, e);
+                                    tuner.remove(board);
+                                }
+                            }
+                            TuneAction::Tuned { .. } => {
+                                saved.boards.extend(tuner.profile().boards);
+                                if let Err(e) = saved.save(&tune.profile) {
+                                    error!("save tune profile err: {}", e);
+                                }
+                            }
+                        }
+                    }
+                    Ok(())
+                });
+            Some(tune)
+        }
+        _ => None,
+    };
 
-    let fpga_writer_clone = fpga_writer.clone();
-    let send_to_fpga = subwork2_stream.for_each(|(sw2, notify, timeout)| {
+    let ledger_clone = ledger.clone();
+    let report_shares = Interval::new_interval(Duration::from_secs(60))
+        .map_err(|e| {
+            error!("report interval err: {:?}", e);
+            Error::Timeout
+        })
+        .for_each(move |_| {
+            for (i, ledger) in ledger_clone.lock().unwrap().iter().enumerate() {
+                let stats = ledger.lock().unwrap().stats();
+                info!(
+                    "=> pool {}: accepted {}, rejected {}, stale {}, lost {}, latency: {:?}!",
+                    i,
+                    stats.accepted,
+                    stats.rejected,
+                    stats.stale,
+                    stats.lost,
+                    stats.average_latency()
+                );
+            }
+
+            for (i, stats) in receiver_stats.iter().enumerate() {
+                let stats = *stats.lock().unwrap();
+                info!(
+                    "=> chain {}: nonces {}, crc errors {}, duplicates {}, unmatched {}, resyncs {}!",
+                    i,
+                    stats.nonces,
+                    stats.crc_errors,
+                    stats.duplicates,
+                    stats.unmatched,
+                    stats.resyncs
+                );
+            }
+
+            if let Some(ref hash_stats) = hash_stats {
+                let now = Instant::now();
+                let hash_stats = hash_stats.lock().unwrap();
+                let elapsed = hash_stats.elapsed(now);
+                for (id, board) in &hash_stats.boards {
+                    info!(
+                        "=> board {}: {:.2} GH/s, {} chips, hw errors: {:.2}%!",
+                        id,
+                        board.hashrate(elapsed) / 1e9,
+                        board.chips.len(),
+                        board.hw_error_rate() * 100.0
+                    );
+                    let silent = board.silent_chips(now, Duration::from_secs(600));
+                    if !silent.is_empty() {
+                        warn!("=> board {}: no result from chips {:?}!", id, silent);
+                    }
+                }
+            }
+            Ok(())
+        });
+
+    // fails only when all the pools have failed
+    let run_pools = select_ok(run_pools).map(drop);
+
+    let pools = subwork2_stream.pools.clone();
+    let stale_shares = config.client.stale_shares;
+    let hungry = backend.hungry();
+    let send_work = subwork2_stream.for_each(move |(mut sw2, notify, timeout)| {
         //debug!("{:?}", &sw2);
-        fpga_writer_clone.lock().unwrap().writer_subwork2(sw2);
+        // the versions are rolled by the backend or over its midstates
+        if !capabilities.version_rolling && capabilities.midstates == 1 {
+            sw2.vermask = 0;
+        }
+        backend.submit(sw2);
+        if backend.idle() {
+            return future::Either::A(future::ok(()));
+        }
 
         // TODO
         let notify_clone = notify.clone();
-        notify
-            .inspect(move |_| drop(notify_clone.notified()))
-            .timeout(timeout)
-            .then(|_| Ok(()))
+        // or until a hasher of the backend runs dry
+        let hungry = match hungry {
+            Some(ref hungry) => future::Either::A(hungry.clone()),
+            None => future::Either::B(future::empty()),
+        };
+        future::Either::B(
+            notify
+                .inspect(move |_| drop(notify_clone.notified()))
+                .select(hungry)
+                .timeout(timeout)
+                .then(|_| Ok(())),
+        )
     });
 
-    let (nonce_reader, nonce_receiver) = fpga::reader().read_nonce();
-
-    let exit1 = Notify::default();
-    let exit1_receiver = exit1.clone();
-    let exit2 = Notify::default();
-    let exit2_receiver = exit2.clone();
+    let receive_nonce = nonces
+        .map_err(|_| Error::ChannelClosed)
+        .for_each(move |found| {
+            let target = found.target();
+            let Found {
+                subwork2: sw2,
+                nonce,
+                version_bits,
+            } = found;
+            let pool = sw2.pool;
 
-    thread::spawn(move || {
-        let mut runtime = current_thread::Runtime::new().unwrap();
-        let _ = runtime.block_on(nonce_reader.select2(exit1_receiver).then(|_| {
-            exit2.notify();
-            Result::<_, ()>::Ok(())
-        }));
-    });
+            let hash = Target::from_be_bytes(&target);
+            let diff = hash.difficulty();
+            debug!("found nonce: 0x{:08x}, difficulty: {:0<18}", nonce, diff);
 
-    let mut offset = 0u32;
-    let mut nonce_id = [0; 2];
-    let receive_nonce = nonce_receiver.for_each(move |received| {
-        let fpga_writer = fpga_writer.clone();
-        let nonce = u32::from_le_bytes(unsafe { *(received[0..4].as_ptr() as *const [u8; 4]) });
-        let version_count =
-            u32::from_le_bytes(unsafe { *(received[8..12].as_ptr() as *const [u8; 4]) })
-                - u32::from(received[7].wrapping_sub(received[5]) & 0x7f);
-
-        let subworks = fpga_writer.lock().unwrap().subworks();
-        if subworks.is_empty() {
-            debug!("received: {}, but there is no subwork!", received.to_hex());
-            return Ok(());
-        }
+            let block = sw2.network_target().is_met_by(&hash);
+            if block {
+                warn!(
+                    "=> found block (job: {}, nonce: 0x{:08x}, hash: {})!",
+                    sw2.workid, nonce, hash
+                );
+            }
 
-        for sw2 in subworks {
-            let pool = sw2.pool;
+            let pool_difficulty = *pool_diff.lock().unwrap()[pool].lock().unwrap();
+            if block || Target::from_difficulty(pool_difficulty).is_met_by(&hash) {
+                let share = Share {
+                    pool,
+                    job_id: sw2.workid.clone(),
+                    nonce,
+                    version_bits,
+                    difficulty: diff,
+                    pool_difficulty,
+                    block,
+                    submit_time: Instant::now(),
+                };
 
-            for i in (1..=16).map(|x| {
-                (if x & 1 == 0 {
-                    offset.wrapping_add(x >> 1)
-                } else {
-                    offset.wrapping_sub(x >> 1)
-                }) & 0xf
-            }) {
-                let version_bits = fpga::version_bits(sw2.vermask, version_count - i);
-                let target = sw2.target(nonce, version_bits);
-                if target.starts_with(b"\0\0\0\0") {
-                    offset = i;
-                    let diff = Subwork2::target_diff(&target);
-                    debug!("received: {}, difficulty: {:0<18}", received.to_hex(), diff);
-                    if diff >= *pool_diff.lock().unwrap()[pool].lock().unwrap() {
-                        let params = sw2.into_params(&user[pool], nonce, version_bits);
-                        let msg = Action {
-                            id: Some(nonce_id[pool]),
-                            method: "mining.submit",
-                            params,
-                        };
-
-                        let data = to_json_string(&msg).unwrap();
-                        tokio::spawn(
-                            pool_sender.lock().unwrap()[pool]
-                                .clone()
-                                .send(data)
-                                .then(|_| Ok(())),
-                        );
-                        info!(
-                            "=> submit nonce: 0x{:08x} (difficulty: {:0<18})",
-                            nonce, diff
-                        );
-
-                        let submitted_nonce = &submitted_nonce.lock().unwrap()[pool];
-                        let submitted_nonce =
-                            &mut submitted_nonce.lock().unwrap()[(nonce_id[pool] & 0b111) as usize];
-                        if let Some(nonce_old) = submitted_nonce {
-                            warn!("submitted nonce 0x{:08x} lost!", nonce_old);
+                let ledger = &ledger.lock().unwrap()[pool];
+                if !pools.lock().unwrap()[pool].is_valid(&sw2) {
+                    match stale_shares {
+                        StalePolicy::Drop => {
+                            debug!("drop stale nonce: 0x{:08x} (job: {})", nonce, sw2.workid)
                         }
-                        *submitted_nonce = Some(nonce);
-                        nonce_id[pool] = nonce_id[pool].wrapping_add(1);
-                    };
+                        StalePolicy::Count => ledger.lock().unwrap().stale(&share),
+                    }
                     return Ok(());
                 }
-            }
-        }
+                let id = ledger.lock().unwrap().submit(share);
 
-        let crc_check = fpga::crc5_false(&received[0..7], 5) == received[6] & 0x1f;
-        debug!(
-            "received: {}, lost, crc check: {}",
-            received.to_hex(),
-            crc_check
-        );
-        Ok(())
-    });
+                let params = sw2.into_params(&user[pool], nonce, version_bits);
+                let msg = Action {
+                    id: Some(id),
+                    method: "mining.submit",
+                    params,
+                };
 
-    let mut runtime = current_thread::Runtime::new().unwrap();
-    let task = connect_pool0
-        .select2(get_pool1_data)
-        .select2(send_to_fpga)
-        .select2(receive_nonce)
-        .select2(exit2_receiver.clone())
-        .then(move |_| {
-            exit1.notify();
-            exit2_receiver
+                let data = to_json_string(&msg).unwrap();
+                tokio::spawn(
+                    pool_sender.lock().unwrap()[pool]
+                        .clone()
+                        .send(data)
+                        .then(|_| Ok(())),
+                );
+                info!(
+                    "=> submit nonce: 0x{:08x} (difficulty: {:0<18})",
+                    nonce, diff
+                );
+            };
+            Ok(())
         });
-    let _ = runtime.block_on(task);
 
-    // exit if authorized failed
-    if pool0.connected.load(Ordering::SeqCst) && !pool0.authorized.1.load(Ordering::SeqCst) {
-        exit(-1);
+    let mut tasks: Vec<Box<dyn Future<Item = (), Error = Error>>> = vec![
+        Box::new(run_pools),
+        Box::new(send_work),
+        Box::new(receive_nonce),
+        Box::new(report_shares),
+    ];
+    if let Some(tune) = tune {
+        tasks.push(Box::new(tune));
+    }
+
+    // the backend is dropped with the tasks, which stops it
+    let mut runtime = current_thread::Runtime::new().unwrap();
+    runtime.block_on(select_all(tasks).map(drop).map_err(|(e, _, _)| e))
+}
+
+const USAGE: &str = "usage: stratum [flash-pic <board> <image.hex> | board-info <board>... \
+                     | dump-eeprom <board> <dump.hex> | restore-eeprom <board> <dump.hex>]";
+
+// the tools talk to the pics directly, the miner must not be running
+fn board_tool(command: &str, args: &[String]) -> Result<(), String> {
+    let board = |id: &String| id.parse::<u16>().map_err(|e| e.to_string());
+    let read_image = |path: &String| {
+        fs::read_to_string(path)
+            .and_then(|x| Image::from_ihex(&x))
+            .map_err(|e| format!("{}: {}", path, e))
+    };
+    let i2c = || i2c::open("/dev/i2c-0");
+
+    match (command, args) {
+        ("flash-pic", [id, path]) => {
+            let image = read_image(path)?;
+            BoardManager::new(Arc::new(Mutex::new(i2c())))
+                .update_firmware(board(id)?, &image)
+                .map_err(|e| e.to_string())
+        }
+        ("board-info", ids) if !ids.is_empty() => {
+            let mut i2c = i2c();
+            for id in ids {
+                let info = board_info(&mut i2c, 0x50 + board(id)?).map_err(|e| e.to_string())?;
+                println!("board {}: {}", id, info);
+            }
+            Ok(())
+        }
+        ("dump-eeprom", [id, path]) => {
+            let image = dump(&mut i2c(), 0x50 + board(id)?, EEPROM_POINTER, EEPROM_SIZE)
+                .map_err(|e| e.to_string())?;
+            fs::write(path, image.to_ihex()).map_err(|e| format!("{}: {}", path, e))
+        }
+        ("restore-eeprom", [id, path]) => {
+            let image = read_image(path)?;
+            restore(&mut i2c(), 0x50 + board(id)?, &image).map_err(|e| e.to_string())
+        }
+        _ => Err(String::from(USAGE)),
     }
 }
 
 fn main() {
     setup_logger().unwrap();
 
-    let boards = Arc::new(Mutex::new(Vec::new()));
-    let i2c = Arc::new(Mutex::new(i2c::open("/dev/i2c-0")));
+    let args: Vec<_> = env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        if let Err(e) = board_tool(command, &args[1..]) {
+            error!("{} err: {}", command, e);
+            exit(-1);
+        }
+        return;
+    }
 
-    let boards_clone = boards.clone();
-    let i2c_clone = i2c.clone();
-    thread::spawn(move || {
-        let i2c_lock = || {
-            let i2c_lock = i2c_clone.lock().unwrap();
-            sleep(Duration::from_micros(100));
-            i2c_lock
+    // there are no boards to drive when hashing with the cpu or the serial chains
+    let config = get_config();
+    let i2c = match (config.cpu, config.serial) {
+        (None, None) => Some(Arc::new(Mutex::new(i2c::open("/dev/i2c-0")))),
+        _ => None,
+    };
+    let boards = i2c.clone().map(|i2c| {
+        let limits = get_config().board.temp;
+        let manager = BoardManager::new(i2c);
+        let manager = match limits.sensor.clone() {
+            Some(sensor) => manager.with_thermal(Box::new(Hwmon::new(&sensor)), limits),
+            None => {
+                warn!("=> no temperature sensor configured, thermal protection is off!");
+                manager
+            }
         };
-        loop {
-            for id in &*boards_clone.lock().unwrap() {
-                i2c_lock()
-                    .send_heart_beat(0x50 + id)
-                    .expect("send heart beat err!");
-                sleep(Duration::from_micros(100));
+        Arc::new(Mutex::new(manager))
+    });
+
+    let mut fans = match get_config().fan {
+        Some(ref fan) if i2c.is_some() => {
+            let pwm = SysfsPwm::new(&fan.pwm, fan.period).expect("open fan pwm err!");
+            let tach = HwmonTach::new(&fan.tach);
+            Some(FanController::new(
+                Box::new(pwm),
+                Box::new(tach),
+                fan.clone(),
+            ))
+        }
+        _ => None,
+    };
+
+    if let Some(boards) = boards.clone() {
+        thread::spawn(move || loop {
+            {
+                let mut boards = boards.lock().unwrap();
+                for id in boards.heartbeat() {
+                    warn!("=> board {} stopped!", id);
+                }
+                boards.check_temperature();
+
+                // the boards can't be cooled without the fans
+                if let Some(ref mut fans) = fans {
+                    match fans.update(boards.hottest(), Instant::now()) {
+                        Ok(FanStatus::Running { duty, rpm }) => {
+                            debug!("fan duty: {:.2}, rpm: {:?}", duty, rpm)
+                        }
+                        Ok(FanStatus::Failed(failed)) => {
+                            boards.fault_all(&format!("fans {:?} failed", failed))
+                        }
+                        Err(e) => boards.fault_all(&format!("set fan duty err: {}", e)),
+                    }
+                }
             }
             sleep(Duration::from_secs(10));
-        }
-    });
+        });
+    }
 
     loop {
-        main_loop(boards.clone(), i2c.clone());
+        match main_loop(boards.clone(), i2c.clone()) {
+            Err(e) if e.is_fatal() => {
+                error!("main loop err: {}, exit!", e);
+                if let Some(ref boards) = boards {
+                    boards.lock().unwrap().power_down();
+                }
+                exit(-1);
+            }
+            Err(e) => error!("main loop err: {}, restart!", e),
+            Ok(()) => warn!("main loop exited, restart!"),
+        }
     }
 }
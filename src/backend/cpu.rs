@@ -0,0 +1,141 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use sha256::Sha256;
+
+use crate::util::{fpga, Flip32};
+use crate::work::Target;
+
+use super::*;
+
+// the nonces of a version are split into batches, the unit of work of a thread
+const BATCH_BITS: u32 = 16;
+
+#[derive(Default)]
+struct State {
+    work: Option<Subwork2>,
+    // the next batch of the work
+    batch: u64,
+    exit: bool,
+}
+
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+pub struct CpuBackend {
+    shared: Shared,
+    receiver: Option<UnboundedReceiver<Found>>,
+    hashers: Vec<JoinHandle<()>>,
+}
+
+impl CpuBackend {
+    // results easier than `difficulty` are not reported
+    pub fn new(threads: usize, difficulty: f64) -> Self {
+        let shared: Shared = Arc::default();
+        let (sender, receiver) = unbounded();
+        let target = Target::from_difficulty(difficulty).to_be_bytes();
+
+        let hashers = (0..threads.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                let sender = sender.clone();
+                thread::spawn(move || hash(&shared, &target, &sender))
+            })
+            .collect();
+
+        Self {
+            shared,
+            receiver: Some(receiver),
+            hashers,
+        }
+    }
+}
+
+impl Drop for CpuBackend {
+    fn drop(&mut self) {
+        self.shared.0.lock().unwrap().exit = true;
+        self.shared.1.notify_all();
+        for hasher in self.hashers.drain(..) {
+            let _ = hasher.join();
+        }
+    }
+}
+
+impl Backend for CpuBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            midstates: 1,
+            version_rolling: true,
+        }
+    }
+
+    fn submit(&mut self, sw2: Subwork2) {
+        let mut state = self.shared.0.lock().unwrap();
+        state.work = Some(sw2);
+        state.batch = 0;
+        self.shared.1.notify_all();
+    }
+
+    fn nonces(&mut self) -> Nonces {
+        Box::new(self.receiver.take().unwrap())
+    }
+}
+
+// the next batch of the current work, None on exit
+fn next_batch(shared: &Shared) -> Option<(Subwork2, u64)> {
+    let (lock, condvar) = &**shared;
+    let mut state = lock.lock().unwrap();
+    loop {
+        if state.exit {
+            return None;
+        }
+        if let Some(work) = state.work.clone() {
+            // every version count of the mask, 2^32 nonces each
+            let batches = 1u64 << (work.vermask.count_ones() + 32 - BATCH_BITS);
+            if state.batch < batches {
+                let batch = state.batch;
+                state.batch += 1;
+                return Some((work, batch));
+            }
+        }
+        state = condvar.wait(state).unwrap();
+    }
+}
+
+fn hash(shared: &Shared, target: &[u8; 32], sender: &UnboundedSender<Found>) {
+    while let Some((sw2, batch)) = next_batch(shared) {
+        let version_count = (batch >> (32 - BATCH_BITS)) as u32;
+        let version_bits = fpga::version_bits(sw2.vermask, version_count);
+
+        let header = sw2.block_header(version_bits);
+        // the first 64 bytes of the header are hashed once for all the nonces
+        let mut midstate = Sha256::default();
+        midstate.update(Bytes::from(&header[..64]).flip32().as_ref());
+        let tail = Bytes::from(&header[64..]).flip32();
+
+        let first = (batch << BATCH_BITS) as u32;
+        for nonce in first..=first | ((1 << BATCH_BITS) - 1) {
+            let mut data = BytesMut::with_capacity(16);
+            data.extend(&tail);
+            data.put_u32_le(nonce);
+
+            let mut sha256 = midstate.clone();
+            sha256.update(&data);
+            let mut hash = Sha256::digest(&sha256.finish());
+            hash.reverse();
+
+            if hash <= *target {
+                let found = Found {
+                    subwork2: sw2.clone(),
+                    nonce,
+                    version_bits,
+                };
+                debug_assert_eq!(found.target(), &hash[..]);
+                if sender.unbounded_send(found).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::sync::mpsc::Receiver;
+use futures::{Future, Stream};
+use tokio::runtime::current_thread;
+
+use crate::util::fpga::{self, NonceResult, Writer};
+use crate::util::{Notify, ToHex};
+
+use super::*;
+
+pub struct FpgaBackend {
+    writer: Arc<Mutex<Writer>>,
+    receiver: Option<Receiver<Bytes>>,
+    exit: Notify,
+    reader: Option<JoinHandle<()>>,
+    stats: Arc<Mutex<HashStats>>,
+}
+
+impl FpgaBackend {
+    // fails when the fpga can not be told or opened
+    pub fn new() -> io::Result<Self> {
+        let writer = fpga::writer()?;
+        let (nonce_reader, receiver) = fpga::reader()?.read_nonce();
+        let exit = Notify::default();
+        let exit_receiver = exit.clone();
+
+        // the uio interrupts are waited on a thread of their own
+        let reader = thread::spawn(move || {
+            let mut runtime = current_thread::Runtime::new().unwrap();
+            let _ = runtime.block_on(
+                nonce_reader
+                    .select2(exit_receiver)
+                    .then(|_| Ok::<_, ()>(())),
+            );
+        });
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            receiver: Some(receiver),
+            exit,
+            reader: Some(reader),
+            stats: Arc::default(),
+        })
+    }
+
+    // shared with the thermal protection, which stops the work of the boards too hot
+    pub fn writer(&self) -> Arc<Mutex<Writer>> {
+        self.writer.clone()
+    }
+}
+
+impl Drop for FpgaBackend {
+    fn drop(&mut self) {
+        self.exit.notify();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Backend for FpgaBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            midstates: 1,
+            version_rolling: true,
+        }
+    }
+
+    fn submit(&mut self, sw2: Subwork2) {
+        self.writer.lock().unwrap().writer_subwork2(sw2);
+    }
+
+    fn nonces(&mut self) -> Nonces {
+        let writer = self.writer.clone();
+        let stats = self.stats.clone();
+        let mut offset = 0u32;
+
+        let nonces = self.receiver.take().unwrap().filter_map(move |received| {
+            let result = NonceResult::decode(&received);
+            let found = find(&writer.lock().unwrap().subworks(), &result, &mut offset);
+            stats
+                .lock()
+                .unwrap()
+                .record(&result, found.is_some(), Instant::now());
+
+            match found {
+                Some(_) => debug!("received: {}", received.to_hex()),
+                None => debug!(
+                    "received: {}, lost, crc check: {}",
+                    received.to_hex(),
+                    result.crc_ok
+                ),
+            }
+            found
+        });
+        Box::new(nonces)
+    }
+
+    fn stats(&self) -> Option<Arc<Mutex<HashStats>>> {
+        Some(self.stats.clone())
+    }
+}
+
+// the subwork & version the result was found on
+fn find(subworks: &[Subwork2], result: &NonceResult, offset: &mut u32) -> Option<Found> {
+    if subworks.is_empty() {
+        debug!(
+            "received nonce 0x{:08x}, but there is no subwork!",
+            result.nonce
+        );
+        return None;
+    }
+
+    // the reported version count lags behind, search around the last offset
+    for subwork2 in subworks {
+        for i in (1..=16).map(|x| {
+            (if x & 1 == 0 {
+                offset.wrapping_add(x >> 1)
+            } else {
+                offset.wrapping_sub(x >> 1)
+            }) & 0xf
+        }) {
+            let version_count = result.version_count.wrapping_sub(i);
+            let version_bits = fpga::version_bits(subwork2.vermask, version_count);
+            if subwork2
+                .target(result.nonce, version_bits)
+                .starts_with(b"\0\0\0\0")
+            {
+                *offset = i;
+                return Some(Found {
+                    subwork2: subwork2.clone(),
+                    nonce: result.nonce,
+                    version_bits,
+                });
+            }
+        }
+    }
+    None
+}
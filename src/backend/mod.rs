@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::stream::Stream;
+
+use crate::util::Notify;
+use crate::work::Subwork2;
+
+pub use self::cpu::CpuBackend;
+pub use self::fpga::FpgaBackend;
+pub use self::serial::SerialBackend;
+pub use self::stats::*;
+
+mod cpu;
+mod fpga;
+mod serial;
+mod stats;
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    // midstates hashed at once, each of its own version
+    pub midstates: usize,
+    // rolls the version bits of `Subwork2::vermask` by itself
+    pub version_rolling: bool,
+}
+
+// a nonce found by the backend, with the version bits it was found on
+#[derive(Clone, Debug)]
+pub struct Found {
+    pub subwork2: Subwork2,
+    pub nonce: u32,
+    pub version_bits: u32,
+}
+
+pub type Nonces = Box<dyn Stream<Item = Found, Error = ()> + Send>;
+
+pub trait Backend: Send {
+    fn capabilities(&self) -> Capabilities;
+
+    // replace the work being hashed
+    fn submit(&mut self, sw2: Subwork2);
+
+    // some of the hashers are left without work, another subwork2 is to be submitted at once
+    fn idle(&self) -> bool {
+        false
+    }
+
+    // notified when some hashers run out of work, if the backend can tell
+    fn hungry(&self) -> Option<Notify> {
+        None
+    }
+
+    // the found nonces, can only be taken once
+    fn nonces(&mut self) -> Nonces;
+
+    // the results by board and chip, if the backend can tell them
+    fn stats(&self) -> Option<Arc<Mutex<HashStats>>> {
+        None
+    }
+}
+
+impl Found {
+    pub fn target(&self) -> Bytes {
+        self.subwork2.target(self.nonce, self.version_bits)
+    }
+}
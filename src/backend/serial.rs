@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{Async, Future, Poll, Stream};
+use tokio::codec::{Decoder, Framed};
+use tokio_serial::Serial;
+
+use crate::util::serial::{self, Codec, ReceiverStats};
+use crate::util::Notify;
+use crate::work::{Subwork, MAX_MIDSTATES};
+
+use super::*;
+
+// as many as the subwork ids of the codec
+const SUBWORKS: usize = 256;
+
+type Decoded = <Codec as Decoder>::Item;
+
+// a subwork2 and the sequence it was submitted in
+type Sequenced = (usize, Subwork2);
+
+// the chains on serial ports, each hashing its share of the versions of the subwork2, or a
+// subwork2 of its own when the versions do not go round
+pub struct SerialBackend {
+    senders: Vec<UnboundedSender<Sequenced>>,
+    chains: Vec<(UnboundedReceiver<Sequenced>, Framed<Serial, Codec>)>,
+    stats: Vec<Arc<Mutex<ReceiverStats>>>,
+    subworks: Arc<Mutex<VecDeque<Subwork2>>>,
+    sequence: usize,
+    // by chain, hashed all its subworks and waits for the next subwork2
+    dry: Arc<Mutex<Vec<bool>>>,
+    // notified when a chain runs dry
+    hungry: Notify,
+}
+
+// the subworks of a chain, the groups of `MAX_MIDSTATES` versions of the latest subwork2 are
+// taken by the chains in turn, the first by the chain of the sequence of the subwork2, unless
+// the subwork2 is its own
+pub(super) struct Feeder {
+    receiver: UnboundedReceiver<Sequenced>,
+    chain: usize,
+    chains: usize,
+    dry: Arc<Mutex<Vec<bool>>>,
+    hungry: Notify,
+    current: Option<Sequenced>,
+    // the next group of versions
+    group: u64,
+    closed: bool,
+}
+
+impl SerialBackend {
+    // `window` subwork ids are searched for the subwork of a nonce
+    pub fn new<T: AsRef<Path>>(paths: &[T], window: usize) -> Self {
+        let mut backend = Self::with_senders(Vec::new());
+        for path in paths {
+            let (sender, receiver) = unbounded();
+            let codec = Codec::new(window);
+            backend.stats.push(codec.stats());
+            backend.senders.push(sender);
+            backend.dry.lock().unwrap().push(true);
+            backend
+                .chains
+                .push((receiver, codec.framed(serial::new(path))));
+        }
+        backend
+    }
+
+    // the chains fed through `senders`, without any port
+    pub(super) fn with_senders(senders: Vec<UnboundedSender<Sequenced>>) -> Self {
+        Self {
+            dry: Arc::new(Mutex::new(vec![true; senders.len()])),
+            senders,
+            chains: Vec::new(),
+            stats: Vec::new(),
+            subworks: Arc::new(Mutex::new(VecDeque::with_capacity(SUBWORKS))),
+            sequence: 0,
+            hungry: Notify::default(),
+        }
+    }
+
+    // the subworks of `chain` from the subwork2 sent to it
+    pub(super) fn feeder(&self, receiver: UnboundedReceiver<Sequenced>, chain: usize) -> Feeder {
+        Feeder {
+            receiver,
+            chain,
+            chains: self.senders.len(),
+            dry: self.dry.clone(),
+            hungry: self.hungry.clone(),
+            current: None,
+            group: 0,
+            closed: false,
+        }
+    }
+
+    // by chain, in the order of the ports
+    pub fn receiver_stats(&self) -> Vec<Arc<Mutex<ReceiverStats>>> {
+        self.stats.clone()
+    }
+}
+
+// the versions of `vermask` make a group of `MAX_MIDSTATES` for each of the chains
+fn shared(vermask: u32, chains: usize) -> bool {
+    Subwork::versions(vermask).div_ceil(MAX_MIDSTATES as u64) >= chains as u64
+}
+
+impl Stream for Feeder {
+    type Item = Subwork;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // the latest subwork2 replaces the one hashed
+        while !self.closed {
+            match self.receiver.poll() {
+                Ok(Async::Ready(Some(current))) => {
+                    self.dry.lock().unwrap()[self.chain] = false;
+                    self.current = Some(current);
+                    self.group = 0;
+                }
+                Ok(Async::Ready(None)) | Err(_) => self.closed = true,
+                Ok(Async::NotReady) => break,
+            }
+        }
+
+        let (sequence, sw2) = match self.current {
+            Some((sequence, ref sw2)) => (sequence, sw2),
+            None if self.closed => return Ok(Async::Ready(None)),
+            None => return Ok(Async::NotReady),
+        };
+        // all the groups of its own subwork2
+        let (chain, chains) = if shared(sw2.vermask, self.chains) {
+            (self.chain as u64, self.chains as u64)
+        } else {
+            (0, 1)
+        };
+        let turn = (sequence as u64 + self.group) % chains;
+        let group = self.group + (chain + chains - turn) % chains;
+        let midstates = MAX_MIDSTATES as u64;
+        if group * midstates >= Subwork::versions(sw2.vermask) {
+            // wait for the next subwork2, asked for at once
+            self.current = None;
+            self.dry.lock().unwrap()[self.chain] = true;
+            self.hungry.notify();
+            if self.closed {
+                return Ok(Async::Ready(None));
+            }
+            return Ok(Async::NotReady);
+        }
+
+        self.group = group + 1;
+        let first = (group * midstates) as u32;
+        Ok(Async::Ready(Some(sw2.subwork(first, MAX_MIDSTATES))))
+    }
+}
+
+// the subwork2 a subwork was made of, the pools may have the same job ids and xnonce2
+pub(super) fn subwork2_of(subworks: &VecDeque<Subwork2>, subwork: &Subwork) -> Option<Subwork2> {
+    subworks
+        .iter()
+        .filter(|x| x.workid == subwork.workid && x.xnonce2 == subwork.xnonce2)
+        .find(|x| x.block_header(0)[4..] == subwork.block_header[4..])
+        .cloned()
+}
+
+impl Backend for SerialBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            midstates: MAX_MIDSTATES,
+            version_rolling: false,
+        }
+    }
+
+    fn submit(&mut self, sw2: Subwork2) {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut subworks = self.subworks.lock().unwrap();
+        let mut dry = self.dry.lock().unwrap();
+        // a new job is given to all the chains again
+        let same_job = subworks.front().is_some_and(|x| {
+            x.pool == sw2.pool && x.session == sw2.session && x.workid == sw2.workid
+        });
+        if !same_job {
+            dry.iter_mut().for_each(|x| *x = true);
+        }
+        subworks.push_front(sw2.clone());
+        subworks.truncate(SUBWORKS);
+
+        // each chain rolls the xnonce2 of its own subwork2, the dry ones first
+        if !shared(sw2.vermask, self.senders.len()) {
+            let chain = dry
+                .iter()
+                .position(|x| *x)
+                .unwrap_or(sequence % self.senders.len());
+            dry[chain] = false;
+            if let Err(e) = self.senders[chain].unbounded_send((sequence, sw2)) {
+                error!("send subwork to serial err: {:?}", e);
+            }
+            return;
+        }
+
+        dry.iter_mut().for_each(|x| *x = false);
+        for sender in &self.senders {
+            if let Err(e) = sender.unbounded_send((sequence, sw2.clone())) {
+                error!("send subwork to serial err: {:?}", e);
+            }
+        }
+    }
+
+    fn idle(&self) -> bool {
+        // the chains running dry from now on are told by `hungry`
+        self.hungry.notified();
+        self.dry.lock().unwrap().iter().any(|x| *x)
+    }
+
+    fn hungry(&self) -> Option<Notify> {
+        Some(self.hungry.clone())
+    }
+
+    fn nonces(&mut self) -> Nonces {
+        let mut decoded: Box<dyn Stream<Item = Option<Decoded>, Error = io::Error> + Send> =
+            Box::new(futures::stream::empty());
+        let chains: Vec<_> = self.chains.drain(..).collect();
+        for (chain, (receiver, framed)) in chains.into_iter().enumerate() {
+            let (sink, stream) = framed.split();
+
+            // the subworks are written to the port while the nonces are read
+            let writer = self
+                .feeder(receiver, chain)
+                .forward(sink)
+                .map(|_| None)
+                .into_stream();
+            decoded = Box::new(decoded.select(stream.map(Some).select(writer)));
+        }
+
+        let subworks = self.subworks.clone();
+        let nonces = decoded
+            .map_err(|e| error!("serial err: {:?}", e))
+            .filter_map(move |decoded| {
+                let (subwork, _, nonce, version_bits) = decoded?;
+                let found =
+                    subwork2_of(&subworks.lock().unwrap(), &subwork).map(|subwork2| Found {
+                        subwork2,
+                        nonce,
+                        version_bits,
+                    });
+                if found.is_none() {
+                    debug!("nonce 0x{:08x} of an unknown subwork!", nonce);
+                }
+                found
+            });
+        Box::new(nonces)
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::util::fpga::NonceResult;
+
+// every result meets difficulty 1, which takes 2^32 hashes on average
+const HASHES_PER_RESULT: f64 = 4_294_967_296.0;
+
+#[derive(Clone, Debug)]
+pub struct ChipStats {
+    pub nonces: u64,
+    // results matching no work
+    pub hw_errors: u64,
+    pub last_seen: Instant,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BoardStats {
+    pub chips: BTreeMap<u8, ChipStats>,
+    // results that can not be told which chip they are of
+    pub crc_errors: u64,
+}
+
+// the results of the chips since `start`, by board and chip address
+#[derive(Clone, Debug)]
+pub struct HashStats {
+    pub start: Instant,
+    pub boards: BTreeMap<u8, BoardStats>,
+}
+
+impl ChipStats {
+    // hashes per second
+    pub fn hashrate(&self, elapsed: Duration) -> f64 {
+        hashrate(self.nonces, elapsed)
+    }
+
+    pub fn hw_error_rate(&self) -> f64 {
+        error_rate(self.hw_errors, self.nonces)
+    }
+}
+
+impl BoardStats {
+    pub fn nonces(&self) -> u64 {
+        self.chips.values().map(|x| x.nonces).sum()
+    }
+
+    pub fn hw_errors(&self) -> u64 {
+        self.crc_errors + self.chips.values().map(|x| x.hw_errors).sum::<u64>()
+    }
+
+    pub fn hashrate(&self, elapsed: Duration) -> f64 {
+        hashrate(self.nonces(), elapsed)
+    }
+
+    pub fn hw_error_rate(&self) -> f64 {
+        error_rate(self.hw_errors(), self.nonces())
+    }
+
+    // the chips seen before but not for `timeout`, likely dead
+    pub fn silent_chips(&self, now: Instant, timeout: Duration) -> Vec<u8> {
+        self.chips
+            .iter()
+            .filter(|(_, x)| now.duration_since(x.last_seen) > timeout)
+            .map(|(chip, _)| *chip)
+            .collect()
+    }
+}
+
+impl Default for HashStats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            boards: BTreeMap::new(),
+        }
+    }
+}
+
+impl HashStats {
+    // `valid`: the result matches a work
+    pub fn record(&mut self, result: &NonceResult, valid: bool, now: Instant) {
+        let board = self.boards.entry(result.board).or_default();
+        if !valid && !result.crc_ok {
+            board.crc_errors += 1;
+            return;
+        }
+
+        let chip = board.chips.entry(result.chip).or_insert(ChipStats {
+            nonces: 0,
+            hw_errors: 0,
+            last_seen: now,
+        });
+        chip.last_seen = now;
+        if valid {
+            chip.nonces += 1;
+        } else {
+            chip.hw_errors += 1;
+        }
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        now.duration_since(self.start)
+    }
+}
+
+pub(crate) fn hashrate(nonces: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        x if x > 0.0 => nonces as f64 * HASHES_PER_RESULT / x,
+        _ => 0.0,
+    }
+}
+
+pub(crate) fn error_rate(errors: u64, nonces: u64) -> f64 {
+    match errors + nonces {
+        0 => 0.0,
+        n => errors as f64 / n as f64,
+    }
+}
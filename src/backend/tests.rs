@@ -0,0 +1,377 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Sink, Stream};
+use serde_json::{json, Value as JsonValue};
+use tokio::prelude::FutureExt;
+use tokio::runtime::Runtime;
+
+use crate::stratum::{Action, Pool, Share};
+use crate::util::fpga::NonceResult;
+use crate::util::{Config, FromHex};
+use crate::work::{PoolData, Subwork2Stream, Target, Work};
+
+use super::*;
+
+// 1 in 256 hashes
+const DIFFICULTY: f64 = 1.0 / 16_777_216.0;
+
+const NOTIFY: &str = r#"[
+    "0",
+    "53295d842611768501295be6a3305f7cc28a70e00016c0380000000000000000",
+    "02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4b03d08d08042d1c505c612f4254432e434f4d2ffabe6d6d54bf3732a3dc252297cf75d4c1cf35878ed99626ef2ffb311cef0cc7c4eff06e0100000000000000",
+    "ffffffff0329e74d4c0000000016001497cfc76442fe717f2a3f0cc9c175f7561b6619970000000000000000266a24aa21a9ed82b1c33e59cfca82f3af6b51d6094775df97a385f135cabb259ca9fdb63f124b00000000000000002952534b424c4f434b3af5fbe7f0043226e246965f4e7db2c3ff6d5dfedb9b85d0873eed8cca4227c14900000000",
+    [
+        "0c3c1a888c2b9e521c3c1456414473b712216568c3a69e7eefe6434134f951ed",
+        "f12de771dc657d5e24c0737c444ab284222997bf3e9c9d298e72effa8cbcde5a"
+    ],
+    "20000000",
+    "17306835",
+    "5c501c2a",
+    true
+]"#;
+
+fn subwork2() -> Subwork2 {
+    Subwork2 {
+        workid: String::from("1"),
+        prevhash: Bytes::from(vec![0u8; 32]),
+        merkle_root: Bytes::from(vec![1u8; 32]),
+        ntime: Bytes::from(vec![2u8; 4]),
+        nbits: Bytes::from(vec![0x1d, 0x00, 0xff, 0xff]),
+        version: 0x2000_0000,
+        vermask: 0x1fff_e000,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn cpu_backend() {
+    let mut backend = CpuBackend::new(2, DIFFICULTY);
+    assert!(backend.capabilities().version_rolling);
+
+    let nonces = backend.nonces();
+    backend.submit(subwork2());
+
+    let found: Vec<_> = nonces.take(3).collect().wait().unwrap();
+    assert_eq!(found.len(), 3);
+    for found in found {
+        assert_eq!(found.subwork2.workid, "1");
+        assert_eq!(found.version_bits & !found.subwork2.vermask, 0);
+        let hash = Target::from_be_bytes(&found.target());
+        assert!(Target::from_difficulty(DIFFICULTY).is_met_by(&hash));
+    }
+}
+
+#[test]
+fn serial_chains() {
+    use std::collections::{HashSet, VecDeque};
+
+    use futures::sync::mpsc::unbounded;
+
+    use super::serial::subwork2_of;
+
+    // the versions of the subworks of each chain, while they last
+    let feed = |sw2: &Subwork2, sequence: usize, chains: usize, take: u64| {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..chains).map(|_| unbounded()).unzip();
+        for sender in &senders {
+            sender.unbounded_send((sequence, sw2.clone())).unwrap();
+        }
+        let backend = SerialBackend::with_senders(senders);
+        let feeders: Vec<_> = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(chain, receiver)| backend.feeder(receiver, chain))
+            .collect();
+        drop(backend);
+        feeders
+            .into_iter()
+            .map(|feeder| feeder.take(take).collect().wait().unwrap())
+            .collect::<Vec<Vec<_>>>()
+    };
+
+    let sw2 = subwork2();
+    let chains = feed(&sw2, 1, 3, 8);
+    let mut versions = HashSet::new();
+    for subworks in &chains {
+        assert_eq!(subworks.len(), 8);
+        for subwork in subworks {
+            assert_eq!(subwork.midstates.len(), 4);
+            versions.extend(subwork.midstates.iter().map(|x| x.0));
+        }
+    }
+    assert_eq!(versions.len(), 3 * 8 * 4);
+    // the first versions are taken by the chain of the sequence
+    assert_eq!(chains[1][0].midstates[0].0, 0);
+
+    // too few versions to go round, each chain hashes all of its own subwork2, then runs dry
+    let mut single = subwork2();
+    single.vermask = 0;
+    let chains = feed(&single, 4, 3, 8);
+    let counts: Vec<_> = chains.iter().map(Vec::len).collect();
+    assert_eq!(counts, [1, 1, 1]);
+
+    // the subwork2 of each xnonce2 to a chain of its own, until all the chains have one
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| unbounded()).unzip();
+    let mut backend = SerialBackend::with_senders(senders);
+    let mut submit = |workid: &str, xnonce2: u8| {
+        let mut sw2 = single.clone();
+        sw2.workid = String::from(workid);
+        sw2.xnonce2 = Bytes::from(vec![xnonce2]);
+        backend.submit(sw2);
+        backend.idle()
+    };
+    assert!(submit("1", 0));
+    // a new job is given to all the chains again
+    assert!(submit("2", 1));
+    assert!(submit("2", 2));
+    assert!(!submit("2", 3));
+    drop(backend);
+    let xnonce2: Vec<Vec<_>> = receivers
+        .into_iter()
+        .map(|receiver| {
+            let sequenced = receiver.collect().wait().unwrap();
+            sequenced.into_iter().map(|(_, x)| x.xnonce2[0]).collect()
+        })
+        .collect();
+    assert_eq!(xnonce2, [vec![0, 1], vec![2], vec![3]]);
+
+    // the job ids and xnonce2 of the pools are the same, not the jobs
+    let mut other = subwork2();
+    other.pool = 1;
+    other.prevhash = Bytes::from(vec![3u8; 32]);
+    let subworks: VecDeque<_> = vec![other.clone(), sw2.clone()].into_iter().collect();
+    assert_eq!(subwork2_of(&subworks, &sw2.subwork(8, 4)).unwrap().pool, 0);
+    assert_eq!(
+        subwork2_of(&subworks, &other.subwork(0, 4)).unwrap().pool,
+        1
+    );
+    single.workid = String::from("2");
+    assert!(subwork2_of(&subworks, &single.subwork(0, 4)).is_none());
+}
+
+#[test]
+fn serial_dry_chains() {
+    use futures::sync::mpsc::unbounded;
+    use futures::Async;
+
+    let mut single = subwork2();
+    single.vermask = 0;
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| unbounded()).unzip();
+    let mut backend = SerialBackend::with_senders(senders);
+    let mut feeders: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(chain, receiver)| backend.feeder(receiver, chain))
+        .collect();
+    let mut hungry = backend.hungry().unwrap();
+
+    // the chains running dry ask for the next xnonce2 at once, the pool notifies nothing
+    let xnonce2 = futures::future::lazy(move || {
+        let mut xnonce2 = vec![Vec::new(); 3];
+        let mut next = 0;
+        for _ in 0..3 {
+            while backend.idle() {
+                let mut sw2 = single.clone();
+                sw2.xnonce2 = Bytes::from(vec![next]);
+                backend.submit(sw2);
+                next += 1;
+            }
+            assert_eq!(hungry.poll(), Ok(Async::NotReady));
+            for (chain, feeder) in feeders.iter_mut().enumerate() {
+                match feeder.poll() {
+                    Ok(Async::Ready(Some(subwork))) => xnonce2[chain].push(subwork.xnonce2[0]),
+                    _ => panic!("chain {} not fed", chain),
+                }
+                assert!(feeder.poll().unwrap().is_not_ready());
+            }
+            assert_eq!(hungry.poll(), Ok(Async::Ready(())));
+        }
+        Ok::<_, ()>(xnonce2)
+    })
+    .wait()
+    .unwrap();
+    assert_eq!(xnonce2, [[0, 3, 6], [1, 4, 7], [2, 5, 8]]);
+}
+
+// a pool of a single connection, accepts every share and reports its params
+fn mock_pool() -> (SocketAddr, mpsc::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (report, submitted) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut writer = socket.try_clone().unwrap();
+        for line in BufReader::new(socket).lines() {
+            let request: JsonValue = serde_json::from_str(&line.unwrap()).unwrap();
+            let id = &request["id"];
+            let replies = match request["method"].as_str().unwrap() {
+                "mining.configure" => vec![json!({"id": id, "error": null, "result": {
+                    "version-rolling": true, "version-rolling.mask": "1fffe000"
+                }})],
+                "mining.subscribe" => vec![json!({"id": id, "error": null, "result": [
+                    [["mining.set_difficulty", "1"], ["mining.notify", "1"]], "72e03131", 8
+                ]})],
+                "mining.authorize" => vec![
+                    json!({"id": id, "result": true, "error": null}),
+                    json!({"id": null, "method": "mining.set_difficulty", "params": [DIFFICULTY]}),
+                    json!({"id": null, "method": "mining.notify",
+                        "params": serde_json::from_str::<JsonValue>(NOTIFY).unwrap()}),
+                ],
+                "mining.submit" => {
+                    let params = request["params"].as_array().unwrap();
+                    let params = params.iter().map(|x| x.as_str().unwrap().to_string());
+                    let _ = report.send(params.collect());
+                    vec![json!({"id": id, "result": true, "error": null})]
+                }
+                _ => Vec::new(),
+            };
+            for reply in replies {
+                writeln!(writer, "{}", reply).unwrap();
+            }
+        }
+    });
+
+    (addr, submitted)
+}
+
+// Subwork2Stream -> CpuBackend -> Subwork2::into_params -> mining.submit, no hardware needed
+#[test]
+fn mine_on_mock_pool() {
+    let (addr, submitted) = mock_pool();
+    let config: Config = toml::from_str(&format!(
+        r#"
+        [client]
+        [client.version-rolling]
+        mask = "1fffe000"
+
+        [board]
+        enabled = []
+        default = {{}}
+
+        [[pool]]
+        addr = "{}"
+        user = "user.0"
+        pass = ""
+        "#,
+        addr
+    ))
+    .unwrap();
+
+    let mut pool = Pool::new(&addr.to_string());
+    let sender = pool.sender();
+    let ledger = pool.ledger.clone();
+    let subwork2_stream = Subwork2Stream::default();
+    subwork2_stream
+        .pools
+        .lock()
+        .unwrap()
+        .push(PoolData::from_pool(&mut pool, 1.0));
+
+    let mut backend = CpuBackend::new(2, DIFFICULTY);
+    let nonces = backend.nonces();
+    let send_work = subwork2_stream
+        .map_err(drop)
+        .for_each(move |(sw2, notify, timeout)| {
+            backend.submit(sw2);
+            notify.timeout(timeout).then(|_| Ok(()))
+        });
+
+    let ledger_clone = ledger.clone();
+    let submit = nonces.for_each(move |found| {
+        let hash = Target::from_be_bytes(&found.target());
+        let Found {
+            subwork2: sw2,
+            nonce,
+            version_bits,
+        } = found;
+        let id = ledger_clone.lock().unwrap().submit(Share {
+            pool: 0,
+            job_id: sw2.workid.clone(),
+            nonce,
+            version_bits,
+            difficulty: hash.difficulty(),
+            pool_difficulty: DIFFICULTY,
+            block: false,
+            submit_time: Instant::now(),
+        });
+        let msg = Action {
+            id: Some(id),
+            method: "mining.submit",
+            params: sw2.into_params("user.0", nonce, version_bits),
+        };
+        sender
+            .clone()
+            .send(serde_json::to_string(&msg).unwrap())
+            .then(|_| Ok(()))
+    });
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(pool.run(config, 0).map_err(|e| panic!("{}", e)));
+    runtime.spawn(send_work);
+    runtime.spawn(submit);
+
+    // the pool can verify the shares from the submitted params alone
+    let work: Work = serde_json::from_str(NOTIFY).unwrap();
+    let xnonce1 = Bytes::from("72e03131".from_hex().unwrap());
+    for _ in 0..3 {
+        let params = submitted.recv_timeout(Duration::from_secs(30)).unwrap();
+        assert_eq!(params[0], "user.0");
+        assert_eq!(params[1], "0");
+        assert_eq!(params[3], "5c501c2a");
+
+        let xnonce2 = Bytes::from(params[2].from_hex().unwrap());
+        let sw2 = work.subwork2((&xnonce1, xnonce2), 0x1fff_e000);
+        let nonce = u32::from_str_radix(&params[4], 16).unwrap();
+        let version_bits = u32::from_str_radix(&params[5], 16).unwrap();
+        let hash = Target::from_be_bytes(&sw2.target(nonce, version_bits));
+        assert!(Target::from_difficulty(DIFFICULTY).is_met_by(&hash));
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while ledger.lock().unwrap().stats().accepted < 3 {
+        assert!(Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn hash_stats() {
+    let result = |board, chip, crc_ok| NonceResult {
+        board,
+        chip,
+        core: 0,
+        nonce: 0,
+        version_count: 0,
+        crc_ok,
+    };
+    let mut stats = HashStats::default();
+    let start = stats.start;
+    let later = start + Duration::from_secs(100);
+
+    for _ in 0..3 {
+        stats.record(&result(5, 0, true), true, start);
+    }
+    stats.record(&result(5, 0, true), false, start);
+    stats.record(&result(5, 4, true), true, later);
+    stats.record(&result(5, 4, false), false, later);
+    stats.record(&result(6, 0, true), true, later);
+
+    let board = &stats.boards[&5];
+    assert_eq!(board.chips.len(), 2);
+    assert_eq!(board.nonces(), 4);
+    assert_eq!(board.crc_errors, 1);
+    assert_eq!(board.hw_errors(), 2);
+    assert_eq!(board.hw_error_rate(), 2.0 / 6.0);
+    assert_eq!(board.chips[&0].hw_error_rate(), 0.25);
+
+    let elapsed = stats.elapsed(later);
+    assert_eq!(board.hashrate(elapsed), 4.0 * 4_294_967_296.0 / 100.0);
+    assert_eq!(board.silent_chips(later, Duration::from_secs(60)), [0]);
+    assert!(stats.boards[&6]
+        .silent_chips(later, Duration::from_secs(60))
+        .is_empty());
+}
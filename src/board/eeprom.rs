@@ -0,0 +1,84 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+
+use crate::util::i2c::{HashBoardId, MacAddress, EEPROM_HOST_MAC, EEPROM_ID, EEPROM_TEMP_OFFSET};
+use crate::util::BoardConfig;
+
+use super::firmware::verify_image;
+use super::{Image, CHUNK_SIZE};
+
+pub use crate::util::i2c::{EEPROM_POINTER, EEPROM_SIZE};
+
+// what tells a board from the others
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardInfo {
+    pub id: HashBoardId,
+    pub date: NaiveDate,
+    pub mac: MacAddress,
+    pub which_mac: u8,
+    pub pic_version: u8,
+}
+
+impl fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "id: {}, date: {}, mac: {} ({}), pic version: 0x{:02x}",
+            self.id, self.date, self.mac, self.which_mac, self.pic_version
+        )
+    }
+}
+
+pub fn board_info<T: BoardConfig>(i2c: &mut T, addr: u16) -> Result<BoardInfo> {
+    Ok(BoardInfo {
+        id: i2c.get_hash_board_id(addr)?,
+        date: i2c.get_date(addr)?,
+        mac: i2c.get_mac(addr)?,
+        which_mac: i2c.get_which_mac(addr)?,
+        pic_version: i2c.get_software_version(addr)?,
+    })
+}
+
+// read `len` bytes from `pointer`, rounded up to whole chunks
+pub fn dump<T: BoardConfig>(i2c: &mut T, addr: u16, pointer: u16, len: usize) -> Result<Image> {
+    let mut data = Vec::with_capacity(len + CHUNK_SIZE);
+    i2c.set_flash_pointer(addr, pointer)?;
+    while data.len() < len {
+        data.extend_from_slice(&i2c.read_data_from_flash(addr)?);
+    }
+    Ok(Image {
+        start: u32::from(pointer) * 2,
+        data,
+    })
+}
+
+// write back a dump of the whole eeprom with the commands of the app, the date and the mac
+// can't be written so the dump must be of the same board
+pub fn restore<T: BoardConfig>(i2c: &mut T, addr: u16, image: &Image) -> Result<()> {
+    let start = u32::from(EEPROM_POINTER) * 2;
+    if image.start != start || image.data.len() != EEPROM_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "image of 0x{:04x}..0x{:04x} is not the eeprom 0x{:04x}..0x{:04x}",
+                image.start,
+                image.start as usize + image.data.len(),
+                start,
+                start as usize + EEPROM_SIZE
+            ),
+        ));
+    }
+
+    let mut id = [0; 12];
+    id.copy_from_slice(&image.data[EEPROM_ID]);
+    i2c.set_hash_board_id(addr, &HashBoardId(id))?;
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&image.data[EEPROM_HOST_MAC]);
+    i2c.set_host_mac_address(addr, MacAddress(mac))?;
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&image.data[EEPROM_TEMP_OFFSET]);
+    i2c.set_temp_offset(addr, u64::from_be_bytes(offset))?;
+    verify_image(i2c, addr, image)
+}
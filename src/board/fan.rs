@@ -0,0 +1,245 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::util::Fan;
+
+// the duty cycle of the fans, 0.0 to 1.0
+pub trait Pwm: Send {
+    fn set_duty(&mut self, duty: f64) -> Result<()>;
+}
+
+// the speed of the fans in rpm
+pub trait Tach: Send {
+    fn rpm(&mut self, fan: usize) -> Result<u32>;
+}
+
+// a pwm channel exported in sysfs, e.g. /sys/class/pwm/pwmchip0/pwm0
+pub struct SysfsPwm {
+    dir: PathBuf,
+    // nanoseconds
+    period: u32,
+}
+
+// the tachometers as hwmon inputs, `{}` is replaced by the fan number from 1
+pub struct HwmonTach {
+    pattern: String,
+}
+
+struct SimFanState {
+    duty: f64,
+    rpm: Vec<u32>,
+    failed: Vec<bool>,
+}
+
+// fans spinning at `max_rpm` times the duty, unless failed by the test
+#[derive(Clone)]
+pub struct SimFan {
+    state: Arc<Mutex<SimFanState>>,
+    max_rpm: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pid {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    integral: f64,
+    last_error: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FanStatus {
+    Running { duty: f64, rpm: Vec<u32> },
+    // the fans too slow for longer than the failure time
+    Failed(Vec<usize>),
+}
+
+pub struct FanController {
+    pwm: Box<dyn Pwm>,
+    tach: Box<dyn Tach>,
+    pid: Pid,
+    config: Fan,
+    // when each fan was first seen too slow
+    slow_since: Vec<Option<Instant>>,
+    last_update: Option<Instant>,
+}
+
+impl SysfsPwm {
+    pub fn new<T: AsRef<Path>>(dir: T, period: u32) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::write(dir.join("period"), period.to_string())?;
+        fs::write(dir.join("enable"), "1")?;
+        Ok(Self { dir, period })
+    }
+}
+
+impl Pwm for SysfsPwm {
+    fn set_duty(&mut self, duty: f64) -> Result<()> {
+        let duty_cycle = (f64::from(self.period) * duty.clamp(0.0, 1.0)) as u32;
+        fs::write(self.dir.join("duty_cycle"), duty_cycle.to_string())
+    }
+}
+
+impl HwmonTach {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+        }
+    }
+}
+
+impl Tach for HwmonTach {
+    fn rpm(&mut self, fan: usize) -> Result<u32> {
+        let path = self.pattern.replace("{}", &(fan + 1).to_string());
+        fs::read_to_string(&path)?
+            .trim()
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+impl SimFan {
+    pub fn new(fans: usize, max_rpm: u32) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimFanState {
+                duty: 0.0,
+                rpm: vec![0; fans],
+                failed: vec![false; fans],
+            })),
+            max_rpm,
+        }
+    }
+
+    pub fn duty(&self) -> f64 {
+        self.state.lock().unwrap().duty
+    }
+
+    pub fn fail(&self, fan: usize, failed: bool) {
+        let state = &mut *self.state.lock().unwrap();
+        state.failed[fan] = failed;
+        state.rpm[fan] = if failed {
+            0
+        } else {
+            rpm(self.max_rpm, state.duty)
+        };
+    }
+}
+
+fn rpm(max_rpm: u32, duty: f64) -> u32 {
+    (f64::from(max_rpm) * duty) as u32
+}
+
+impl Pwm for SimFan {
+    fn set_duty(&mut self, duty: f64) -> Result<()> {
+        let state = &mut *self.state.lock().unwrap();
+        state.duty = duty;
+        for (speed, failed) in state.rpm.iter_mut().zip(&state.failed) {
+            *speed = if *failed { 0 } else { rpm(self.max_rpm, duty) };
+        }
+        Ok(())
+    }
+}
+
+impl Tach for SimFan {
+    fn rpm(&mut self, fan: usize) -> Result<u32> {
+        self.state
+            .lock()
+            .unwrap()
+            .rpm
+            .get(fan)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no fan {}", fan)))
+    }
+}
+
+impl Pid {
+    pub fn new(kp: f64, ki: f64, kd: f64) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral: 0.0,
+            last_error: None,
+        }
+    }
+
+    // `error` is how far above the target, the output is clamped to `min..=max`
+    pub fn update(&mut self, error: f64, dt: f64, min: f64, max: f64) -> f64 {
+        let derivative = match self.last_error {
+            Some(last) if dt > 0.0 => (error - last) / dt,
+            _ => 0.0,
+        };
+        self.last_error = Some(error);
+
+        let integral = self.integral + error * dt;
+        let output = self.kp * error + self.ki * integral + self.kd * derivative;
+        // no windup while saturated
+        if output < max && output > min {
+            self.integral = integral;
+        }
+        output.clamp(min, max)
+    }
+}
+
+impl FanController {
+    pub fn new(pwm: Box<dyn Pwm>, tach: Box<dyn Tach>, config: Fan) -> Self {
+        Self {
+            pwm,
+            tach,
+            pid: Pid::new(config.kp, config.ki, config.kd),
+            slow_since: vec![None; config.count],
+            config,
+            last_update: None,
+        }
+    }
+
+    // `temp` is the hottest chip, the fans run full without it
+    pub fn update(&mut self, temp: Option<f64>, now: Instant) -> Result<FanStatus> {
+        let dt = self
+            .last_update
+            .map_or(0.0, |x| now.duration_since(x).as_secs_f64());
+        self.last_update = Some(now);
+
+        let duty = match temp {
+            Some(temp) => {
+                let error = temp - self.config.target_temp;
+                self.pid.update(error, dt, self.config.min_duty, 1.0)
+            }
+            None => 1.0,
+        };
+        self.pwm.set_duty(duty)?;
+
+        let failure_time = Duration::from_secs_f64(self.config.failure_time);
+        let mut rpm = Vec::with_capacity(self.config.count);
+        let mut failed = Vec::new();
+        for fan in 0..self.config.count {
+            let speed = match self.tach.rpm(fan) {
+                Ok(speed) => speed,
+                Err(e) => {
+                    warn!("read fan {} err: {}!", fan, e);
+                    0
+                }
+            };
+            rpm.push(speed);
+
+            if speed >= self.config.min_rpm {
+                self.slow_since[fan] = None;
+                continue;
+            }
+            let since = *self.slow_since[fan].get_or_insert(now);
+            if now.duration_since(since) >= failure_time {
+                failed.push(fan);
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(FanStatus::Running { duty, rpm })
+        } else {
+            error!("fans {:?} failed: {:?} rpm!", failed, rpm);
+            Ok(FanStatus::Failed(failed))
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::thread::sleep;
+
+use crate::util::{BoardConfig, FromHex, ToHex};
+
+use super::{Board, BoardManager, BoardState};
+
+// the bytes written to the flash at a time
+pub const CHUNK_SIZE: usize = 16;
+
+// the configuration words of the pic are above the program, the loader can't write them
+const PROGRAM_END: u32 = 0x1_0000;
+
+// a program for the pic, padded to whole chunks with the erased value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    // the byte address of `data`, two bytes per flash word
+    pub start: u32,
+    pub data: Vec<u8>,
+}
+
+fn invalid_data<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+impl Image {
+    // parse an intel hex file
+    pub fn from_ihex(hex: &str) -> Result<Self> {
+        let mut bytes = BTreeMap::new();
+        let mut base = 0u32;
+
+        for (i, line) in hex.lines().map(str::trim).enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let record = match line.strip_prefix(':') {
+                Some(record) => record.from_hex().map_err(invalid_data)?,
+                None => return Err(invalid_data(format!("line {}: no start code", i + 1))),
+            };
+            if record.len() < 5 || record.len() != 5 + record[0] as usize {
+                return Err(invalid_data(format!("line {}: bad length", i + 1)));
+            }
+            if record.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)) != 0 {
+                return Err(invalid_data(format!("line {}: bad checksum", i + 1)));
+            }
+
+            let offset = u32::from(u16::from_be_bytes([record[1], record[2]]));
+            let data = &record[4..record.len() - 1];
+            match record[3] {
+                0x00 => {
+                    for (j, v) in data.iter().enumerate() {
+                        bytes.insert(base + offset + j as u32, *v);
+                    }
+                }
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 4
+                }
+                0x04 if data.len() == 2 => {
+                    base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 16
+                }
+                0x03 | 0x05 => {}
+                kind => {
+                    return Err(invalid_data(format!(
+                        "line {}: bad record type {:02x}",
+                        i + 1,
+                        kind
+                    )))
+                }
+            }
+        }
+
+        let mut program = bytes.range(..PROGRAM_END).map(|(address, _)| *address);
+        let (first, last) = match (program.next(), program.next_back()) {
+            (Some(first), last) => (first, last.unwrap_or(first)),
+            (None, _) => return Err(invalid_data("no program in the image")),
+        };
+        let start = first / CHUNK_SIZE as u32 * CHUNK_SIZE as u32;
+        let end = last + 1;
+        let len = ((end - start) as usize).div_ceil(CHUNK_SIZE) * CHUNK_SIZE;
+
+        let mut data = vec![0xff; len];
+        for (address, v) in bytes.range(start..end) {
+            data[(address - start) as usize] = *v;
+        }
+        Ok(Self { start, data })
+    }
+
+    // the word address the image is written at
+    pub fn flash_pointer(&self) -> u16 {
+        (self.start / 2) as u16
+    }
+
+    // as an intel hex file
+    pub fn to_ihex(&self) -> String {
+        let record = |kind: u8, offset: u16, data: &[u8]| {
+            let mut record = vec![data.len() as u8];
+            record.extend_from_slice(&offset.to_be_bytes());
+            record.push(kind);
+            record.extend_from_slice(data);
+            let sum = record.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+            record.push(sum.wrapping_neg());
+            format!(":{}\n", record.to_hex().to_uppercase())
+        };
+
+        let mut hex = String::new();
+        let mut base = None;
+        for (i, chunk) in self.data.chunks(CHUNK_SIZE).enumerate() {
+            let address = self.start + (i * CHUNK_SIZE) as u32;
+            if base != Some(address >> 16) {
+                base = Some(address >> 16);
+                hex += &record(0x04, 0, &((address >> 16) as u16).to_be_bytes());
+            }
+            hex += &record(0x00, address as u16, chunk);
+        }
+        hex + &record(0x01, 0, &[])
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = [u8; CHUNK_SIZE]> + '_ {
+        self.data.chunks(CHUNK_SIZE).map(|x| {
+            let mut chunk = [0; CHUNK_SIZE];
+            chunk.copy_from_slice(x);
+            chunk
+        })
+    }
+}
+
+// write the image with the loader of the pic running, then read it back
+pub fn flash<T: BoardConfig>(i2c: &mut T, addr: u16, image: &Image) -> Result<()> {
+    i2c.erase_pic_app_program(addr)?;
+    write_image(i2c, addr, image)?;
+    verify_image(i2c, addr, image)
+}
+
+fn write_image<T: BoardConfig>(i2c: &mut T, addr: u16, image: &Image) -> Result<()> {
+    let chunks = image.data.len() / CHUNK_SIZE;
+    i2c.set_flash_pointer(addr, image.flash_pointer())?;
+    for (i, chunk) in image.chunks().enumerate() {
+        i2c.send_data_to_iic(addr, chunk)?;
+        i2c.write_data_into_pic(addr)?;
+        if (i + 1) % 64 == 0 || i + 1 == chunks {
+            info!(
+                "=> pic 0x{:02x}: written {}/{} chunks!",
+                addr,
+                i + 1,
+                chunks
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn verify_image<T: BoardConfig>(i2c: &mut T, addr: u16, image: &Image) -> Result<()> {
+    i2c.set_flash_pointer(addr, image.flash_pointer())?;
+    for (i, chunk) in image.chunks().enumerate() {
+        if i2c.read_data_from_flash(addr)? != chunk {
+            let address = image.start + (i * CHUNK_SIZE) as u32;
+            return Err(invalid_data(format!(
+                "pic 0x{:02x}: verify failed at 0x{:04x}",
+                addr, address
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl<T: BoardConfig> BoardManager<T> {
+    // flash the app of the pic of a board not powered, the app is started after
+    pub fn update_firmware(&mut self, id: u16, image: &Image) -> Result<()> {
+        // the app may be broken, only the loader has to answer
+        self.boards.entry(id).or_insert_with(|| Board::new(id));
+        let result = self.try_update_firmware(id, image);
+        if let Err(ref e) = result {
+            self.fault(id, format!("update firmware err: {}", e));
+        }
+        result
+    }
+
+    fn try_update_firmware(&mut self, id: u16, image: &Image) -> Result<()> {
+        let addr = self.board(id)?.addr();
+        match self.board(id)?.state {
+            BoardState::Detected | BoardState::Bootloader | BoardState::AppRunning => {}
+            ref state => return Err(super::invalid_state(id, state)),
+        }
+
+        self.i2c().reset_pic(addr)?;
+        self.set_state(id, BoardState::Bootloader);
+        sleep(self.settle);
+
+        info!("=> board {}: flashing {} bytes!", id, image.data.len());
+        flash(&mut *self.i2c(), addr, image)?;
+
+        self.i2c().jump_to_app(addr)?;
+        sleep(self.settle);
+        let version = self.i2c().get_software_version(addr)?;
+        self.boards.get_mut(&id).unwrap().pic_version = Some(version);
+        self.set_state(id, BoardState::AppRunning);
+        info!("=> board {}: pic version 0x{:02x}!", id, version);
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::util::{BoardConfig, TempLimits};
+
+pub use self::eeprom::{board_info, dump, restore, BoardInfo, EEPROM_POINTER, EEPROM_SIZE};
+pub use self::fan::{FanController, FanStatus, HwmonTach, Pid, Pwm, SimFan, SysfsPwm, Tach};
+pub use self::firmware::{flash, Image, CHUNK_SIZE};
+pub use self::temp::{Hwmon, SimSensor, TempSensor, Temperature, Thermal};
+pub use self::tune::{BoardProfile, Measured, Profile, Setting, TuneAction, Tuner};
+
+mod eeprom;
+mod fan;
+mod firmware;
+mod temp;
+#[cfg(test)]
+mod tests;
+mod tune;
+
+// heartbeats missed in a row before the board is given up
+const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoardState {
+    // the pic answers
+    Detected,
+    // the pic is reset and runs its loader
+    Bootloader,
+    AppRunning,
+    // the voltage of the chips is on
+    Powered,
+    Hashing,
+    // stopped and the voltage turned off until it cools down
+    Overheated,
+    Faulted(String),
+}
+
+// brings up the chips of a powered board, kept to restart the board after it cools down
+pub type InitChips = Box<dyn FnMut(u16) -> Result<()> + Send>;
+
+// called with false to stop sending work to a board, with true to resume
+pub type SenderHook = Box<dyn FnMut(u16, bool) + Send>;
+
+#[derive(Clone, Debug)]
+pub struct Board {
+    pub id: u16,
+    pub state: BoardState,
+    pub pic_version: Option<u8>,
+    // the voltage applied, lower than the configured one when the board is hot
+    pub voltage: Option<f64>,
+    pub last_heartbeat: Option<Instant>,
+    pub temperature: Option<Temperature>,
+    pub thermal: Thermal,
+    nominal_voltage: f64,
+    missed_heartbeats: u32,
+    missed_readings: u32,
+    // faulted for the safety of the rig, never powered up again until the miner restarts
+    latched: bool,
+}
+
+pub struct BoardManager<T> {
+    i2c: Arc<Mutex<T>>,
+    boards: BTreeMap<u16, Board>,
+    // the time the pic takes to switch between the loader and the app
+    settle: Duration,
+    inits: BTreeMap<u16, InitChips>,
+    sensor: Option<Box<dyn TempSensor>>,
+    limits: TempLimits,
+    sender: Option<SenderHook>,
+}
+
+impl Board {
+    fn new(id: u16) -> Self {
+        Self {
+            id,
+            state: BoardState::Detected,
+            pic_version: None,
+            voltage: None,
+            last_heartbeat: None,
+            temperature: None,
+            thermal: Thermal::Normal,
+            nominal_voltage: 0.0,
+            missed_heartbeats: 0,
+            missed_readings: 0,
+            latched: false,
+        }
+    }
+
+    // the i2c address of the pic
+    pub fn addr(&self) -> u16 {
+        0x50 + self.id
+    }
+
+    pub fn is_powered(&self) -> bool {
+        matches!(self.state, BoardState::Powered | BoardState::Hashing)
+    }
+}
+
+impl<T: BoardConfig> BoardManager<T> {
+    pub fn new(i2c: Arc<Mutex<T>>) -> Self {
+        Self {
+            i2c,
+            boards: BTreeMap::new(),
+            settle: Duration::from_millis(500),
+            inits: BTreeMap::new(),
+            sensor: None,
+            limits: TempLimits::default(),
+            sender: None,
+        }
+    }
+
+    pub fn with_settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    pub fn with_thermal(mut self, sensor: Box<dyn TempSensor>, limits: TempLimits) -> Self {
+        self.sensor = Some(sensor);
+        self.limits = limits;
+        self
+    }
+
+    // the work sent to the boards is gated by the thermal protection through `sender`
+    pub fn on_sender<F: FnMut(u16, bool) + Send + 'static>(&mut self, sender: F) {
+        self.sender = Some(Box::new(sender));
+    }
+
+    pub fn get(&self, id: u16) -> Option<&Board> {
+        self.boards.get(&id)
+    }
+
+    pub fn boards(&self) -> impl Iterator<Item = &Board> {
+        self.boards.values()
+    }
+
+    pub fn hashing(&self) -> Vec<u16> {
+        self.boards()
+            .filter(|x| x.state == BoardState::Hashing)
+            .map(|x| x.id)
+            .collect()
+    }
+
+    fn i2c(&self) -> MutexGuard<'_, T> {
+        let i2c = self.i2c.lock().unwrap();
+        sleep(Duration::from_micros(100));
+        i2c
+    }
+
+    // probe the pic of the board, a board not answering is not managed
+    pub fn detect(&mut self, id: u16) -> Result<()> {
+        let mut board = Board::new(id);
+        board.pic_version = Some(self.i2c().get_software_version(board.addr())?);
+        info!("=> board {} detected!", id);
+        self.boards.insert(id, board);
+        Ok(())
+    }
+
+    // from a detected board to the voltage of its chips on, a faulted board is probed again
+    // unless its fault is latched
+    pub fn power_up(&mut self, id: u16, voltage: f64) -> Result<()> {
+        let board = self.boards.get(&id);
+        if let Some(board) = board.filter(|x| x.latched) {
+            return Err(invalid_state(id, &board.state));
+        }
+        let faulted = matches!(board.map(|x| &x.state), Some(BoardState::Faulted(_)));
+        if faulted || board.is_none() {
+            self.detect(id)?;
+        }
+        let result = self.try_power_up(id, voltage);
+        if let Err(ref e) = result {
+            self.fault(id, format!("power up err: {}", e));
+        }
+        result
+    }
+
+    fn try_power_up(&mut self, id: u16, voltage: f64) -> Result<()> {
+        let addr = self.board(id)?.addr();
+        match self.board(id)?.state {
+            BoardState::Detected
+            | BoardState::Bootloader
+            | BoardState::AppRunning
+            | BoardState::Overheated => {}
+            ref state => return Err(invalid_state(id, state)),
+        }
+
+        self.i2c().reset_pic(addr)?;
+        self.set_state(id, BoardState::Bootloader);
+        sleep(self.settle);
+
+        self.i2c().jump_to_app(addr)?;
+        sleep(self.settle);
+        let version = self.i2c().get_software_version(addr)?;
+        self.boards.get_mut(&id).unwrap().pic_version = Some(version);
+        self.set_state(id, BoardState::AppRunning);
+
+        self.i2c().set_voltage(addr, voltage)?;
+        self.i2c().enable_voltage(addr)?;
+        let board = self.boards.get_mut(&id).unwrap();
+        board.voltage = Some(voltage);
+        board.nominal_voltage = voltage;
+        board.thermal = Thermal::Normal;
+        board.missed_heartbeats = 0;
+        board.missed_readings = 0;
+        self.set_state(id, BoardState::Powered);
+        Ok(())
+    }
+
+    // `init` brings up the chips of the powered board
+    pub fn start<F>(&mut self, id: u16, init: F) -> Result<()>
+    where
+        F: FnMut(u16) -> Result<()> + Send + 'static,
+    {
+        self.board(id)?;
+        self.inits.insert(id, Box::new(init));
+        let result = self.restart(id);
+        if let Err(ref e) = result {
+            self.fault(id, format!("init chips err: {}", e));
+        }
+        result
+    }
+
+    fn restart(&mut self, id: u16) -> Result<()> {
+        match self.board(id)?.state {
+            BoardState::Powered => {}
+            ref state => return Err(invalid_state(id, state)),
+        }
+        (self.inits.get_mut(&id).unwrap())(id)?;
+        self.set_state(id, BoardState::Hashing);
+        Ok(())
+    }
+
+    // init a hashing board again at another voltage, no work is sent to it meanwhile, a board
+    // not hashing is left as it is
+    pub fn retune<F>(&mut self, id: u16, voltage: f64, init: F) -> Result<()>
+    where
+        F: FnMut(u16) -> Result<()> + Send + 'static,
+    {
+        match self.board(id)?.state {
+            BoardState::Hashing => {}
+            ref state => return Err(invalid_state(id, state)),
+        }
+        let result = self.try_retune(id, voltage, init);
+        if let Err(ref e) = result {
+            self.fault(id, format!("retune err: {}", e));
+        }
+        result
+    }
+
+    fn try_retune<F>(&mut self, id: u16, voltage: f64, init: F) -> Result<()>
+    where
+        F: FnMut(u16) -> Result<()> + Send + 'static,
+    {
+        let board = self.board(id)?;
+        let (addr, thermal) = (board.addr(), board.thermal);
+
+        self.set_sender(id, false);
+        // a hot board stays below its voltage
+        let applied = match thermal {
+            Thermal::Warning => voltage - self.limits.voltage_step,
+            _ => voltage,
+        };
+        self.i2c().set_voltage(addr, applied)?;
+        let board = self.boards.get_mut(&id).unwrap();
+        board.voltage = Some(applied);
+        board.nominal_voltage = voltage;
+        self.set_state(id, BoardState::Powered);
+
+        self.inits.insert(id, Box::new(init));
+        self.restart(id)?;
+        self.set_sender(id, true);
+        Ok(())
+    }
+
+    // keep the pics of the running boards from powering down, returns the boards faulted now
+    pub fn heartbeat(&mut self) -> Vec<u16> {
+        let running: Vec<_> = self
+            .boards()
+            .filter(|x| {
+                matches!(
+                    x.state,
+                    BoardState::AppRunning
+                        | BoardState::Powered
+                        | BoardState::Hashing
+                        | BoardState::Overheated
+                )
+            })
+            .map(|x| (x.id, x.addr()))
+            .collect();
+
+        let mut faulted = Vec::new();
+        for (id, addr) in running {
+            let result = self.i2c().send_heart_beat(addr);
+            sleep(Duration::from_micros(100));
+
+            let board = self.boards.get_mut(&id).unwrap();
+            match result {
+                Ok(()) => {
+                    board.missed_heartbeats = 0;
+                    board.last_heartbeat = Some(Instant::now());
+                }
+                Err(e) => {
+                    board.missed_heartbeats += 1;
+                    warn!(
+                        "send heart beat to board {} err: {} ({} missed)!",
+                        id, e, board.missed_heartbeats
+                    );
+                    if board.missed_heartbeats >= MAX_MISSED_HEARTBEATS {
+                        self.fault(id, format!("heart beat err: {}", e));
+                        faulted.push(id);
+                    }
+                }
+            }
+        }
+        faulted
+    }
+
+    // turn the voltage of every board off, the faulted ones included
+    pub fn power_down(&mut self) {
+        let ids: Vec<_> = self.boards.keys().cloned().collect();
+        for id in ids {
+            let board = &self.boards[&id];
+            let faulted = matches!(board.state, BoardState::Faulted(_));
+            if !board.is_powered() && !faulted {
+                continue;
+            }
+
+            let addr = board.addr();
+            let result = self.i2c().disable_voltage(addr);
+            match result {
+                Ok(()) => {
+                    info!("=> board {} powered down!", id);
+                    self.boards.get_mut(&id).unwrap().voltage = None;
+                    if !faulted {
+                        self.set_state(id, BoardState::AppRunning);
+                    }
+                }
+                Err(e) => error!("power down board {} err: {}", id, e),
+            }
+        }
+    }
+
+    // give up every board still running, e.g. when the fans fail
+    pub fn fault_all(&mut self, reason: &str) {
+        let running: Vec<_> = self
+            .boards()
+            .filter(|x| x.is_powered() || x.state == BoardState::Overheated)
+            .map(|x| x.id)
+            .collect();
+        for id in running {
+            self.latch(id, reason.to_string());
+        }
+    }
+
+    // a fault for the safety of the rig (fans, temperatures), kept over power ups
+    pub fn latch(&mut self, id: u16, reason: String) {
+        self.fault(id, reason);
+        if let Some(board) = self.boards.get_mut(&id) {
+            board.latched = true;
+        }
+    }
+
+    // the board is given up, no more work is sent to it and its voltage is turned off if possible
+    pub fn fault(&mut self, id: u16, reason: String) {
+        if let Some(board) = self.boards.get(&id) {
+            error!("board {} faulted: {}!", id, reason);
+            let (addr, powered) = (board.addr(), board.is_powered());
+            self.set_sender(id, false);
+            if powered {
+                if let Err(e) = self.i2c().disable_voltage(addr) {
+                    error!("power down board {} err: {}", id, e);
+                }
+            }
+            self.set_state(id, BoardState::Faulted(reason));
+        }
+    }
+
+    fn board(&self, id: u16) -> Result<&Board> {
+        self.boards
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("board {} is not detected", id)))
+    }
+
+    fn set_sender(&mut self, id: u16, enable: bool) {
+        if let Some(ref mut sender) = self.sender {
+            sender(id, enable);
+        }
+    }
+
+    fn set_state(&mut self, id: u16, state: BoardState) {
+        let board = self.boards.get_mut(&id).unwrap();
+        debug!("board {}: {:?} -> {:?}", id, board.state, state);
+        board.state = state;
+    }
+}
+
+fn invalid_state(id: u16, state: &BoardState) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("board {} can not do that when {:?}", id, state),
+    )
+}
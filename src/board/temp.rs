@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::util::{BoardConfig, TempLimits};
+
+use super::{BoardManager, BoardState};
+
+// readings failed in a row before the board is given up, it can't be protected blind
+const MAX_MISSED_READINGS: u32 = 3;
+
+// degrees celsius
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Temperature {
+    pub board: f64,
+    // the sensor next to the chips, if the board has one
+    pub chip: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Thermal {
+    Normal,
+    // the voltage is lowered
+    Warning,
+    // the board is stopped and its voltage turned off
+    Critical,
+}
+
+pub trait TempSensor: Send {
+    fn read(&mut self, board: u16) -> Result<Temperature>;
+}
+
+// the sensors of the boards as hwmon devices, temp1 on the board and temp2 by the chips
+pub struct Hwmon {
+    // `{}` is replaced by the board id
+    pattern: String,
+}
+
+// the temperatures are set by the test or the emulator
+#[derive(Clone, Default)]
+pub struct SimSensor {
+    temps: Arc<Mutex<BTreeMap<u16, Temperature>>>,
+}
+
+impl Temperature {
+    pub fn hottest(&self) -> f64 {
+        self.chip.map_or(self.board, |x| x.max(self.board))
+    }
+}
+
+impl Thermal {
+    // a board back to normal has to cool below `warning - hysteresis` first
+    pub fn next(self, limits: &TempLimits, temp: f64) -> Thermal {
+        let cooled = temp <= limits.warning - limits.hysteresis;
+        match self {
+            _ if temp >= limits.critical => Thermal::Critical,
+            Thermal::Normal if temp >= limits.warning => Thermal::Warning,
+            Thermal::Warning | Thermal::Critical if cooled => Thermal::Normal,
+            thermal => thermal,
+        }
+    }
+}
+
+impl Hwmon {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+        }
+    }
+}
+
+impl TempSensor for Hwmon {
+    fn read(&mut self, board: u16) -> Result<Temperature> {
+        let dir = self.pattern.replace("{}", &board.to_string());
+        let dir = Path::new(&dir);
+        let read = |file: &str| -> Result<f64> {
+            let millis = fs::read_to_string(dir.join(file))?;
+            let millis = millis
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            Ok(f64::from(millis) / 1000.0)
+        };
+        Ok(Temperature {
+            board: read("temp1_input")?,
+            chip: read("temp2_input").ok(),
+        })
+    }
+}
+
+impl SimSensor {
+    pub fn set(&self, board: u16, temp: Temperature) {
+        self.temps.lock().unwrap().insert(board, temp);
+    }
+
+    pub fn remove(&self, board: u16) {
+        self.temps.lock().unwrap().remove(&board);
+    }
+}
+
+impl TempSensor for SimSensor {
+    fn read(&mut self, board: u16) -> Result<Temperature> {
+        self.temps
+            .lock()
+            .unwrap()
+            .get(&board)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no sensor on board {}", board)))
+    }
+}
+
+impl<T: BoardConfig> BoardManager<T> {
+    // the hottest sensor of the boards powered, None if none was read
+    pub fn hottest(&self) -> Option<f64> {
+        self.boards()
+            .filter(|x| x.is_powered())
+            .filter_map(|x| x.temperature.map(|x| x.hottest()))
+            .fold(None, |hottest, x| {
+                Some(hottest.map_or(x, |y: f64| y.max(x)))
+            })
+    }
+
+    // read the temperatures of the powered boards and act on the limits, returns the changes
+    pub fn check_temperature(&mut self) -> Vec<(u16, Thermal)> {
+        let sensor = match self.sensor {
+            Some(ref mut sensor) => sensor,
+            None => return Vec::new(),
+        };
+        let readings: Vec<_> = self
+            .boards
+            .values()
+            .filter(|x| x.is_powered() || x.state == BoardState::Overheated)
+            .map(|x| (x.id, sensor.read(x.id)))
+            .collect();
+
+        let mut changes = Vec::new();
+        for (id, reading) in readings {
+            let board = self.boards.get_mut(&id).unwrap();
+            let temp = match reading {
+                Ok(temp) => {
+                    board.temperature = Some(temp);
+                    board.missed_readings = 0;
+                    temp.hottest()
+                }
+                Err(e) => {
+                    board.missed_readings += 1;
+                    warn!("read temperature of board {} err: {}!", id, e);
+                    if board.missed_readings >= MAX_MISSED_READINGS {
+                        self.latch(id, format!("read temperature err: {}", e));
+                    }
+                    continue;
+                }
+            };
+
+            let thermal = board.thermal.next(&self.limits, temp);
+            if thermal != board.thermal {
+                warn!(
+                    "=> board {} at {:.1}°C: {:?} -> {:?}!",
+                    id, temp, board.thermal, thermal
+                );
+                if let Err(e) = self.set_thermal(id, thermal) {
+                    self.latch(id, format!("thermal protection err: {}", e));
+                }
+                changes.push((id, thermal));
+            }
+        }
+        changes
+    }
+
+    fn set_thermal(&mut self, id: u16, thermal: Thermal) -> Result<()> {
+        let board = &self.boards[&id];
+        let (addr, nominal, previous) = (board.addr(), board.nominal_voltage, board.thermal);
+        self.boards.get_mut(&id).unwrap().thermal = thermal;
+
+        match (previous, thermal) {
+            (_, Thermal::Critical) => {
+                self.set_sender(id, false);
+                self.i2c().disable_voltage(addr)?;
+                self.boards.get_mut(&id).unwrap().voltage = None;
+                self.set_state(id, BoardState::Overheated);
+            }
+            (Thermal::Normal, Thermal::Warning) => {
+                let voltage = nominal - self.limits.voltage_step;
+                self.i2c().set_voltage(addr, voltage)?;
+                self.boards.get_mut(&id).unwrap().voltage = Some(voltage);
+            }
+            (Thermal::Warning, Thermal::Normal) => {
+                self.i2c().set_voltage(addr, nominal)?;
+                self.boards.get_mut(&id).unwrap().voltage = Some(nominal);
+            }
+            (Thermal::Critical, Thermal::Normal) => {
+                self.try_power_up(id, nominal)?;
+                self.restart(id)?;
+                self.set_sender(id, true);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
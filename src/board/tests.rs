@@ -0,0 +1,704 @@
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+
+use crate::backend::{ChipStats, HashStats};
+use crate::util::i2c::{
+    Command, HashBoardId, MacAddress, EEPROM_DATE, EEPROM_HOST_MAC, EEPROM_ID, EEPROM_MAC,
+    EEPROM_WHICH_MAC,
+};
+use crate::util::{BoardConfig, Fan, SimPic, TempLimits, Tune, TuneTarget};
+
+use super::*;
+
+// the pics of 16 boards
+fn manager() -> (Arc<Mutex<SimPic>>, BoardManager<SimPic>) {
+    let pic = Arc::new(Mutex::new(SimPic::new(0x50..0x60)));
+    let manager = BoardManager::new(pic.clone()).with_settle(Duration::from_millis(0));
+    (pic, manager)
+}
+
+fn commands(pic: &Arc<Mutex<SimPic>>) -> Vec<(u16, u8, Vec<u8>)> {
+    pic.lock().unwrap().commands.drain(..).collect()
+}
+
+#[test]
+fn power_up() {
+    let (pic, mut manager) = manager();
+    manager.power_up(6, 8.6).unwrap();
+
+    let board = manager.get(6).unwrap();
+    assert_eq!(board.state, BoardState::Powered);
+    assert_eq!(board.addr(), 0x56);
+    assert_eq!(board.pic_version, Some(0x03));
+    assert_eq!(
+        commands(&pic),
+        [
+            (0x56, Command::GET_PIC_SOFTWARE_VERSION as u8, vec![]),
+            (0x56, Command::RESET_PIC as u8, vec![]),
+            (0x56, Command::JUMP_FROM_LOADER_TO_APP as u8, vec![]),
+            (0x56, Command::GET_PIC_SOFTWARE_VERSION as u8, vec![]),
+            (0x56, Command::SET_VOLTAGE as u8, vec![142]),
+            (0x56, Command::ENABLE_VOLTAGE as u8, vec![1]),
+        ]
+    );
+
+    assert!(manager.start(7, |_| Ok(())).is_err());
+    manager.start(6, |_| Ok(())).unwrap();
+    assert_eq!(manager.hashing(), [6]);
+    assert!(manager.power_up(6, 8.6).is_err());
+}
+
+#[test]
+fn absent_board() {
+    let (pic, mut manager) = manager();
+    pic.lock().unwrap().unplug(0x51);
+
+    assert!(manager.power_up(1, 8.6).is_err());
+    assert!(manager.get(1).is_none());
+    assert!(manager.heartbeat().is_empty());
+}
+
+#[test]
+fn faulted_on_init_err() {
+    let (pic, mut manager) = manager();
+    manager.power_up(2, 8.6).unwrap();
+    commands(&pic);
+
+    let init = |_| Err(Error::new(ErrorKind::InvalidData, "no chips"));
+    assert!(manager.start(2, init).is_err());
+    match manager.get(2).unwrap().state {
+        BoardState::Faulted(ref reason) => assert!(reason.contains("no chips")),
+        ref state => panic!("{:?}", state),
+    }
+    assert_eq!(
+        commands(&pic),
+        [(0x52, Command::ENABLE_VOLTAGE as u8, vec![0])]
+    );
+}
+
+#[test]
+fn heartbeat() {
+    let (pic, mut manager) = manager();
+    for id in 0..2 {
+        manager.power_up(id, 8.6).unwrap();
+        manager.start(id, |_| Ok(())).unwrap();
+    }
+    commands(&pic);
+
+    assert!(manager.heartbeat().is_empty());
+    assert_eq!(commands(&pic).len(), 2);
+    assert!(manager.get(1).unwrap().last_heartbeat.is_some());
+
+    // board 1 stops answering, it is given up after missing 3 heart beats
+    pic.lock().unwrap().unplug(0x51);
+    for _ in 0..2 {
+        assert!(manager.heartbeat().is_empty());
+        assert_eq!(manager.get(1).unwrap().state, BoardState::Hashing);
+    }
+    assert_eq!(manager.heartbeat(), [1]);
+    assert!(matches!(
+        manager.get(1).unwrap().state,
+        BoardState::Faulted(_)
+    ));
+    assert_eq!(manager.hashing(), [0]);
+
+    // the faulted board is not supervised anymore
+    commands(&pic);
+    assert!(manager.heartbeat().is_empty());
+    assert_eq!(
+        commands(&pic),
+        [(0x50, Command::SEND_HEART_BEAT as u8, vec![])]
+    );
+
+    // until it answers again and is powered up anew
+    assert!(manager.power_up(1, 8.6).is_err());
+    pic.lock().unwrap().plug(0x51);
+    manager.power_up(1, 8.6).unwrap();
+    manager.start(1, |_| Ok(())).unwrap();
+    assert_eq!(manager.hashing(), [0, 1]);
+}
+
+#[test]
+fn power_down() {
+    let (pic, mut manager) = manager();
+    for id in 0..3 {
+        manager.power_up(id, 8.6).unwrap();
+    }
+    manager.start(0, |_| Ok(())).unwrap();
+    manager.fault(1, String::from("too hot"));
+    commands(&pic);
+
+    manager.power_down();
+    let disabled = (0..3)
+        .map(|x| (0x50 + x, Command::ENABLE_VOLTAGE as u8, vec![0]))
+        .collect::<Vec<_>>();
+    assert_eq!(commands(&pic), disabled);
+    assert_eq!(manager.get(0).unwrap().state, BoardState::AppRunning);
+    assert_eq!(manager.get(0).unwrap().voltage, None);
+    assert_eq!(
+        manager.get(1).unwrap().state,
+        BoardState::Faulted(String::from("too hot"))
+    );
+
+    // a board powered down can be powered up again
+    manager.power_up(0, 8.6).unwrap();
+    assert_eq!(manager.get(0).unwrap().state, BoardState::Powered);
+}
+
+#[test]
+fn thermal_next() {
+    let limits = TempLimits::default();
+    let steps = [
+        (84.0, Thermal::Normal),
+        (85.0, Thermal::Warning),
+        (81.0, Thermal::Warning),
+        (80.0, Thermal::Normal),
+        (95.0, Thermal::Critical),
+        (84.0, Thermal::Critical),
+        (80.0, Thermal::Normal),
+    ];
+    let mut thermal = Thermal::Normal;
+    for (temp, next) in steps.iter() {
+        thermal = thermal.next(&limits, *temp);
+        assert_eq!(thermal, *next, "at {}", temp);
+    }
+    assert_eq!(Thermal::Normal.next(&limits, 99.0), Thermal::Critical);
+}
+
+#[test]
+fn thermal_protection() {
+    let sensor = SimSensor::default();
+    let (pic, manager) = manager();
+    let mut manager = manager.with_thermal(Box::new(sensor.clone()), TempLimits::default());
+    let senders = Arc::new(Mutex::new(Vec::new()));
+    let senders_clone = senders.clone();
+    manager.on_sender(move |id, enable| senders_clone.lock().unwrap().push((id, enable)));
+    let inits = Arc::new(Mutex::new(0));
+    let inits_clone = inits.clone();
+
+    manager.power_up(3, 8.6).unwrap();
+    manager
+        .start(3, move |_| {
+            *inits_clone.lock().unwrap() += 1;
+            Ok(())
+        })
+        .unwrap();
+    commands(&pic);
+
+    let check = |manager: &mut BoardManager<SimPic>, board, chip| {
+        sensor.set(3, Temperature { board, chip });
+        manager.check_temperature()
+    };
+    assert!(check(&mut manager, 60.0, Some(70.0)).is_empty());
+
+    // too hot, the voltage is lowered until the board cools below warning - hysteresis
+    assert_eq!(
+        check(&mut manager, 60.0, Some(86.0)),
+        [(3, Thermal::Warning)]
+    );
+    assert_eq!(manager.get(3).unwrap().voltage, Some(8.6 - 0.3));
+    assert!(check(&mut manager, 82.0, None).is_empty());
+    assert_eq!(check(&mut manager, 79.0, None), [(3, Thermal::Normal)]);
+    assert_eq!(manager.get(3).unwrap().voltage, Some(8.6));
+    let sent: Vec<_> = commands(&pic).into_iter().map(|x| x.1).collect();
+    assert_eq!(sent, [Command::SET_VOLTAGE as u8; 2]);
+
+    // far too hot, the board is stopped until it cools down, then restarted
+    assert_eq!(check(&mut manager, 96.0, None), [(3, Thermal::Critical)]);
+    assert_eq!(manager.get(3).unwrap().state, BoardState::Overheated);
+    assert_eq!(
+        commands(&pic),
+        [(0x53, Command::ENABLE_VOLTAGE as u8, vec![0])]
+    );
+    assert_eq!(*senders.lock().unwrap(), [(3, false)]);
+    assert!(check(&mut manager, 84.0, None).is_empty());
+    assert_eq!(check(&mut manager, 75.0, None), [(3, Thermal::Normal)]);
+    assert_eq!(manager.get(3).unwrap().state, BoardState::Hashing);
+    assert_eq!(*inits.lock().unwrap(), 2);
+    assert_eq!(*senders.lock().unwrap(), [(3, false), (3, true)]);
+
+    // a board that can't be read is not protected, it is given up
+    sensor.remove(3);
+    for _ in 0..3 {
+        assert!(manager.check_temperature().is_empty());
+    }
+    assert!(matches!(
+        manager.get(3).unwrap().state,
+        BoardState::Faulted(_)
+    ));
+    assert_eq!(senders.lock().unwrap().last(), Some(&(3, false)));
+    assert!(manager.power_up(3, 8.6).is_err());
+}
+
+#[test]
+fn pid() {
+    let mut pid = Pid::new(0.1, 0.01, 0.0);
+    assert_eq!(pid.update(5.0, 0.0, 0.2, 1.0), 0.5);
+    // the integral builds up while the error stays
+    assert!(pid.update(5.0, 10.0, 0.2, 1.0) > 0.5);
+    assert_eq!(pid.update(-10.0, 10.0, 0.2, 1.0), 0.2);
+    assert_eq!(pid.update(50.0, 10.0, 0.2, 1.0), 1.0);
+}
+
+#[test]
+fn fan_controller() {
+    let sim = SimFan::new(2, 6000);
+    let config = Fan {
+        min_rpm: 1000,
+        failure_time: 10.0,
+        ..Fan::default()
+    };
+    let mut fans = FanController::new(Box::new(sim.clone()), Box::new(sim.clone()), config);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    // no temperature is known, the fans run full
+    assert_eq!(
+        fans.update(None, at(0)).unwrap(),
+        FanStatus::Running {
+            duty: 1.0,
+            rpm: vec![6000, 6000]
+        }
+    );
+
+    // below the target the fans slow down to the minimum, above it they speed up
+    fans.update(Some(60.0), at(5)).unwrap();
+    assert_eq!(sim.duty(), 0.2);
+    let mut last = sim.duty();
+    for i in 0..5 {
+        fans.update(Some(85.0), at(10 + i * 5)).unwrap();
+        assert!(sim.duty() > last);
+        last = sim.duty();
+    }
+
+    // a fan stopped is failed after the failure time
+    sim.fail(1, true);
+    match fans.update(Some(75.0), at(40)).unwrap() {
+        FanStatus::Running { rpm, .. } => assert_eq!(rpm[1], 0),
+        status => panic!("{:?}", status),
+    }
+    assert_eq!(
+        fans.update(Some(75.0), at(50)).unwrap(),
+        FanStatus::Failed(vec![1])
+    );
+    sim.fail(1, false);
+    assert!(matches!(
+        fans.update(Some(75.0), at(60)).unwrap(),
+        FanStatus::Running { .. }
+    ));
+}
+
+#[test]
+fn fault_all() {
+    let (pic, manager) = manager();
+    let sensor = SimSensor::default();
+    let mut manager = manager.with_thermal(Box::new(sensor.clone()), TempLimits::default());
+    for id in 0..2 {
+        manager.power_up(id, 8.6).unwrap();
+        sensor.set(
+            id,
+            Temperature {
+                board: 50.0 + f64::from(id),
+                chip: Some(70.0 + f64::from(id)),
+            },
+        );
+    }
+    assert_eq!(manager.hottest(), None);
+    manager.check_temperature();
+    assert_eq!(manager.hottest(), Some(71.0));
+    commands(&pic);
+
+    manager.fault_all("fans [0] failed");
+    assert!(manager
+        .boards()
+        .all(|x| x.state == BoardState::Faulted(String::from("fans [0] failed"))));
+    assert_eq!(commands(&pic).len(), 2);
+    assert_eq!(manager.hottest(), None);
+
+    // the fault is latched, the boards are not probed nor powered up again
+    for id in 0..2 {
+        assert!(manager.power_up(id, 8.6).is_err());
+    }
+    assert!(commands(&pic).is_empty());
+    assert!(manager
+        .boards()
+        .all(|x| x.state == BoardState::Faulted(String::from("fans [0] failed"))));
+}
+
+#[test]
+fn parse_ihex() {
+    // the configuration words at 0x1000e are left out
+    let hex = ":0300300002337A1E\n:020000040001F9\n:02000E00E43FCD\n:00000001FF\n";
+    let image = Image::from_ihex(hex).unwrap();
+    assert_eq!(image.start, 0x30);
+    assert_eq!(image.flash_pointer(), 0x18);
+    assert_eq!(image.data.len(), CHUNK_SIZE);
+    assert_eq!(image.data[..4], [0x02, 0x33, 0x7a, 0xff]);
+
+    assert!(Image::from_ihex(":0300300002337A1F\n").is_err());
+    assert!(Image::from_ihex("0300300002337A1E\n").is_err());
+    assert!(Image::from_ihex(":03003000023\n").is_err());
+    assert!(Image::from_ihex(":00000001FF\n").is_err());
+}
+
+#[test]
+fn update_firmware() {
+    let program: Vec<u8> = (0..200u32).map(|x| (x * 7) as u8).collect();
+    let hex = Image {
+        start: 0x600,
+        data: program.clone(),
+    }
+    .to_ihex();
+    let image = Image::from_ihex(&hex).unwrap();
+    assert_eq!(image.data.len(), 208);
+
+    // the new app has another version
+    let flash_pic = |stuck| {
+        let mut sim = SimPic::new(Some(0x54));
+        sim.pic(0x54).stuck = stuck;
+        sim.pic(0x54).version = 0x04;
+        let pic = Arc::new(Mutex::new(sim));
+        let manager = BoardManager::new(pic.clone()).with_settle(Duration::from_millis(0));
+        (pic, manager)
+    };
+
+    let (pic, mut manager) = flash_pic(None);
+    manager.update_firmware(4, &image).unwrap();
+    let board = manager.get(4).unwrap();
+    assert_eq!(board.state, BoardState::AppRunning);
+    assert_eq!(board.pic_version, Some(0x04));
+    let pic = &mut *pic.lock().unwrap();
+    let pic = pic.pic(0x54);
+    assert!(!pic.loader);
+    assert_eq!(pic.flash[0x600..0x600 + 200], program[..]);
+    assert_eq!(pic.flash[0x600 + 200..0x600 + 208], [0xff; 8]);
+    assert_eq!(pic.flash[0x5ff], 0xff);
+
+    // the write that didn't take is caught reading back
+    let (_, mut manager) = flash_pic(Some(0x640));
+    let e = manager.update_firmware(4, &image).unwrap_err();
+    assert!(e.to_string().contains("0x0640"), "{}", e);
+    assert!(matches!(
+        manager.get(4).unwrap().state,
+        BoardState::Faulted(_)
+    ));
+}
+
+#[test]
+fn board_ids() {
+    let id: HashBoardId = "S9K1234567".parse().unwrap();
+    assert_eq!(id.0[..11], *b"S9K1234567\0");
+    assert_eq!(id.to_string(), "S9K1234567");
+    assert!("S9K1234567890".parse::<HashBoardId>().is_err());
+
+    let mac: MacAddress = "00:0a:fe:12:34:56".parse().unwrap();
+    assert_eq!(mac.0, [0x00, 0x0a, 0xfe, 0x12, 0x34, 0x56]);
+    assert_eq!(mac.to_string(), "00:0a:fe:12:34:56");
+    for bad in &[
+        "00:0a:fe:12:34",
+        "00:0a:fe:12:34:56:78",
+        "0:0a:fe:12:34:56",
+        "zz:0a:fe:12:34:56",
+    ] {
+        assert!(bad.parse::<MacAddress>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn board_info() {
+    let mut pic = SimPic::new(Some(0x55));
+    let mac: MacAddress = "00:0a:fe:12:34:56".parse().unwrap();
+    let state = pic.pic(0x55);
+    state.eeprom()[EEPROM_DATE].copy_from_slice(&[19, 4, 30]);
+    state.eeprom()[EEPROM_MAC].copy_from_slice(&mac.0);
+    state.eeprom()[EEPROM_WHICH_MAC] = 1;
+    state.version = 0x04;
+    pic.set_hash_board_id(0x55, &"S9K1234567".parse().unwrap())
+        .unwrap();
+    pic.set_host_mac_address(0x55, mac).unwrap();
+    let time = NaiveDate::from_ymd_opt(2019, 5, 6)
+        .unwrap()
+        .and_hms_opt(7, 8, 9)
+        .unwrap();
+    pic.set_voltage_time(0x55, time).unwrap();
+    assert_eq!(pic.pic(0x55).voltage_time, [19, 5, 6, 7, 8, 9]);
+    assert_eq!(pic.pic(0x55).eeprom()[EEPROM_HOST_MAC], mac.0);
+    let too_early = NaiveDate::from_ymd_opt(1999, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    assert!(pic.set_voltage_time(0x55, too_early).is_err());
+
+    let info = super::board_info(&mut pic, 0x55).unwrap();
+    assert_eq!(
+        info,
+        BoardInfo {
+            id: "S9K1234567".parse().unwrap(),
+            date: NaiveDate::from_ymd_opt(2019, 4, 30).unwrap(),
+            mac,
+            which_mac: 1,
+            pic_version: 0x04,
+        }
+    );
+    assert_eq!(
+        info.to_string(),
+        "id: S9K1234567, date: 2019-04-30, mac: 00:0a:fe:12:34:56 (1), pic version: 0x04"
+    );
+}
+
+#[test]
+fn dump_and_restore() {
+    let mut pic = SimPic::new(Some(0x55));
+    for (i, v) in pic.pic(0x55).flash.iter_mut().enumerate() {
+        *v = (i * 3) as u8;
+    }
+    let saved = dump(&mut pic, 0x55, EEPROM_POINTER, EEPROM_SIZE).unwrap();
+    assert_eq!(saved.start, 0xf00);
+    assert_eq!(saved.data, pic.pic(0x55).eeprom()[..]);
+
+    // the dump is kept as an intel hex file
+    let saved = Image::from_ihex(&saved.to_ihex()).unwrap();
+    pic.set_hash_board_id(0x55, &"S9K7654321".parse().unwrap())
+        .unwrap();
+    pic.set_temp_offset(0x55, 0).unwrap();
+    restore(&mut pic, 0x55, &saved).unwrap();
+    assert_eq!(pic.pic(0x55).eeprom()[..], saved.data[..]);
+    // the program is not touched
+    assert_eq!(pic.pic(0x55).flash[0x10], 0x30);
+
+    // another range than the eeprom, a dump of another board
+    let program = dump(&mut pic, 0x55, 0x10, EEPROM_SIZE).unwrap();
+    assert!(restore(&mut pic, 0x55, &program).is_err());
+    pic.pic(0x55).eeprom()[EEPROM_DATE.start] ^= 1;
+    assert!(restore(&mut pic, 0x55, &saved).is_err());
+    pic.pic(0x55).eeprom()[EEPROM_DATE.start] ^= 1;
+
+    pic.pic(0x55).stuck = Some(0xf00 + EEPROM_ID.start);
+    assert!(restore(&mut pic, 0x55, &saved).is_err());
+}
+
+#[test]
+fn retune() {
+    let (pic, mut manager) = manager();
+    let senders = Arc::new(Mutex::new(Vec::new()));
+    let senders_clone = senders.clone();
+    manager.on_sender(move |id, enable| senders_clone.lock().unwrap().push((id, enable)));
+    manager.power_up(5, 8.6).unwrap();
+    manager.start(5, |_| Ok(())).unwrap();
+    assert!(manager.retune(4, 8.8, |_| Ok(())).is_err());
+
+    let params = Arc::new(Mutex::new(Vec::new()));
+    let params_clone = params.clone();
+    manager
+        .retune(5, 8.8, move |_| {
+            params_clone.lock().unwrap().push(112);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(manager.get(5).unwrap().state, BoardState::Hashing);
+    assert_eq!(manager.get(5).unwrap().voltage, Some(8.8));
+    assert_eq!(*params.lock().unwrap(), [112]);
+    assert_eq!(*senders.lock().unwrap(), [(5, false), (5, true)]);
+    let raw = pic.lock().unwrap().pic(0x55).voltage;
+    assert_eq!(raw, (1608.420446 - 170.423497 * 8.8) as u8);
+
+    // a board not hashing is not retuned, nor given up for it
+    manager.power_down();
+    assert!(manager.retune(5, 8.6, |_| Ok(())).is_err());
+    assert_eq!(manager.get(5).unwrap().state, BoardState::AppRunning);
+
+    // a board failing to init at the new setting is given up
+    manager.power_up(5, 8.6).unwrap();
+    manager.start(5, |_| Ok(())).unwrap();
+    let init = |_| Err(Error::new(ErrorKind::InvalidData, "no chips"));
+    assert!(manager.retune(5, 8.8, init).is_err());
+    assert!(matches!(
+        manager.get(5).unwrap().state,
+        BoardState::Faulted(_)
+    ));
+}
+
+// the chips of a board make errors above a param growing with the voltage
+fn tune(target: TuneTarget, start: Setting) -> (Setting, Vec<Setting>) {
+    let config = Tune {
+        target,
+        ..Tune::default()
+    };
+    let mut tuner = Tuner::new(config.clone());
+    let mut stats = HashStats::default();
+    let mut now = Instant::now();
+    tuner.add(0, start, &stats, now);
+
+    let mut current = start;
+    let mut settings = Vec::new();
+    for _ in 0..50 {
+        let limit = 100 + ((current.voltage - 8.2) * 10.0).round() as u32 * 4;
+        let nonces = u64::from(current.param) * 6000;
+        let chip = stats
+            .boards
+            .entry(0)
+            .or_default()
+            .chips
+            .entry(0)
+            .or_insert(ChipStats {
+                nonces: 0,
+                hw_errors: 0,
+                last_seen: now,
+            });
+        chip.nonces += nonces;
+        chip.hw_errors += if current.param > limit {
+            nonces / 20
+        } else {
+            nonces / 200
+        };
+
+        now += Duration::from_secs_f64(config.interval);
+        for action in tuner.update(&stats, &[0], now) {
+            match action {
+                TuneAction::Set { board: 0, setting } => {
+                    current = setting;
+                    settings.push(setting);
+                }
+                TuneAction::Tuned { board: 0, setting } => {
+                    assert_eq!(setting, current);
+                    assert!(tuner.is_tuned(0));
+                    return (setting, settings);
+                }
+                action => panic!("{:?}", action),
+            }
+        }
+    }
+    panic!("not tuned: {:?}", settings);
+}
+
+#[test]
+fn tuner() {
+    let start = Setting {
+        voltage: 8.6,
+        param: 108,
+    };
+    let (tuned, settings) = tune(TuneTarget::Hashrate, start);
+    assert_eq!(
+        tuned,
+        Setting {
+            voltage: 8.8,
+            param: 124,
+        }
+    );
+    let config = Tune::default();
+    assert!(settings.iter().all(|x| x.param >= config.min_param
+        && x.param <= config.max_param
+        && x.voltage >= config.min_voltage
+        && x.voltage <= config.max_voltage));
+
+    // the lower voltage takes less energy, even if the frequency is lower too
+    let (tuned, _) = tune(TuneTarget::Efficiency, start);
+    assert_eq!(
+        tuned,
+        Setting {
+            voltage: 8.2,
+            param: 100,
+        }
+    );
+
+    // a start with too many errors is slowed down
+    let hot = Setting {
+        voltage: 8.2,
+        param: 112,
+    };
+    let (tuned, _) = tune(TuneTarget::Efficiency, hot);
+    assert_eq!(tuned.voltage, 8.2);
+    assert!(tuned.param <= 100);
+}
+
+#[test]
+fn tuner_pauses() {
+    let config = Tune::default();
+    let interval = Duration::from_secs_f64(config.interval);
+    let mut tuner = Tuner::new(config);
+    let mut stats = HashStats::default();
+    let mut now = Instant::now();
+    let start = Setting {
+        voltage: 8.6,
+        param: 108,
+    };
+    tuner.add(0, start, &stats, now);
+
+    // overheated for the interval, the board is not measured at 0 H/s
+    now += interval;
+    assert!(tuner.update(&stats, &[], now).is_empty());
+
+    // hashing again, it is measured from then on
+    stats.boards.entry(0).or_default().chips.insert(
+        0,
+        ChipStats {
+            nonces: 600_000,
+            hw_errors: 0,
+            last_seen: now,
+        },
+    );
+    now += interval;
+    let setting = Setting {
+        param: 112,
+        ..start
+    };
+    assert_eq!(
+        tuner.update(&stats, &[0], now),
+        [TuneAction::Set { board: 0, setting }]
+    );
+}
+
+#[test]
+fn tune_profile() {
+    let config = Tune {
+        profile: std::env::temp_dir()
+            .join(format!("stratum-profile-{}.json", std::process::id()))
+            .to_string_lossy()
+            .into_owned(),
+        ..Tune::default()
+    };
+    let mut tuner = Tuner::new(config.clone());
+    let mut stats = HashStats::default();
+    let mut now = Instant::now();
+    let profile = BoardProfile {
+        setting: Setting {
+            voltage: 8.4,
+            param: 104,
+        },
+        hashrate: 4500.0,
+        efficiency: 96.0,
+    };
+    tuner.add_tuned(2, &profile, &stats, now);
+
+    // a tuned board stays at its setting, even with errors
+    for _ in 0..3 {
+        let chip = stats
+            .boards
+            .entry(2)
+            .or_default()
+            .chips
+            .entry(3)
+            .or_insert(ChipStats {
+                nonces: 0,
+                hw_errors: 0,
+                last_seen: now,
+            });
+        chip.nonces += 1000;
+        chip.hw_errors += 100;
+        now += Duration::from_secs_f64(config.interval);
+        assert!(tuner.update(&stats, &[2], now).is_empty());
+    }
+
+    let saved = tuner.profile();
+    assert_eq!(saved.target, TuneTarget::Efficiency);
+    assert_eq!(saved.boards[&2].setting, profile.setting);
+    assert_eq!(saved.boards[&2].hashrate, 4500.0);
+
+    saved.save(&config.profile).unwrap();
+    assert_eq!(Profile::load(&config.profile).unwrap(), saved);
+    std::fs::remove_file(&config.profile).unwrap();
+    assert!(Profile::load(&config.profile).is_err());
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{error_rate, hashrate, HashStats};
+use crate::util::{Tune, TuneTarget};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Setting {
+    pub voltage: f64,
+    // the frequency setting of the chips, as `param` of the board config
+    pub param: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TuneAction {
+    // init the board again at the setting
+    Set { board: u16, setting: Setting },
+    // the board is at its best setting, the profile can be saved
+    Tuned { board: u16, setting: Setting },
+}
+
+// the tuned settings, loaded at boot if tuned for the same target
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub target: TuneTarget,
+    pub boards: BTreeMap<u16, BoardProfile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BoardProfile {
+    pub setting: Setting,
+    // as measured, GH/s and estimated J/TH
+    pub hashrate: f64,
+    pub efficiency: f64,
+}
+
+// hashrate in H/s, hardware error rate 0.0 to 1.0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measured {
+    pub hashrate: f64,
+    pub hw_error_rate: f64,
+}
+
+struct BoardTuner {
+    current: Setting,
+    // the best setting measured, with its score
+    best: Option<(Setting, f64, Measured)>,
+    tried: Vec<Setting>,
+    tuned: bool,
+    since: Instant,
+    // (nonces, hw errors) when `current` was set
+    base: (u64, u64),
+}
+
+// steps the boards hashing to the best setting for the target
+pub struct Tuner {
+    config: Tune,
+    boards: BTreeMap<u16, BoardTuner>,
+}
+
+impl Profile {
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let profile = fs::read_to_string(path)?;
+        serde_json::from_str(&profile).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    // written aside first, a profile half written would be lost at boot
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let path = path.as_ref();
+        let profile = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, profile)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+impl Measured {
+    fn new(nonces: u64, hw_errors: u64, elapsed: Duration) -> Self {
+        Self {
+            hashrate: hashrate(nonces, elapsed),
+            hw_error_rate: error_rate(hw_errors, nonces),
+        }
+    }
+
+    // the estimated joules per terahash
+    pub fn efficiency(&self, config: &Tune, setting: &Setting) -> f64 {
+        let ths = self.hashrate / 1e12;
+        let watts = config.static_power + config.power_coefficient * setting.voltage.powi(2) * ths;
+        watts / ths
+    }
+
+    // higher is better, None if the setting can't be used
+    pub fn score(&self, config: &Tune, setting: &Setting) -> Option<f64> {
+        if self.hashrate <= 0.0 || self.hw_error_rate > config.max_hw_error_rate {
+            return None;
+        }
+        match config.target {
+            TuneTarget::Hashrate => Some(self.hashrate * (1.0 - self.hw_error_rate)),
+            TuneTarget::Efficiency => Some(-self.efficiency(config, setting)),
+        }
+    }
+}
+
+// the voltages are kept to hundredths to be compared, the figures of the profile to be read
+fn hundredths(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+fn board_counts(stats: &HashStats, board: u16) -> (u64, u64) {
+    stats
+        .boards
+        .get(&(board as u8))
+        .map_or((0, 0), |x| (x.nonces(), x.hw_errors()))
+}
+
+impl Tuner {
+    pub fn new(config: Tune) -> Self {
+        Self {
+            config,
+            boards: BTreeMap::new(),
+        }
+    }
+
+    // a board hashing at `setting`, it is tuned from there
+    pub fn add(&mut self, board: u16, setting: Setting, stats: &HashStats, now: Instant) {
+        let setting = Setting {
+            voltage: hundredths(setting.voltage),
+            ..setting
+        };
+        let base = board_counts(stats, board);
+        self.boards.insert(
+            board,
+            BoardTuner {
+                current: setting,
+                best: None,
+                tried: vec![setting],
+                tuned: false,
+                since: now,
+                base,
+            },
+        );
+    }
+
+    // a board tuned before, it is kept at its setting
+    pub fn add_tuned(
+        &mut self,
+        board: u16,
+        profile: &BoardProfile,
+        stats: &HashStats,
+        now: Instant,
+    ) {
+        self.add(board, profile.setting, stats, now);
+        let measured = Measured {
+            hashrate: profile.hashrate * 1e9,
+            hw_error_rate: 0.0,
+        };
+        let tuner = self.boards.get_mut(&board).unwrap();
+        tuner.tuned = true;
+        tuner.best = Some((profile.setting, 0.0, measured));
+    }
+
+    // the board stopped hashing
+    pub fn remove(&mut self, board: u16) {
+        self.boards.remove(&board);
+    }
+
+    pub fn is_tuned(&self, board: u16) -> bool {
+        self.boards.get(&board).is_some_and(|x| x.tuned)
+    }
+
+    // the settings next to `setting` within the limits, the voltage is raised only with
+    // the frequency and lowered with it or alone
+    fn neighbours(&self, setting: Setting) -> Vec<Setting> {
+        let c = &self.config;
+        let (voltage, param) = (setting.voltage, setting.param);
+        let (higher, lower) = (voltage + c.voltage_step, voltage - c.voltage_step);
+        let (faster, slower) = (param + c.param_step, param.saturating_sub(c.param_step));
+        let neighbours = [
+            (voltage, faster),
+            (lower, param),
+            (voltage, slower),
+            (higher, faster),
+            (lower, slower),
+        ];
+        neighbours
+            .iter()
+            .map(|(voltage, param)| Setting {
+                voltage: hundredths(*voltage),
+                param: *param,
+            })
+            .filter(|x| x.param >= c.min_param && x.param <= c.max_param)
+            .filter(|x| x.voltage >= c.min_voltage && x.voltage <= c.max_voltage)
+            .collect()
+    }
+
+    // measure the boards `hashing` whose setting has run for the interval, returns what to
+    // change, the others (e.g. overheated) are measured anew once they hash again
+    pub fn update(&mut self, stats: &HashStats, hashing: &[u16], now: Instant) -> Vec<TuneAction> {
+        for (id, tuner) in self.boards.iter_mut() {
+            if !hashing.contains(id) {
+                tuner.since = now;
+                tuner.base = board_counts(stats, *id);
+            }
+        }
+
+        let interval = Duration::from_secs_f64(self.config.interval);
+        let ids: Vec<_> = self
+            .boards
+            .iter()
+            .filter(|(id, x)| hashing.contains(id) && now.duration_since(x.since) >= interval)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut actions = Vec::new();
+        for id in ids {
+            let counts = board_counts(stats, id);
+            let tuner = &self.boards[&id];
+            let measured = Measured::new(
+                counts.0.saturating_sub(tuner.base.0),
+                counts.1.saturating_sub(tuner.base.1),
+                now.duration_since(tuner.since),
+            );
+            if !tuner.tuned {
+                actions.extend(self.step(id, measured));
+            }
+
+            let tuner = self.boards.get_mut(&id).unwrap();
+            tuner.since = now;
+            tuner.base = counts;
+        }
+        actions
+    }
+
+    // keep the setting measured if it is the best, then try the next
+    fn step(&mut self, id: u16, measured: Measured) -> Vec<TuneAction> {
+        let current = self.boards[&id].current;
+        let score = measured.score(&self.config, &current);
+        info!(
+            "=> tune board {}: {:?}, {:.2} GH/s, hw errors: {:.2}%!",
+            id,
+            current,
+            measured.hashrate / 1e9,
+            measured.hw_error_rate * 100.0
+        );
+
+        let best = self.boards[&id].best;
+        let better = match (score, best) {
+            (Some(score), Some((_, best, _))) => score > best,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if better {
+            self.boards.get_mut(&id).unwrap().best = Some((current, score.unwrap(), measured));
+        }
+
+        // around the best, or slower and safer until there is one
+        let next = match self.boards[&id].best {
+            Some((best, _, _)) => self.neighbours(best),
+            None => vec![Setting {
+                param: current.param.saturating_sub(self.config.param_step),
+                ..current
+            }]
+            .into_iter()
+            .filter(|x| x.param >= self.config.min_param)
+            .collect(),
+        };
+        let tuner = self.boards.get_mut(&id).unwrap();
+        match next.into_iter().find(|x| !tuner.tried.contains(x)) {
+            Some(setting) => {
+                tuner.tried.push(setting);
+                tuner.current = setting;
+                vec![TuneAction::Set { board: id, setting }]
+            }
+            None => {
+                tuner.tuned = true;
+                let setting = tuner.best.map_or(current, |x| x.0);
+                tuner.current = setting;
+                info!("=> board {} tuned: {:?}!", id, setting);
+                let mut actions = Vec::new();
+                if setting != current {
+                    actions.push(TuneAction::Set { board: id, setting });
+                }
+                actions.push(TuneAction::Tuned { board: id, setting });
+                actions
+            }
+        }
+    }
+
+    // the best settings of the boards tuned, to be loaded at boot
+    pub fn profile(&self) -> Profile {
+        let boards = self
+            .boards
+            .iter()
+            .filter(|(_, x)| x.tuned)
+            .map(|(id, x)| {
+                let (setting, measured) = match x.best {
+                    Some((setting, _, measured)) => (setting, Some(measured)),
+                    None => (x.current, None),
+                };
+                let profile = BoardProfile {
+                    setting,
+                    hashrate: measured.map_or(0.0, |x| hundredths(x.hashrate / 1e9)),
+                    efficiency: measured
+                        .map_or(0.0, |x| hundredths(x.efficiency(&self.config, &setting))),
+                };
+                (*id, profile)
+            })
+            .collect();
+        Profile {
+            target: self.config.target,
+            boards,
+        }
+    }
+}
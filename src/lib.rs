@@ -3,6 +3,8 @@
 #[macro_use]
 extern crate log;
 
+pub mod backend;
+pub mod board;
 pub mod stratum;
 pub mod util;
 pub mod work;
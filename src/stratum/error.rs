@@ -0,0 +1,60 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    Connect(io::Error),
+    Io(io::Error),
+    AuthRejected(String),
+    Protocol(String),
+    Timeout,
+    ChannelClosed,
+    Disconnected,
+    // a request of the pool not understood, answered with an error while the session goes on
+    UnknownMethod(String),
+    // the config can not work, fixed only by editing it
+    Config(String),
+    // asked by the pool to reconnect to the address after the delay
+    Reconnect(String, Duration),
+}
+
+impl Error {
+    // errors that will not go away by reconnecting to the same pool
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::AuthRejected(_) | Error::Config(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "connect failed: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::AuthRejected(e) => write!(f, "authorize rejected: {}", e),
+            Error::Protocol(e) => write!(f, "protocol violation: {}", e),
+            Error::Timeout => write!(f, "connection timeout"),
+            Error::ChannelClosed => write!(f, "channel closed"),
+            Error::Disconnected => write!(f, "disconnected by pool"),
+            Error::UnknownMethod(e) => write!(f, "unknown method: {}", e),
+            Error::Config(e) => write!(f, "bad config: {}", e),
+            Error::Reconnect(addr, wait) => write!(f, "reconnect to {} in {:?}", addr, wait),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Connect(e) | Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
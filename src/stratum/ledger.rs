@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// json-rpc ids below are used by the handshake
+const FIRST_ID: u64 = 4;
+// shares without result after this are counted as lost
+const EXPIRE: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+pub struct Share {
+    pub pool: usize,
+    pub job_id: String,
+    pub nonce: u32,
+    pub version_bits: u32,
+    // difficulty of the share itself and the one the pool asked for
+    pub difficulty: f64,
+    pub pool_difficulty: f64,
+    // meets the network target
+    pub block: bool,
+    pub submit_time: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShareResult {
+    Accepted,
+    Rejected(String),
+    Stale(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ShareStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    // the stale shares found locally and not submitted
+    pub local_stale: u64,
+    pub lost: u64,
+    // sum of the pool difficulty of the accepted shares
+    pub accepted_diff: f64,
+    // reject & stale reason -> count
+    pub reasons: HashMap<String, u64>,
+    // sum of the round-trip latency of the answered shares
+    pub latency: Duration,
+}
+
+#[derive(Debug)]
+pub struct Ledger {
+    next_id: u64,
+    pending: HashMap<u64, Share>,
+    stats: ShareStats,
+}
+
+impl Share {
+    pub(super) fn log_result(&self, result: &str, latency: Duration) {
+        if self.block {
+            warn!(
+                "=> submitted block 0x{:08x} {} (latency: {:?})!",
+                self.nonce, result, latency
+            );
+        } else {
+            info!(
+                "=> submitted nonce 0x{:08x} {} (latency: {:?})!",
+                self.nonce, result, latency
+            );
+        }
+    }
+}
+
+impl ShareResult {
+    // stratum error 21 is "job not found", the pools word stale shares differently
+    pub fn rejected(code: Option<i64>, reason: &str) -> Self {
+        let lowercase = reason.to_lowercase();
+        if code == Some(21) || lowercase.contains("stale") || lowercase.contains("job not found") {
+            ShareResult::Stale(reason.to_string())
+        } else {
+            ShareResult::Rejected(reason.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ShareResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShareResult::Accepted => write!(f, "accepted"),
+            ShareResult::Rejected(reason) => write!(f, "rejected: {}", reason),
+            ShareResult::Stale(reason) => write!(f, "stale: {}", reason),
+        }
+    }
+}
+
+impl ShareStats {
+    pub fn answered(&self) -> u64 {
+        self.accepted + self.rejected + self.stale - self.local_stale
+    }
+
+    pub fn average_latency(&self) -> Option<Duration> {
+        match self.answered() {
+            0 => None,
+            n => Some(self.latency.div_f64(n as f64)),
+        }
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            next_id: FIRST_ID,
+            pending: HashMap::new(),
+            stats: ShareStats::default(),
+        }
+    }
+}
+
+impl Ledger {
+    // record the share, returns the json-rpc id to submit it with
+    pub fn submit(&mut self, share: Share) -> u64 {
+        self.expire(share.submit_time);
+
+        let id = self.next_id;
+        self.next_id = self.next_id.checked_add(1).unwrap_or(FIRST_ID);
+        self.pending.insert(id, share);
+        id
+    }
+
+    // the share of `id` and its round-trip latency, None if `id` is not pending
+    pub fn result(&mut self, id: u64, result: ShareResult) -> Option<(Share, Duration)> {
+        let share = self.pending.remove(&id)?;
+        let latency = share.submit_time.elapsed();
+
+        let stats = &mut self.stats;
+        stats.latency += latency;
+        match result {
+            ShareResult::Accepted => {
+                stats.accepted += 1;
+                stats.accepted_diff += share.pool_difficulty;
+            }
+            ShareResult::Rejected(reason) => {
+                stats.rejected += 1;
+                *stats.reasons.entry(reason).or_insert(0) += 1;
+            }
+            ShareResult::Stale(reason) => {
+                stats.stale += 1;
+                *stats.reasons.entry(reason).or_insert(0) += 1;
+            }
+        }
+        Some((share, latency))
+    }
+
+    // a share of a superseded job, counted without being submitted
+    pub fn stale(&mut self, share: &Share) {
+        debug!("stale nonce 0x{:08x} (job: {})", share.nonce, share.job_id);
+        self.stats.stale += 1;
+        self.stats.local_stale += 1;
+    }
+
+    // the connection is gone, the pending shares will never be answered
+    pub fn lose_pending(&mut self) {
+        for (_, share) in self.pending.drain() {
+            warn!("submitted nonce 0x{:08x} lost!", share.nonce);
+            self.stats.lost += 1;
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let lost = &mut self.stats.lost;
+        self.pending.retain(|_, share| {
+            let keep = now.duration_since(share.submit_time) < EXPIRE;
+            if !keep {
+                warn!("submitted nonce 0x{:08x} lost!", share.nonce);
+                *lost += 1;
+            }
+            keep
+        });
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn stats(&self) -> ShareStats {
+        self.stats.clone()
+    }
+}
@@ -9,14 +9,14 @@ use super::*;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Action<'a> {
-    pub id: Option<u8>,
+    pub id: Option<u64>,
     pub method: &'a str,
     pub params: Params,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Respond {
-    pub id: Option<u8>,
+    pub id: Option<u64>,
     pub result: ResultOf,
     pub error: JsonValue,
 }
@@ -29,6 +29,11 @@ pub enum Params {
     Work(Work),
     Bool(bool),
     Num([f64; 1]),
+    #[serde(skip_serializing)]
+    Xnonce(
+        #[serde(deserialize_with = "hex_to::bytes")] Bytes, // xnonce1
+        usize,                                              // xnonce2_size
+    ),
     #[serde(skip_serializing, deserialize_with = "hex_to::u32_vec")]
     TMask(Vec<u32>),
     #[serde(skip_deserializing)]
@@ -41,6 +46,8 @@ pub enum Params {
     Submit2([String; 6]), // with version_bits
     String([String; 1]),
     None([(); 0]),
+    #[serde(skip_serializing)]
+    Any(Vec<JsonValue>),
 }
 
 #[derive(Deserialize, Debug)]
@@ -1,35 +1,46 @@
-use std::net::TcpStream as StdTcpStream;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use bytes::Bytes;
-use futures::future::{err, ok};
-use futures::stream::Stream;
+use futures::future::{err, Either};
+use futures::stream::{iter_ok, Stream};
 use futures::sync::mpsc::{channel, Receiver, Sender};
 use futures::{Async::*, Future, Poll};
 use serde_json::json;
 use tokio::codec::{Decoder, LinesCodec};
 use tokio::net::TcpStream;
 use tokio::prelude::*;
-use tokio::reactor::Handle;
 
 use super::util::{Client, Notify, SinkHook};
 use super::work::*;
 
+pub use self::error::Error;
+pub use self::ledger::*;
 pub use self::message::*;
-use crate::util::Config;
+use crate::util::{Config, Protocol};
 
 mod checker;
+mod error;
+mod ledger;
 mod message;
 mod reader;
+mod reconnect;
+#[cfg(test)]
+mod tests;
+pub mod v2;
+
+use self::reconnect::reconnect_addr;
+pub use self::reconnect::Backoff;
 
 #[derive(Debug)]
 pub struct WorkStream(pub Receiver<Work>);
 
 impl Stream for WorkStream {
     type Item = Work;
-    type Error = ();
+    type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         let mut item: Async<Option<Self::Item>> = NotReady;
@@ -43,22 +54,40 @@ impl Stream for WorkStream {
                     item = Async::Ready(w);
                 }
                 Ok(NotReady) => return Ok(item),
-                Err(_) => return Err(()),
+                Err(_) => return Err(Error::ChannelClosed),
             }
         }
     }
 }
 
+// the receiving end of `Pool::sender`, outlives the connections
+#[derive(Clone)]
+struct Outbox(Arc<Mutex<Receiver<String>>>);
+
+impl Stream for Outbox {
+    type Item = String;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.0.lock().unwrap().poll()
+    }
+}
+
 pub struct Pool {
     addr: String,
     reader: Option<Receiver<String>>,
-    writer: Option<Sender<String>>,
+    writer: Sender<String>,
+    outbox: Outbox,
+    pub session: Arc<AtomicUsize>,
     pub connected: Arc<AtomicBool>,
     pub authorized: (Option<String>, Arc<AtomicBool>),
     pub xnonce: Arc<Mutex<(Bytes, usize)>>,
-    pub submitted_nonce: Arc<Mutex<[Option<u32>; 8]>>,
+    pub ledger: Arc<Mutex<Ledger>>,
     pub work_channel: (Sender<Work>, Option<Receiver<Work>>),
     pub work_notify: Notify,
+    // the jobs of the session the results can still be submitted for, kept up to date by the
+    // reader whether the pool is hashed for or not
+    pub jobs: Arc<Mutex<Jobs>>,
     pub vermask: Arc<Mutex<Option<u32>>>,
     pub diff: Arc<Mutex<f64>>,
     pub last_active: Arc<Mutex<Instant>>,
@@ -67,16 +96,20 @@ pub struct Pool {
 impl Pool {
     pub fn new(addr: &str) -> Self {
         let work_channel = channel(4);
+        let (writer, outbox) = channel(16);
         Self {
             addr: String::from(addr),
             reader: None,
-            writer: None,
+            writer,
+            outbox: Outbox(Arc::new(Mutex::new(outbox))),
+            session: Arc::new(AtomicUsize::new(0)),
             connected: Arc::new(AtomicBool::new(false)),
             authorized: (None, Arc::new(AtomicBool::new(false))),
             xnonce: Arc::new(Mutex::new((Bytes::new(), 0))),
-            submitted_nonce: Arc::new(Mutex::new([None; 8])),
+            ledger: Arc::new(Mutex::new(Ledger::default())),
             work_channel: (work_channel.0, Some(work_channel.1)),
             work_notify: Notify::default(),
+            jobs: Arc::default(),
             vermask: Arc::new(Mutex::new(None)),
             diff: Arc::new(Mutex::new(1.0)),
             last_active: Arc::new(Mutex::new(Instant::now())),
@@ -87,37 +120,66 @@ impl Pool {
         &mut self,
         config: &Config,
         pool: usize,
-    ) -> impl Future<Item = (), Error = ()> + Send {
-        let connect =
-            || TcpStream::from_std(StdTcpStream::connect(&self.addr)?, &Handle::default());
-
-        let tcpstream = match connect() {
-            Ok(tcpstream) => {
-                tcpstream
-                    .set_nodelay(true)
-                    .unwrap_or_else(|e| warn!("set_nodelay err: {:?}!", e));
-                ok(tcpstream)
-            }
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        self.session.fetch_add(1, Ordering::SeqCst);
+        self.authorized.1.store(false, Ordering::SeqCst);
+        *self.last_active.lock().unwrap() = Instant::now();
+
+        let addr = self.addr.to_socket_addrs().and_then(|mut addrs| {
+            addrs.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::AddrNotAvailable, "no address resolved")
+            })
+        });
+
+        let tcpstream = match addr {
+            Ok(addr) => Either::A(
+                TcpStream::connect(&addr)
+                    .map(|tcpstream| {
+                        tcpstream
+                            .set_nodelay(true)
+                            .unwrap_or_else(|e| warn!("set_nodelay err: {:?}!", e));
+                        tcpstream
+                    })
+                    .map_err(|e| {
+                        error!("tcp connect err: {:?}!", e);
+                        Error::Connect(e)
+                    }),
+            ),
             Err(e) => {
-                error!("tcp connect err: {:?}!", e);
-                err(())
+                error!("resolve {} err: {:?}!", self.addr, e);
+                Either::B(err(Error::Connect(e)))
             }
         };
 
+        match config.pool[pool].protocol {
+            Protocol::V1 => Either::A(self.connect_v1(config, pool, tcpstream)),
+            Protocol::V2 => Either::B(self.connect_v2(config, pool, tcpstream)),
+        }
+    }
+
+    fn connect_v1(
+        &mut self,
+        config: &Config,
+        pool: usize,
+        tcpstream: impl Future<Item = TcpStream, Error = Error> + Send,
+    ) -> impl Future<Item = (), Error = Error> + Send {
         let (reader_tx, reader_rx) = channel::<String>(16);
         self.reader = Some(reader_rx);
 
-        let (writer_tx, writer_rx) = channel::<String>(16);
-        self.writer = Some(writer_tx);
-
-        self.configure(&config.client);
-        self.subscribe(&config.client.user_agent);
-        self.authorize(&config.pool[pool].user, &config.pool[pool].pass);
+        let mut handshake = vec![
+            self.configure(&config.client),
+            self.subscribe(&config.client.user_agent),
+            self.authorize(&config.pool[pool].user, &config.pool[pool].pass),
+        ];
+        if config.pool[pool].extranonce_subscribe {
+            handshake.push(self.extranonce_subscribe());
+        }
+        let writer_rx = iter_ok(handshake).chain(self.outbox.clone());
 
         let connected = self.connected.clone();
 
         let last_active = self.last_active.clone();
-        let read_line = self.reader();
+        let read_line = self.reader(config, pool);
 
         tcpstream.and_then(move |tcpstream| {
             connected.store(true, Ordering::SeqCst);
@@ -128,15 +190,28 @@ impl Pool {
                     debug!("recv: {}", line);
                     *last_active.lock().unwrap() = Instant::now();
                 })
-                .map_err(|e| error!("recv from pool err: {:?}", e))
-                .forward(reader_tx.sink_map_err(|e| error!("send data to channel err: {:?}", e)));
-            let reader = reader.select2(read_line);
-
-            let writer = writer_rx.inspect(|line| debug!("send: {}", line)).forward(
-                SinkHook::new(sink, || debug!("data sent!"))
-                    .sink_map_err(|e| error!("send to pool err: {:?}", e)),
-            );
-            reader.select2(writer).map(drop).map_err(drop)
+                .map_err(|e| {
+                    error!("recv from pool err: {:?}", e);
+                    Error::Io(e)
+                })
+                .forward(reader_tx.sink_map_err(|e| {
+                    error!("send data to channel err: {:?}", e);
+                    Error::ChannelClosed
+                }))
+                .and_then(|_| Err::<(), _>(Error::Disconnected));
+            let reader = reader.select(read_line).map(drop).map_err(|(e, _)| e);
+
+            let writer = writer_rx
+                .map_err(|_| Error::ChannelClosed)
+                .inspect(|line| debug!("send: {}", line))
+                .forward(
+                    SinkHook::new(sink, || debug!("data sent!")).sink_map_err(|e| {
+                        error!("send to pool err: {:?}", e);
+                        Error::Io(e)
+                    }),
+                )
+                .map(drop);
+            reader.select(writer).map(drop).map_err(|(e, _)| e)
         })
     }
 
@@ -145,7 +220,7 @@ impl Pool {
     }
 
     pub fn sender(&mut self) -> Sender<String> {
-        self.writer.clone().unwrap()
+        self.writer.clone()
     }
 
     pub fn receiver(&mut self) -> Receiver<String> {
@@ -158,7 +233,7 @@ impl Pool {
             .and_then(|_| Ok(()))
     }
 
-    pub fn subscribe(&mut self, ua: &Option<String>) {
+    pub fn subscribe(&mut self, ua: &Option<String>) -> String {
         let params = match ua {
             Some(ua) => Params::String([ua.to_string(); 1]),
             None => Params::None([]),
@@ -169,20 +244,29 @@ impl Pool {
             method: "mining.subscribe",
             params,
         };
-        let _ = self.send(&msg).wait();
+        serde_json::to_string(&msg).unwrap()
     }
 
-    pub fn authorize(&mut self, user: &str, pass: &str) {
+    pub fn authorize(&mut self, user: &str, pass: &str) -> String {
         self.authorized.0 = Some(user.to_string());
         let msg = Action {
             id: Some(2),
             method: "mining.authorize",
             params: Params::User([user.to_string(), pass.to_string()]),
         };
-        let _ = self.send(&msg).wait();
+        serde_json::to_string(&msg).unwrap()
     }
 
-    pub fn configure(&mut self, client: &Client) {
+    pub fn extranonce_subscribe(&mut self) -> String {
+        let msg = Action {
+            id: Some(3),
+            method: "mining.extranonce.subscribe",
+            params: Params::None([]),
+        };
+        serde_json::to_string(&msg).unwrap()
+    }
+
+    pub fn configure(&mut self, client: &Client) -> String {
         let exts = vec!["version-rolling"];
         let ext_params = json!({
             "version-rolling.mask": client.version_rolling.mask,
@@ -193,7 +277,6 @@ impl Pool {
             method: "mining.configure",
             params: Params::Config(exts, ext_params),
         };
-
-        let _ = self.send(&msg).wait();
+        serde_json::to_string(&msg).unwrap()
     }
 }
@@ -1,4 +1,6 @@
+use std::convert::TryFrom;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use serde_json::Value as JsonValue;
 
@@ -7,21 +9,36 @@ use crate::util::{hex_to, ToHex};
 use super::*;
 
 impl Pool {
-    pub(super) fn reader(&mut self) -> impl Future<Item = (), Error = ()> + Send {
+    pub(super) fn reader(
+        &mut self,
+        config: &Config,
+        pool: usize,
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        let addr = self.addr.clone();
+        let writer = self.writer.clone();
+        let user_agent = config.client.user_agent.clone().unwrap_or_else(|| {
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string()
+        });
         let authorized = self.authorized.1.clone();
         let xnonce = self.xnonce.clone();
-        let submitted_nonce = self.submitted_nonce.clone();
+        let ledger = self.ledger.clone();
         let work_sender = self.work_channel.0.clone();
         let work_notify = self.work_notify.clone();
+        let jobs = self.jobs.clone();
+        let session = self.session.clone();
         let vermask = self.vermask.clone();
         let diff = self.diff.clone();
+        let mut extranonce_pending = config.pool[pool].extranonce_subscribe;
 
         #[allow(clippy::cognitive_complexity)]
-        self.receiver().for_each(move |line| {
+        let receiver = self.receiver().map_err(|_| Error::ChannelClosed);
+        receiver.for_each(move |line| {
             if let Ok(s) = serde_json::from_str::<Action>(&line) {
                 match s.params {
                     Params::Work(w) => {
                         info!("=> received new work!");
+                        let current = session.load(Ordering::SeqCst);
+                        jobs.lock().unwrap().update(current, &w);
                         let work_notify = work_notify.clone();
                         tokio::spawn(work_sender.clone().send(w).then(move |_| {
                             work_notify.notify();
@@ -32,6 +49,18 @@ impl Pool {
                         info!("=> set difficulty: {}!", &n);
                         *diff.lock().unwrap() = n;
                     }
+                    Params::Xnonce(xnonce1, xnonce2_size)
+                        if s.method == "mining.set_extranonce" =>
+                    {
+                        info!(
+                            "=> set xnonce1: 0x{}, xnonce2_size: {}!",
+                            xnonce1.to_hex(),
+                            xnonce2_size
+                        );
+                        *xnonce.lock().unwrap() = (xnonce1, xnonce2_size);
+                        // the current subwork is built on the old xnonce, drop it
+                        work_notify.notify();
+                    }
                     Params::TMask(mask) if s.method == "mining.set_version_mask" => {
                         if mask.len() == 1 {
                             let mask = mask[0];
@@ -41,13 +70,32 @@ impl Pool {
                             warn!("=> unknown vermask: {:?}!", mask);
                         }
                     }
+                    _ if s.method == "client.reconnect" => {
+                        warn!("=> pool asks to reconnect: {}!", line);
+                        return Err(reconnect_to(&addr, s.params));
+                    }
+                    Params::String([message]) if s.method == "client.show_message" => {
+                        warn!("=> message from pool: {}", message);
+                    }
+                    _ if s.method == "client.get_version" => {
+                        info!("=> reply version: {}!", user_agent);
+                        let reply = json!({"id": s.id, "result": user_agent, "error": null});
+                        tokio::spawn(writer.clone().send(reply.to_string()).then(|_| Ok(())));
+                    }
+                    // a request we can not answer, tell the pool so and go on
+                    _ if s.id.is_some() => {
+                        let e = Error::UnknownMethod(s.method.to_string());
+                        warn!("=> {}: {}!", e, line);
+                        let error = json!([-32601, "Method not found", null]);
+                        let reply = json!({"id": s.id, "result": null, "error": error});
+                        tokio::spawn(writer.clone().send(reply.to_string()).then(|_| Ok(())));
+                    }
                     _ => warn!("=> unknown method: {}!", line),
                 }
             } else if let Ok(s) = serde_json::from_str::<Respond>(&line) {
                 match s.result {
                     ResultOf::Authorize(r) => {
                         let result = r.unwrap_or(false);
-                        let mut nonce = 0;
 
                         match s.id {
                             Some(2) if !authorized.load(Ordering::SeqCst) => {
@@ -56,26 +104,32 @@ impl Pool {
                                     info!("=> authorized successfully!");
                                 } else {
                                     info!("=> authorized failed!");
-                                    return Err(());
+                                    let reason = s.error.get(1).and_then(JsonValue::as_str);
+                                    return Err(Error::AuthRejected(
+                                        reason.unwrap_or("unknown reason").to_string(),
+                                    ));
                                 }
                             }
-                            Some(id)
-                                if submitted_nonce.lock().unwrap()[(id & 7) as usize]
-                                    .take()
-                                    .map(|x| nonce = x)
-                                    .is_some() =>
-                            {
+                            Some(3) if extranonce_pending => {
+                                extranonce_pending = false;
                                 if result {
-                                    info!("=> submitted nonce 0x{:08x} accepted!", nonce);
-                                } else if let Some(Some(reason)) =
-                                    s.error.get(1).map(JsonValue::as_str)
-                                {
-                                    info!(
-                                        "=> submitted nonce 0x{:08x} rejected: {}!",
-                                        nonce, reason
-                                    );
+                                    info!("=> subscribed to extranonce!");
+                                } else {
+                                    info!("=> the pool does not support extranonce subscription!");
+                                }
+                            }
+                            Some(id) => {
+                                let result = if result {
+                                    ShareResult::Accepted
                                 } else {
-                                    info!("=> submitted nonce 0x{:08x} rejected!", nonce);
+                                    let code = s.error.get(0).and_then(JsonValue::as_i64);
+                                    let reason = s.error.get(1).and_then(JsonValue::as_str);
+                                    ShareResult::rejected(code, reason.unwrap_or("unknown reason"))
+                                };
+                                let message = result.to_string();
+                                match ledger.lock().unwrap().result(id, result) {
+                                    Some((share, latency)) => share.log_result(&message, latency),
+                                    None => warn!("unknown respond: {}!", line),
                                 }
                             }
                             _ => warn!("unknown respond: {}!", line),
@@ -95,8 +149,12 @@ impl Pool {
                         if let Some(result) = r.get("version-rolling") {
                             if let serde_json::Value::Bool(result) = result {
                                 if *result {
-                                    let mask = hex_to::u32(r.get("version-rolling.mask").unwrap())
-                                        .unwrap();
+                                    let mask = r
+                                        .get("version-rolling.mask")
+                                        .and_then(|x| hex_to::u32(x).ok())
+                                        .ok_or_else(|| {
+                                            Error::Protocol(format!("bad configure: {}", line))
+                                        })?;
                                     info!("=> set vermask: 0x{}!", mask.to_be_bytes().to_hex());
                                     *vermask.lock().unwrap() = Some(mask);
                                 } else {
@@ -108,8 +166,30 @@ impl Pool {
                         }
                     }
                 }
+            } else {
+                warn!("=> unknown message: {}!", line);
             }
             Ok(())
         })
     }
 }
+
+// client.reconnect("host", port, wait), all optional, numbers may come as strings
+fn reconnect_to(addr: &str, params: Params) -> Error {
+    let params = match params {
+        Params::Any(params) => params,
+        Params::String([host]) => vec![JsonValue::from(host)],
+        _ => Vec::new(),
+    };
+    let number = |x: Option<&JsonValue>| {
+        x.and_then(|x| {
+            x.as_u64()
+                .or_else(|| x.as_str().and_then(|x| x.parse().ok()))
+        })
+    };
+
+    let host = params.first().and_then(JsonValue::as_str);
+    let port = number(params.get(1)).and_then(|x| u16::try_from(x).ok());
+    let wait = number(params.get(2)).unwrap_or(0);
+    Error::Reconnect(reconnect_addr(addr, host, port), Duration::from_secs(wait))
+}
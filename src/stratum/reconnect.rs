@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::future::{loop_fn, Loop};
+use tokio::timer::Delay;
+
+use crate::util::Reconnect;
+
+use super::*;
+
+pub struct Backoff {
+    config: Reconnect,
+    attempt: i32,
+}
+
+impl Backoff {
+    pub fn new(config: &Reconnect) -> Self {
+        Self {
+            config: config.clone(),
+            attempt: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let config = &self.config;
+        let delay =
+            (config.initial_delay * config.multiplier.powi(self.attempt)).min(config.max_delay);
+        self.attempt = self.attempt.saturating_add(1);
+
+        // no rng dependency, the clock is random enough to spread the rigs
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.subsec_nanos())
+            .unwrap_or(0);
+        let random = f64::from(nanos) / 1e9 * 2.0 - 1.0;
+
+        Duration::from_secs_f64((delay * (1.0 + config.jitter * random)).max(0.0))
+    }
+}
+
+// host and port of "host:port"
+fn split_addr(addr: &str) -> (&str, &str) {
+    match addr.rfind(':') {
+        Some(i) => (&addr[..i], &addr[i + 1..]),
+        None => (addr, ""),
+    }
+}
+
+// the address a reconnect request points to, missing parts are taken from `addr`
+pub(super) fn reconnect_addr(addr: &str, host: Option<&str>, port: Option<u16>) -> String {
+    let (current_host, current_port) = split_addr(addr);
+    let host = host.filter(|x| !x.is_empty()).unwrap_or(current_host);
+    match port.filter(|&x| x != 0) {
+        Some(port) => format!("{}:{}", host, port),
+        None => format!("{}:{}", host, current_port),
+    }
+}
+
+impl Pool {
+    // keep the pool connected, only fatal errors are returned
+    pub fn run(self, config: Config, pool: usize) -> impl Future<Item = (), Error = Error> + Send {
+        let backoff = Backoff::new(&config.client.reconnect);
+        let timeout = Duration::from_secs_f64(config.client.reconnect.idle_timeout);
+        let max_wait = Duration::from_secs_f64(config.client.reconnect.max_wait);
+        let any_host = config.client.reconnect.any_host;
+        // the configured address, gone back to when a redirect does not work out
+        let home = self.addr.clone();
+
+        loop_fn(
+            (self, config, backoff),
+            move |(mut this, config, mut backoff)| {
+                let home = home.clone();
+                this.connect(&config, pool)
+                    .select(this.checker(timeout))
+                    .map(drop)
+                    .map_err(|(e, _)| e)
+                    .then(move |result| {
+                        let e = result.err().unwrap_or(Error::Disconnected);
+                        this.connected.store(false, Ordering::SeqCst);
+                        this.ledger.lock().unwrap().lose_pending();
+                        // stop hashing the work of the lost session
+                        this.work_notify.notify();
+
+                        if e.is_fatal() {
+                            return Either::A(err(e));
+                        }
+
+                        let delay = match e {
+                            // pool migration or maintenance, not a failure
+                            Error::Reconnect(addr, wait) => {
+                                let wait = wait.min(max_wait);
+                                if any_host || split_addr(&addr).0 == split_addr(&home).0 {
+                                    info!("=> pool {} reconnect to {} in {:?}!", pool, addr, wait);
+                                    this.addr = addr;
+                                } else {
+                                    warn!("=> pool {} redirect to {} refused!", pool, addr);
+                                }
+                                backoff.reset();
+                                wait
+                            }
+                            e if this.addr != home => {
+                                warn!(
+                                    "pool {} lost at {}: {}, back to {}!",
+                                    pool, this.addr, e, home
+                                );
+                                this.addr = home.clone();
+                                backoff.next_delay()
+                            }
+                            e => {
+                                if this.authorized.1.load(Ordering::SeqCst) {
+                                    backoff.reset();
+                                }
+                                let delay = backoff.next_delay();
+                                warn!("pool {} lost: {}, reconnect in {:?}!", pool, e, delay);
+                                delay
+                            }
+                        };
+                        Either::B(
+                            Delay::new(Instant::now() + delay)
+                                .map_err(|e| {
+                                    error!("reconnect delay err: {:?}", e);
+                                    Error::Timeout
+                                })
+                                .map(move |_| Loop::Continue((this, config, backoff))),
+                        )
+                    })
+            },
+        )
+    }
+}
@@ -0,0 +1,428 @@
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use futures::future::{empty, Either};
+use tokio::net::TcpListener;
+use tokio::runtime::{current_thread, Runtime};
+
+use crate::util::{Reconnect, ToHex};
+
+use super::*;
+
+const SUBSCRIBE: &str = r#"{"id":1,"result":[[["mining.set_difficulty","1"],["mining.notify","1"]],"72e03131",8],"error":null}"#;
+const AUTHORIZE: &str = r#"{"id":2,"result":true,"error":null}"#;
+const EXTRANONCE_SUBSCRIBE: &str = r#"{"id":3,"result":true,"error":null}"#;
+const SET_EXTRANONCE: &str =
+    r#"{"id":null,"method":"mining.set_extranonce","params":["08000002",4]}"#;
+
+fn config(addr: &SocketAddr, idle_timeout: f64) -> Config {
+    toml::from_str(&format!(
+        r#"
+        [client]
+        user-agent = "stratum/test"
+
+        [client.version-rolling]
+        mask = "1fffe000"
+
+        [client.reconnect]
+        initial-delay = 0.01
+        max-delay = 0.1
+        idle-timeout = {}
+
+        [board]
+        enabled = []
+        default = {{}}
+
+        [[pool]]
+        addr = "{}"
+        user = "user.0"
+        pass = ""
+        "#,
+        idle_timeout, addr
+    ))
+    .unwrap()
+}
+
+// answers subscribe & authorize (followed by `extra`), then drops the connection or goes silent
+fn mock_server(silent: bool, extra: Vec<String>) -> (SocketAddr, mpsc::Receiver<(usize, String)>) {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (report, received) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut session = 0;
+        let server = listener
+            .incoming()
+            .for_each(move |socket| {
+                let n = session;
+                session += 1;
+
+                let report = report.clone();
+                let extra = extra.clone();
+                let (sink, stream) = LinesCodec::new().framed(socket).split();
+                let task = stream
+                    .inspect(move |line| drop(report.send((n, line.clone()))))
+                    .skip_while(|line| Ok(!line.contains("mining.authorize")))
+                    .into_future()
+                    .map_err(|(e, _)| e)
+                    .and_then(move |(_, stream)| {
+                        let replies = [SUBSCRIBE, AUTHORIZE]
+                            .iter()
+                            .map(|x| x.to_string())
+                            .chain(extra)
+                            .collect::<Vec<_>>();
+                        sink.send_all(iter_ok::<_, io::Error>(replies))
+                            .map(|_| stream)
+                    })
+                    .and_then(move |stream| {
+                        if silent {
+                            Either::A(stream.for_each(|_| Ok(())).and_then(|_| empty()))
+                        } else {
+                            Either::B(Ok(()).into_future())
+                        }
+                    });
+                current_thread::spawn(task.map_err(drop));
+                Ok(())
+            })
+            .map_err(drop);
+        let _ = current_thread::Runtime::new().unwrap().block_on(server);
+    });
+
+    (addr, received)
+}
+
+fn wait_session(
+    received: &mpsc::Receiver<(usize, String)>,
+    session: usize,
+    count: usize,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let (n, line) = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        if n == session {
+            lines.push(line);
+            if lines.len() == count {
+                return lines;
+            }
+        }
+    }
+}
+
+fn reconnect(silent: bool) {
+    let (addr, received) = mock_server(silent, Vec::new());
+    let config = config(&addr, if silent { 0.3 } else { 60.0 });
+
+    let pool = Pool::new(&addr.to_string());
+    let session = pool.session.clone();
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(pool.run(config, 0).map_err(|e| panic!("{}", e)));
+
+    for n in 0..3 {
+        let lines = wait_session(&received, n, 3);
+        assert!(lines[0].contains("mining.configure"));
+        assert!(lines[1].contains("mining.subscribe"));
+        assert!(lines[2].contains("mining.authorize"));
+    }
+    assert!(session.load(Ordering::SeqCst) >= 3);
+}
+
+#[test]
+fn reconnect_after_drop() {
+    reconnect(false);
+}
+
+#[test]
+fn reconnect_after_silence() {
+    reconnect(true);
+}
+
+#[test]
+fn set_extranonce() {
+    let extra = vec![EXTRANONCE_SUBSCRIBE.to_string(), SET_EXTRANONCE.to_string()];
+    let (addr, received) = mock_server(true, extra);
+    let mut config = config(&addr, 60.0);
+    config.pool[0].extranonce_subscribe = true;
+
+    let pool = Pool::new(&addr.to_string());
+    let xnonce = pool.xnonce.clone();
+    let work_notify = pool.work_notify.clone();
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(pool.run(config, 0).map_err(|e| panic!("{}", e)));
+
+    let lines = wait_session(&received, 0, 4);
+    assert!(lines[3].contains("mining.extranonce.subscribe"));
+
+    for _ in 0..50 {
+        if xnonce.lock().unwrap().1 == 4 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let xnonce = xnonce.lock().unwrap().clone();
+    assert_eq!(xnonce.0.to_hex(), "08000002");
+    assert_eq!(xnonce.1, 4);
+    assert!(work_notify.notified());
+}
+
+#[test]
+fn jobs_of_idle_pool() {
+    let notify = |id: &str, clean: bool| {
+        format!(
+            r#"{{"id":null,"method":"mining.notify","params":["{}","{}","00","00",[],"20000000","17306835","5c501c2a",{}]}}"#,
+            id,
+            "00".repeat(32),
+            clean
+        )
+    };
+    let extra = vec![notify("1", true), notify("2", true)];
+    let (addr, received) = mock_server(true, extra);
+    let config = config(&addr, 60.0);
+
+    // the works are never taken, as for a pool not hashed for
+    let pool = Pool::new(&addr.to_string());
+    let jobs = pool.jobs.clone();
+    let session = pool.session.clone();
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(pool.run(config, 0).map_err(|e| panic!("{}", e)));
+
+    wait_session(&received, 0, 3);
+    for _ in 0..50 {
+        if jobs.lock().unwrap().is_valid(1, "2") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let jobs = jobs.lock().unwrap();
+    assert_eq!(session.load(Ordering::SeqCst), 1);
+    assert!(jobs.is_valid(1, "2"));
+    assert!(!jobs.is_valid(1, "1"));
+}
+
+#[test]
+fn get_version() {
+    let extra = vec![
+        r#"{"id":null,"method":"client.show_message","params":["maintenance"]}"#.to_string(),
+        r#"{"id":4,"method":"client.get_capabilities","params":[]}"#.to_string(),
+        r#"{"id":5,"method":"client.get_version","params":[]}"#.to_string(),
+    ];
+    let (addr, received) = mock_server(true, extra);
+    let config = config(&addr, 60.0);
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(
+        Pool::new(&addr.to_string())
+            .run(config, 0)
+            .map_err(|e| panic!("{}", e)),
+    );
+
+    // the unknown request is answered with an error, the session goes on
+    let lines = wait_session(&received, 0, 5);
+    assert_eq!(
+        lines[3],
+        r#"{"error":[-32601,"Method not found",null],"id":4,"result":null}"#
+    );
+    assert_eq!(lines[4], r#"{"error":null,"id":5,"result":"stratum/test"}"#);
+}
+
+#[test]
+fn bad_configure() {
+    // a mask that is not hex drops the session instead of the miner
+    let extra = vec![
+        r#"{"id":4,"result":{"version-rolling":true,"version-rolling.mask":"xyz"},"error":null}"#
+            .to_string(),
+    ];
+    let (addr, received) = mock_server(true, extra);
+    let config = config(&addr, 60.0);
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(
+        Pool::new(&addr.to_string())
+            .run(config, 0)
+            .map_err(|e| panic!("{}", e)),
+    );
+
+    wait_session(&received, 0, 3);
+    let lines = wait_session(&received, 1, 3);
+    assert!(lines[2].contains("mining.authorize"));
+}
+
+#[test]
+fn client_reconnect() {
+    let (addr1, received1) = mock_server(true, Vec::new());
+    let extra = vec![format!(
+        r#"{{"id":null,"method":"client.reconnect","params":["127.0.0.1","{}",0]}}"#,
+        addr1.port()
+    )];
+    let (addr0, received0) = mock_server(true, extra);
+    let config = config(&addr0, 60.0);
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(
+        Pool::new(&addr0.to_string())
+            .run(config, 0)
+            .map_err(|e| panic!("{}", e)),
+    );
+
+    wait_session(&received0, 0, 3);
+    let lines = wait_session(&received1, 0, 3);
+    assert!(lines[2].contains("mining.authorize"));
+    // the new address is kept
+    assert!(received0.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+// a pool sending client.reconnect, the runtime must be kept for the pool to run
+fn redirect(
+    host: &str,
+    port: u16,
+    wait: &str,
+    max_wait: f64,
+) -> (Runtime, mpsc::Receiver<(usize, String)>) {
+    let extra = vec![format!(
+        r#"{{"id":null,"method":"client.reconnect","params":["{}",{},{}]}}"#,
+        host, port, wait
+    )];
+    let (addr, received) = mock_server(true, extra);
+    let mut config = config(&addr, 60.0);
+    config.client.reconnect.max_wait = max_wait;
+
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(
+        Pool::new(&addr.to_string())
+            .run(config, 0)
+            .map_err(|e| panic!("{}", e)),
+    );
+    (runtime, received)
+}
+
+#[test]
+fn client_reconnect_bounded() {
+    // a wait that would overflow the deadline is cut to max-wait
+    let (addr1, received1) = mock_server(true, Vec::new());
+    let (_runtime, received0) = redirect("127.0.0.1", addr1.port(), "18446744073709551615", 0.0);
+    wait_session(&received0, 0, 3);
+    let lines = wait_session(&received1, 0, 3);
+    assert!(lines[2].contains("mining.authorize"));
+
+    // another host is not followed by default
+    let (addr1, received1) = mock_server(true, Vec::new());
+    let (_runtime, received0) = redirect("localhost", addr1.port(), "0", 300.0);
+    wait_session(&received0, 1, 3);
+    assert!(received1.recv_timeout(Duration::from_millis(300)).is_err());
+
+    // back to the configured address when the redirect does not work
+    let closed = TcpListener::bind(&"127.0.0.1:0".parse().unwrap())
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (_runtime, received0) = redirect("127.0.0.1", closed.port(), "0", 300.0);
+    let lines = wait_session(&received0, 1, 3);
+    assert!(lines[2].contains("mining.authorize"));
+}
+
+#[test]
+fn reconnect_addr() {
+    use super::reconnect_addr;
+
+    let addr = "pool.example.com:3333";
+    assert_eq!(reconnect_addr(addr, None, None), addr);
+    assert_eq!(reconnect_addr(addr, Some(""), Some(0)), addr);
+    assert_eq!(
+        reconnect_addr(addr, Some("eu.example.com"), None),
+        "eu.example.com:3333"
+    );
+    assert_eq!(
+        reconnect_addr(addr, Some("eu.example.com"), Some(443)),
+        "eu.example.com:443"
+    );
+}
+
+fn share(nonce: u32) -> Share {
+    Share {
+        pool: 0,
+        job_id: "1".to_string(),
+        nonce,
+        version_bits: 0,
+        difficulty: 2.0,
+        pool_difficulty: 1.0,
+        block: false,
+        submit_time: Instant::now(),
+    }
+}
+
+#[test]
+fn ledger() {
+    let mut ledger = Ledger::default();
+    let ids: Vec<_> = (0..20).map(|n| ledger.submit(share(n))).collect();
+    assert_eq!(ledger.pending(), 20);
+
+    // answered out of order, far more than 8 in flight
+    for (n, &id) in ids.iter().enumerate().rev() {
+        let result = match n % 4 {
+            0 => ShareResult::rejected(Some(23), "Low difficulty share"),
+            1 => ShareResult::rejected(Some(21), "Job not found"),
+            2 => ShareResult::rejected(None, "stale-share"),
+            _ => ShareResult::Accepted,
+        };
+        let (share, _) = ledger.result(id, result).unwrap();
+        assert_eq!(share.nonce, n as u32);
+    }
+    assert!(ledger.result(ids[0], ShareResult::Accepted).is_none());
+
+    ledger.submit(share(20));
+    ledger.lose_pending();
+
+    let stats = ledger.stats();
+    assert_eq!(
+        (stats.accepted, stats.rejected, stats.stale, stats.lost),
+        (5, 5, 10, 1)
+    );
+    assert_eq!(stats.accepted_diff, 5.0);
+    assert_eq!(stats.reasons["Job not found"], 5);
+    assert!(stats.average_latency().is_some());
+}
+
+#[test]
+fn average_latency() {
+    // more answers than a u32 holds, the count must not be truncated
+    let stats = ShareStats {
+        accepted: (1 << 32) + 1,
+        latency: Duration::from_secs(1 << 32),
+        ..ShareStats::default()
+    };
+    let latency = stats.average_latency().unwrap().as_secs_f64();
+    assert!((latency - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn backoff() {
+    let config = Reconnect {
+        initial_delay: 1.0,
+        max_delay: 10.0,
+        multiplier: 2.0,
+        jitter: 0.0,
+        ..Reconnect::default()
+    };
+    let mut backoff = Backoff::new(&config);
+
+    let delays: Vec<_> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+
+    let mut backoff = Backoff::new(&Reconnect {
+        jitter: 0.5,
+        ..config
+    });
+    for _ in 0..100 {
+        let delay = backoff.next_delay().as_secs_f64();
+        assert!(delay <= 15.0);
+        backoff.reset();
+        let delay = backoff.next_delay().as_secs_f64();
+        assert!((0.5..=1.5).contains(&delay));
+    }
+}
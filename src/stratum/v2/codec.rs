@@ -0,0 +1,94 @@
+use std::io::{Error, ErrorKind, Result};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use snow::TransportState;
+use tokio::codec::{Decoder, Encoder};
+
+use super::noise::{noise_err, MAX_MESSAGE_LEN};
+use super::Message;
+
+const HEADER_LEN: usize = 6;
+const TAG_LEN: usize = 16;
+
+// every sv2 frame travels in its own noise transport message:
+// [len: u16][encrypt(ext_type: u16, msg_type: u8, msg_length: u24, payload)]
+pub struct Codec {
+    noise: TransportState,
+}
+
+impl Codec {
+    pub fn new(noise: TransportState) -> Self {
+        Self { noise }
+    }
+}
+
+impl Decoder for Codec {
+    type Item = Message;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+        let len = u16::from_le_bytes([src[0], src[1]]) as usize;
+        if src.len() < len + 2 {
+            return Ok(None);
+        }
+
+        let encrypted = src.split_to(len + 2);
+        let mut frame = vec![0; len];
+        let len = self
+            .noise
+            .read_message(&encrypted[2..], &mut frame)
+            .map_err(noise_err)?;
+        frame.truncate(len);
+
+        if len < HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "sv2 frame too short"));
+        }
+        let ext_type = u16::from_le_bytes([frame[0], frame[1]]);
+        let msg_type = frame[2];
+        let msg_length = u32::from_le_bytes([frame[3], frame[4], frame[5], 0]) as usize;
+        if msg_length != len - HEADER_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "sv2 frame length mismatch: {} != {}",
+                    msg_length,
+                    len - HEADER_LEN
+                ),
+            ));
+        }
+
+        Message::decode(ext_type, msg_type, Bytes::from(&frame[HEADER_LEN..])).map(Some)
+    }
+}
+
+impl Encoder for Codec {
+    type Item = Message;
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<()> {
+        let payload = item.encode();
+        if HEADER_LEN + payload.len() + TAG_LEN > MAX_MESSAGE_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, "sv2 frame too long"));
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&item.ext_type().to_le_bytes());
+        frame.push(item.msg_type());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes()[..3]);
+        frame.extend(payload);
+
+        let mut encrypted = vec![0; frame.len() + TAG_LEN];
+        let len = self
+            .noise
+            .write_message(&frame, &mut encrypted)
+            .map_err(noise_err)?;
+
+        dst.reserve(len + 2);
+        dst.put_u16_le(len as u16);
+        dst.extend(&encrypted[..len]);
+        Ok(())
+    }
+}
@@ -0,0 +1,649 @@
+use std::io::{Error, ErrorKind, Result};
+
+use bytes::Bytes;
+
+pub const PROTOCOL_MINING: u8 = 0;
+pub const CHANNEL_MSG: u16 = 0x8000;
+
+pub const SETUP_CONNECTION: u8 = 0x00;
+pub const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+pub const SETUP_CONNECTION_ERROR: u8 = 0x02;
+pub const OPEN_STANDARD_MINING_CHANNEL: u8 = 0x10;
+pub const OPEN_STANDARD_MINING_CHANNEL_SUCCESS: u8 = 0x11;
+pub const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+pub const OPEN_EXTENDED_MINING_CHANNEL: u8 = 0x13;
+pub const OPEN_EXTENDED_MINING_CHANNEL_SUCCESS: u8 = 0x14;
+pub const SET_EXTRANONCE_PREFIX: u8 = 0x19;
+pub const SUBMIT_SHARES_STANDARD: u8 = 0x1a;
+pub const SUBMIT_SHARES_EXTENDED: u8 = 0x1b;
+pub const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+pub const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+pub const NEW_MINING_JOB: u8 = 0x1e;
+pub const NEW_EXTENDED_MINING_JOB: u8 = 0x1f;
+pub const SET_NEW_PREV_HASH: u8 = 0x20;
+pub const SET_TARGET: u8 = 0x21;
+pub const RECONNECT: u8 = 0x25;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    SetupConnection {
+        protocol: u8,
+        min_version: u16,
+        max_version: u16,
+        flags: u32,
+        endpoint_host: String,
+        endpoint_port: u16,
+        vendor: String,
+        hardware_version: String,
+        firmware: String,
+        device_id: String,
+    },
+    SetupConnectionSuccess {
+        used_version: u16,
+        flags: u32,
+    },
+    SetupConnectionError {
+        flags: u32,
+        error_code: String,
+    },
+    OpenStandardMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: Bytes,
+    },
+    OpenStandardMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: Bytes,
+        extranonce_prefix: Bytes,
+        group_channel_id: u32,
+    },
+    OpenExtendedMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: Bytes,
+        min_extranonce_size: u16,
+    },
+    OpenExtendedMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: Bytes,
+        extranonce_size: u16,
+        extranonce_prefix: Bytes,
+    },
+    OpenMiningChannelError {
+        request_id: u32,
+        error_code: String,
+    },
+    SetExtranoncePrefix {
+        channel_id: u32,
+        extranonce_prefix: Bytes,
+    },
+    SubmitSharesStandard {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+    },
+    SubmitSharesExtended {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+        extranonce: Bytes,
+    },
+    SubmitSharesSuccess {
+        channel_id: u32,
+        last_sequence_number: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u64,
+    },
+    SubmitSharesError {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+    NewMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        future_job: bool,
+        version: u32,
+        merkle_root: Bytes,
+    },
+    NewExtendedMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        future_job: bool,
+        version: u32,
+        version_rolling_allowed: bool,
+        merkle_path: Vec<Bytes>,
+        coinbase_tx_prefix: Bytes,
+        coinbase_tx_suffix: Bytes,
+    },
+    SetNewPrevHash {
+        channel_id: u32,
+        job_id: u32,
+        prev_hash: Bytes,
+        min_ntime: u32,
+        nbits: u32,
+    },
+    SetTarget {
+        channel_id: u32,
+        maximum_target: Bytes,
+    },
+    Reconnect {
+        new_host: String,
+        new_port: u16,
+    },
+    Unknown {
+        ext_type: u16,
+        msg_type: u8,
+    },
+}
+
+struct Payload(Bytes);
+
+impl Payload {
+    fn take(&mut self, n: usize) -> Result<Bytes> {
+        if self.0.len() < n {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "sv2 payload truncated",
+            ));
+        }
+        Ok(self.0.split_to(n))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? & 1 == 1)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let b = self.take(8)?;
+        let mut v = [0; 8];
+        v.copy_from_slice(&b);
+        Ok(u64::from_le_bytes(v))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn u256(&mut self) -> Result<Bytes> {
+        self.take(32)
+    }
+
+    fn b0_32(&mut self) -> Result<Bytes> {
+        let len = self.u8()? as usize;
+        if len > 32 {
+            return Err(Error::new(ErrorKind::InvalidData, "sv2 B0_32 too long"));
+        }
+        self.take(len)
+    }
+
+    fn b0_64k(&mut self) -> Result<Bytes> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn str0_255(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn seq0_255_u256(&mut self) -> Result<Vec<Bytes>> {
+        let len = self.u8()? as usize;
+        (0..len).map(|_| self.u256()).collect()
+    }
+}
+
+#[derive(Default)]
+struct PayloadMut(Vec<u8>);
+
+impl PayloadMut {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn bool(&mut self, v: bool) -> &mut Self {
+        self.u8(v as u8)
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn f32(&mut self, v: f32) -> &mut Self {
+        self.u32(v.to_bits())
+    }
+
+    fn u256(&mut self, v: &[u8]) -> &mut Self {
+        debug_assert_eq!(v.len(), 32);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn b0_32(&mut self, v: &[u8]) -> &mut Self {
+        debug_assert!(v.len() <= 32);
+        self.u8(v.len() as u8);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn b0_64k(&mut self, v: &[u8]) -> &mut Self {
+        debug_assert!(v.len() <= 0xffff);
+        self.u16(v.len() as u16);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn str0_255(&mut self, v: &str) -> &mut Self {
+        let v = &v.as_bytes()[..v.len().min(255)];
+        self.u8(v.len() as u8);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn seq0_255_u256(&mut self, v: &[Bytes]) -> &mut Self {
+        debug_assert!(v.len() <= 255);
+        self.u8(v.len() as u8);
+        for i in v {
+            self.u256(i);
+        }
+        self
+    }
+}
+
+impl Message {
+    pub fn msg_type(&self) -> u8 {
+        use self::Message::*;
+
+        match self {
+            SetupConnection { .. } => SETUP_CONNECTION,
+            SetupConnectionSuccess { .. } => SETUP_CONNECTION_SUCCESS,
+            SetupConnectionError { .. } => SETUP_CONNECTION_ERROR,
+            OpenStandardMiningChannel { .. } => OPEN_STANDARD_MINING_CHANNEL,
+            OpenStandardMiningChannelSuccess { .. } => OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+            OpenExtendedMiningChannel { .. } => OPEN_EXTENDED_MINING_CHANNEL,
+            OpenExtendedMiningChannelSuccess { .. } => OPEN_EXTENDED_MINING_CHANNEL_SUCCESS,
+            OpenMiningChannelError { .. } => OPEN_MINING_CHANNEL_ERROR,
+            SetExtranoncePrefix { .. } => SET_EXTRANONCE_PREFIX,
+            SubmitSharesStandard { .. } => SUBMIT_SHARES_STANDARD,
+            SubmitSharesExtended { .. } => SUBMIT_SHARES_EXTENDED,
+            SubmitSharesSuccess { .. } => SUBMIT_SHARES_SUCCESS,
+            SubmitSharesError { .. } => SUBMIT_SHARES_ERROR,
+            NewMiningJob { .. } => NEW_MINING_JOB,
+            NewExtendedMiningJob { .. } => NEW_EXTENDED_MINING_JOB,
+            SetNewPrevHash { .. } => SET_NEW_PREV_HASH,
+            SetTarget { .. } => SET_TARGET,
+            Reconnect { .. } => RECONNECT,
+            Unknown { msg_type, .. } => *msg_type,
+        }
+    }
+
+    pub fn ext_type(&self) -> u16 {
+        use self::Message::*;
+
+        match self {
+            SetupConnection { .. }
+            | SetupConnectionSuccess { .. }
+            | SetupConnectionError { .. }
+            | OpenStandardMiningChannel { .. }
+            | OpenExtendedMiningChannel { .. }
+            | OpenStandardMiningChannelSuccess { .. }
+            | OpenExtendedMiningChannelSuccess { .. }
+            | OpenMiningChannelError { .. }
+            | Reconnect { .. } => 0,
+            Unknown { ext_type, .. } => *ext_type,
+            _ => CHANNEL_MSG,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        use self::Message::*;
+
+        let mut p = PayloadMut::default();
+        match self {
+            SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                vendor,
+                hardware_version,
+                firmware,
+                device_id,
+            } => p
+                .u8(*protocol)
+                .u16(*min_version)
+                .u16(*max_version)
+                .u32(*flags)
+                .str0_255(endpoint_host)
+                .u16(*endpoint_port)
+                .str0_255(vendor)
+                .str0_255(hardware_version)
+                .str0_255(firmware)
+                .str0_255(device_id),
+            SetupConnectionSuccess {
+                used_version,
+                flags,
+            } => p.u16(*used_version).u32(*flags),
+            SetupConnectionError { flags, error_code } => p.u32(*flags).str0_255(error_code),
+            OpenStandardMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+            } => p
+                .u32(*request_id)
+                .str0_255(user_identity)
+                .f32(*nominal_hash_rate)
+                .u256(max_target),
+            OpenStandardMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                group_channel_id,
+            } => p
+                .u32(*request_id)
+                .u32(*channel_id)
+                .u256(target)
+                .b0_32(extranonce_prefix)
+                .u32(*group_channel_id),
+            OpenExtendedMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+                min_extranonce_size,
+            } => p
+                .u32(*request_id)
+                .str0_255(user_identity)
+                .f32(*nominal_hash_rate)
+                .u256(max_target)
+                .u16(*min_extranonce_size),
+            OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_size,
+                extranonce_prefix,
+            } => p
+                .u32(*request_id)
+                .u32(*channel_id)
+                .u256(target)
+                .u16(*extranonce_size)
+                .b0_32(extranonce_prefix),
+            OpenMiningChannelError {
+                request_id,
+                error_code,
+            } => p.u32(*request_id).str0_255(error_code),
+            SetExtranoncePrefix {
+                channel_id,
+                extranonce_prefix,
+            } => p.u32(*channel_id).b0_32(extranonce_prefix),
+            SubmitSharesStandard {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+            } => p
+                .u32(*channel_id)
+                .u32(*sequence_number)
+                .u32(*job_id)
+                .u32(*nonce)
+                .u32(*ntime)
+                .u32(*version),
+            SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            } => p
+                .u32(*channel_id)
+                .u32(*sequence_number)
+                .u32(*job_id)
+                .u32(*nonce)
+                .u32(*ntime)
+                .u32(*version)
+                .b0_32(extranonce),
+            SubmitSharesSuccess {
+                channel_id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => p
+                .u32(*channel_id)
+                .u32(*last_sequence_number)
+                .u32(*new_submits_accepted_count)
+                .u64(*new_shares_sum),
+            SubmitSharesError {
+                channel_id,
+                sequence_number,
+                error_code,
+            } => p
+                .u32(*channel_id)
+                .u32(*sequence_number)
+                .str0_255(error_code),
+            NewMiningJob {
+                channel_id,
+                job_id,
+                future_job,
+                version,
+                merkle_root,
+            } => p
+                .u32(*channel_id)
+                .u32(*job_id)
+                .bool(*future_job)
+                .u32(*version)
+                .u256(merkle_root),
+            NewExtendedMiningJob {
+                channel_id,
+                job_id,
+                future_job,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            } => p
+                .u32(*channel_id)
+                .u32(*job_id)
+                .bool(*future_job)
+                .u32(*version)
+                .bool(*version_rolling_allowed)
+                .seq0_255_u256(merkle_path)
+                .b0_64k(coinbase_tx_prefix)
+                .b0_64k(coinbase_tx_suffix),
+            SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } => p
+                .u32(*channel_id)
+                .u32(*job_id)
+                .u256(prev_hash)
+                .u32(*min_ntime)
+                .u32(*nbits),
+            SetTarget {
+                channel_id,
+                maximum_target,
+            } => p.u32(*channel_id).u256(maximum_target),
+            Reconnect { new_host, new_port } => p.str0_255(new_host).u16(*new_port),
+            Unknown { .. } => &mut p,
+        };
+        p.0
+    }
+
+    pub fn decode(ext_type: u16, msg_type: u8, payload: Bytes) -> Result<Self> {
+        use self::Message::*;
+
+        let p = &mut Payload(payload);
+        if ext_type & !CHANNEL_MSG != 0 {
+            return Ok(Unknown { ext_type, msg_type });
+        }
+
+        let msg = match msg_type {
+            SETUP_CONNECTION => SetupConnection {
+                protocol: p.u8()?,
+                min_version: p.u16()?,
+                max_version: p.u16()?,
+                flags: p.u32()?,
+                endpoint_host: p.str0_255()?,
+                endpoint_port: p.u16()?,
+                vendor: p.str0_255()?,
+                hardware_version: p.str0_255()?,
+                firmware: p.str0_255()?,
+                device_id: p.str0_255()?,
+            },
+            SETUP_CONNECTION_SUCCESS => SetupConnectionSuccess {
+                used_version: p.u16()?,
+                flags: p.u32()?,
+            },
+            SETUP_CONNECTION_ERROR => SetupConnectionError {
+                flags: p.u32()?,
+                error_code: p.str0_255()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL => OpenStandardMiningChannel {
+                request_id: p.u32()?,
+                user_identity: p.str0_255()?,
+                nominal_hash_rate: p.f32()?,
+                max_target: p.u256()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL_SUCCESS => OpenStandardMiningChannelSuccess {
+                request_id: p.u32()?,
+                channel_id: p.u32()?,
+                target: p.u256()?,
+                extranonce_prefix: p.b0_32()?,
+                group_channel_id: p.u32()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL => OpenExtendedMiningChannel {
+                request_id: p.u32()?,
+                user_identity: p.str0_255()?,
+                nominal_hash_rate: p.f32()?,
+                max_target: p.u256()?,
+                min_extranonce_size: p.u16()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => OpenExtendedMiningChannelSuccess {
+                request_id: p.u32()?,
+                channel_id: p.u32()?,
+                target: p.u256()?,
+                extranonce_size: p.u16()?,
+                extranonce_prefix: p.b0_32()?,
+            },
+            OPEN_MINING_CHANNEL_ERROR => OpenMiningChannelError {
+                request_id: p.u32()?,
+                error_code: p.str0_255()?,
+            },
+            SET_EXTRANONCE_PREFIX => SetExtranoncePrefix {
+                channel_id: p.u32()?,
+                extranonce_prefix: p.b0_32()?,
+            },
+            SUBMIT_SHARES_STANDARD => SubmitSharesStandard {
+                channel_id: p.u32()?,
+                sequence_number: p.u32()?,
+                job_id: p.u32()?,
+                nonce: p.u32()?,
+                ntime: p.u32()?,
+                version: p.u32()?,
+            },
+            SUBMIT_SHARES_EXTENDED => SubmitSharesExtended {
+                channel_id: p.u32()?,
+                sequence_number: p.u32()?,
+                job_id: p.u32()?,
+                nonce: p.u32()?,
+                ntime: p.u32()?,
+                version: p.u32()?,
+                extranonce: p.b0_32()?,
+            },
+            SUBMIT_SHARES_SUCCESS => SubmitSharesSuccess {
+                channel_id: p.u32()?,
+                last_sequence_number: p.u32()?,
+                new_submits_accepted_count: p.u32()?,
+                new_shares_sum: p.u64()?,
+            },
+            SUBMIT_SHARES_ERROR => SubmitSharesError {
+                channel_id: p.u32()?,
+                sequence_number: p.u32()?,
+                error_code: p.str0_255()?,
+            },
+            NEW_MINING_JOB => NewMiningJob {
+                channel_id: p.u32()?,
+                job_id: p.u32()?,
+                future_job: p.bool()?,
+                version: p.u32()?,
+                merkle_root: p.u256()?,
+            },
+            NEW_EXTENDED_MINING_JOB => NewExtendedMiningJob {
+                channel_id: p.u32()?,
+                job_id: p.u32()?,
+                future_job: p.bool()?,
+                version: p.u32()?,
+                version_rolling_allowed: p.bool()?,
+                merkle_path: p.seq0_255_u256()?,
+                coinbase_tx_prefix: p.b0_64k()?,
+                coinbase_tx_suffix: p.b0_64k()?,
+            },
+            SET_NEW_PREV_HASH => SetNewPrevHash {
+                channel_id: p.u32()?,
+                job_id: p.u32()?,
+                prev_hash: p.u256()?,
+                min_ntime: p.u32()?,
+                nbits: p.u32()?,
+            },
+            SET_TARGET => SetTarget {
+                channel_id: p.u32()?,
+                maximum_target: p.u256()?,
+            },
+            RECONNECT => Reconnect {
+                new_host: p.str0_255()?,
+                new_port: p.u16()?,
+            },
+            _ => Unknown { ext_type, msg_type },
+        };
+        Ok(msg)
+    }
+}
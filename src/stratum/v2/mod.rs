@@ -0,0 +1,436 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use serde_json::Value as JsonValue;
+use tokio::codec::Decoder;
+
+use crate::util::{Channel, Flip32, FromHex, ToHex};
+
+use self::codec::Codec;
+pub use self::message::*;
+pub use self::noise::generate_keypair;
+
+use super::*;
+
+mod codec;
+mod message;
+pub mod noise;
+#[cfg(test)]
+mod tests;
+
+const NOMINAL_HASH_RATE: f32 = 1.0e13;
+const MIN_EXTRANONCE_SIZE: u16 = 4;
+
+#[derive(Default)]
+struct State {
+    channel_id: Option<u32>,
+    extended: bool,
+    vermask: u32,
+    // job_id -> version, for the jobs valid on the current prevhash
+    versions: HashMap<u32, u32>,
+    future_jobs: HashMap<u32, Work>,
+    prevhash: Option<(Bytes, u32, u32)>,
+    sequence_number: u32,
+    // (sequence_number, json-rpc id)
+    submitted: VecDeque<(u32, u64)>,
+}
+
+fn target_diff(target: &Bytes) -> f64 {
+    Target::from_le_bytes(target).difficulty()
+}
+
+fn hex_u32(value: &JsonValue) -> Option<u32> {
+    u32::from_str_radix(value.as_str()?, 16).ok()
+}
+
+impl State {
+    fn activate(&mut self, mut work: Work, job_id: u32) -> Option<Work> {
+        let (prevhash, ntime, nbits) = self.prevhash.as_ref()?;
+        work.prevhash = prevhash.clone();
+        work.ntime = Bytes::from(&ntime.to_be_bytes()[..]);
+        work.nbits = Bytes::from(&nbits.to_be_bytes()[..]);
+        self.versions.insert(job_id, work.version);
+        Some(work)
+    }
+
+    // translate the `mining.submit` sent by the miner into SubmitShares
+    fn submit(&mut self, line: &str) -> Option<Message> {
+        let msg: JsonValue = serde_json::from_str(line).ok()?;
+        if msg["method"] != "mining.submit" {
+            warn!("ignore message for v1 pool: {}", line);
+            return None;
+        }
+
+        let id = msg["id"].as_u64()?;
+        let params = msg["params"].as_array()?;
+        let job_id = params.get(1)?.as_str()?.parse().ok()?;
+        let extranonce = Bytes::from(params.get(2)?.as_str()?.from_hex().ok()?);
+        let ntime = hex_u32(params.get(3)?)?;
+        let nonce = hex_u32(params.get(4)?)?;
+        let version_bits = params.get(5).and_then(hex_u32).unwrap_or(0);
+
+        let version = match self.versions.get(&job_id) {
+            Some(version) => (version & !self.vermask) | version_bits,
+            None => {
+                warn!("submit for unknown job: {}!", job_id);
+                return None;
+            }
+        };
+
+        let channel_id = self.channel_id?;
+        let sequence_number = self.sequence_number;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.submitted.push_back((sequence_number, id));
+
+        Some(if self.extended {
+            Message::SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            }
+        } else {
+            Message::SubmitSharesStandard {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+            }
+        })
+    }
+}
+
+impl Pool {
+    pub(super) fn connect_v2(
+        &mut self,
+        config: &Config,
+        pool: usize,
+        tcpstream: impl Future<Item = TcpStream, Error = Error> + Send,
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        let pool_config = &config.pool[pool];
+        // a key mistyped must not turn off the authentication of the pool
+        let pubkey = match pool_config.pubkey.as_ref().map(|x| x.from_hex()) {
+            Some(Ok(ref pubkey)) if pubkey.len() != 32 => {
+                let e = format!("pool {} pubkey: {} bytes, 32 expected", pool, pubkey.len());
+                return Either::B(err(Error::Config(e)));
+            }
+            Some(Ok(pubkey)) => Some(pubkey),
+            Some(Err(e)) => {
+                return Either::B(err(Error::Config(format!("pool {} pubkey: {}", pool, e))));
+            }
+            None => None,
+        };
+
+        let (frame_tx, frame_rx) = unbounded();
+        let (host, port) = match self.addr.rfind(':') {
+            Some(n) => (&self.addr[..n], self.addr[n + 1..].parse().unwrap_or(0)),
+            None => (&self.addr[..], 0),
+        };
+        let _ = frame_tx.unbounded_send(Message::SetupConnection {
+            protocol: PROTOCOL_MINING,
+            min_version: 2,
+            max_version: 2,
+            flags: (pool_config.channel == Channel::Standard) as u32,
+            endpoint_host: host.to_string(),
+            endpoint_port: port,
+            vendor: String::from("stratum"),
+            hardware_version: String::new(),
+            firmware: config.client.user_agent.clone().unwrap_or_default(),
+            device_id: String::new(),
+        });
+
+        let vermask = u32::from_str_radix(&config.client.version_rolling.mask, 16).unwrap_or(0);
+        let state = Arc::new(Mutex::new(State {
+            extended: pool_config.channel == Channel::Extended,
+            ..Default::default()
+        }));
+
+        let connected = self.connected.clone();
+        let last_active = self.last_active.clone();
+        let handle = self.handler(state.clone(), frame_tx, pool_config.user.clone(), vermask);
+        let writer_rx = self.outbox.clone();
+
+        Either::A(tcpstream.and_then(move |tcpstream| {
+            noise::initiator(tcpstream, pubkey)
+                .map_err(|e| {
+                    error!("noise handshake err: {:?}", e);
+                    Error::Connect(e)
+                })
+                .and_then(move |(tcpstream, noise)| {
+                    connected.store(true, Ordering::SeqCst);
+                    let (sink, stream) = Codec::new(noise).framed(tcpstream).split();
+
+                    let reader = stream
+                        .inspect(move |msg| {
+                            debug!("recv: {:?}", msg);
+                            *last_active.lock().unwrap() = Instant::now();
+                        })
+                        .map_err(|e| {
+                            error!("recv from pool err: {:?}", e);
+                            Error::Io(e)
+                        })
+                        .for_each(handle)
+                        .and_then(|_| Err::<(), _>(Error::Disconnected));
+
+                    let writer = writer_rx
+                        .filter_map(move |line| state.lock().unwrap().submit(&line))
+                        .select(frame_rx)
+                        .map_err(|_| Error::ChannelClosed)
+                        .inspect(|msg| debug!("send: {:?}", msg))
+                        .forward(
+                            SinkHook::new(sink, || debug!("data sent!")).sink_map_err(|e| {
+                                error!("send to pool err: {:?}", e);
+                                Error::Io(e)
+                            }),
+                        )
+                        .map(drop);
+                    reader.select(writer).map(drop).map_err(|(e, _)| e)
+                })
+        }))
+    }
+
+    fn handler(
+        &mut self,
+        state: Arc<Mutex<State>>,
+        frame_tx: UnboundedSender<Message>,
+        user: String,
+        vermask: u32,
+    ) -> impl FnMut(Message) -> Result<(), Error> + Send {
+        let addr = self.addr.clone();
+        let authorized = self.authorized.1.clone();
+        self.authorized.0 = Some(user.clone());
+        let xnonce = self.xnonce.clone();
+        let ledger = self.ledger.clone();
+        let work_sender = self.work_channel.0.clone();
+        let work_notify = self.work_notify.clone();
+        let xnonce_notify = self.work_notify.clone();
+        let jobs = self.jobs.clone();
+        let session = self.session.clone();
+        let pool_vermask = self.vermask.clone();
+        let diff = self.diff.clone();
+
+        let send_work = move |work: Work| {
+            info!("=> received new work!");
+            jobs.lock().unwrap().update(session.load(Ordering::SeqCst), &work);
+            let work_notify = work_notify.clone();
+            tokio::spawn(work_sender.clone().send(work).then(move |_| {
+                work_notify.notify();
+                Ok(())
+            }));
+        };
+
+        let submit_result = move |id: u64, error: Option<&str>| {
+            let result = match error {
+                None => ShareResult::Accepted,
+                Some(reason) => ShareResult::rejected(None, reason),
+            };
+            let message = result.to_string();
+            match ledger.lock().unwrap().result(id, result) {
+                Some((share, latency)) => share.log_result(&message, latency),
+                None => warn!("unknown submit result (id: {})!", id),
+            }
+        };
+
+        #[allow(clippy::cognitive_complexity)]
+        move |msg| {
+            let mut state = state.lock().unwrap();
+            match msg {
+                Message::SetupConnectionSuccess { used_version, .. } => {
+                    info!(
+                        "=> setup connection successfully (version: {})!",
+                        used_version
+                    );
+                    let max_target = Bytes::from(&[0xff; 32][..]);
+                    let open = if state.extended {
+                        Message::OpenExtendedMiningChannel {
+                            request_id: 0,
+                            user_identity: user.clone(),
+                            nominal_hash_rate: NOMINAL_HASH_RATE,
+                            max_target,
+                            min_extranonce_size: MIN_EXTRANONCE_SIZE,
+                        }
+                    } else {
+                        Message::OpenStandardMiningChannel {
+                            request_id: 0,
+                            user_identity: user.clone(),
+                            nominal_hash_rate: NOMINAL_HASH_RATE,
+                            max_target,
+                        }
+                    };
+                    let _ = frame_tx.unbounded_send(open);
+                }
+                Message::SetupConnectionError { error_code, .. } => {
+                    error!("=> setup connection failed: {}!", error_code);
+                    return Err(Error::Protocol(error_code));
+                }
+                Message::OpenStandardMiningChannelSuccess {
+                    channel_id,
+                    target,
+                    extranonce_prefix,
+                    ..
+                } => {
+                    info!("=> authorized successfully (channel: {})!", channel_id);
+                    authorized.store(true, Ordering::SeqCst);
+                    state.channel_id = Some(channel_id);
+                    state.vermask = vermask;
+                    *pool_vermask.lock().unwrap() = Some(vermask);
+                    *xnonce.lock().unwrap() = (extranonce_prefix, 0);
+                    *diff.lock().unwrap() = target_diff(&target);
+                }
+                Message::OpenExtendedMiningChannelSuccess {
+                    channel_id,
+                    target,
+                    extranonce_size,
+                    extranonce_prefix,
+                    ..
+                } => {
+                    info!("=> authorized successfully (channel: {})!", channel_id);
+                    info!(
+                        "=> set xnonce1: 0x{}, xnonce2_size: {}!",
+                        extranonce_prefix.to_hex(),
+                        extranonce_size
+                    );
+                    authorized.store(true, Ordering::SeqCst);
+                    state.channel_id = Some(channel_id);
+                    *xnonce.lock().unwrap() = (extranonce_prefix, extranonce_size as usize);
+                    *diff.lock().unwrap() = target_diff(&target);
+                }
+                Message::OpenMiningChannelError { error_code, .. } => {
+                    info!("=> authorized failed: {}!", error_code);
+                    return Err(Error::AuthRejected(error_code));
+                }
+                Message::SetExtranoncePrefix {
+                    extranonce_prefix, ..
+                } => {
+                    info!("=> set xnonce1: 0x{}!", extranonce_prefix.to_hex());
+                    xnonce.lock().unwrap().0 = extranonce_prefix;
+                    xnonce_notify.notify();
+                }
+                Message::SetTarget { maximum_target, .. } => {
+                    let n = target_diff(&maximum_target);
+                    info!("=> set difficulty: {}!", n);
+                    *diff.lock().unwrap() = n;
+                }
+                Message::NewMiningJob {
+                    job_id,
+                    future_job,
+                    version,
+                    merkle_root,
+                    ..
+                } => {
+                    let work = Work {
+                        id: job_id.to_string(),
+                        prevhash: Bytes::new(),
+                        coinbase1: Bytes::new(),
+                        coinbase2: Bytes::new(),
+                        merkle_branch: Vec::new(),
+                        version,
+                        nbits: Bytes::new(),
+                        ntime: Bytes::new(),
+                        clean: false,
+                        merkle_root: Some(Bytes::from(merkle_root.to_vec().flip32())),
+                    };
+                    if future_job {
+                        state.future_jobs.insert(job_id, work);
+                    } else if let Some(work) = state.activate(work, job_id) {
+                        send_work(work);
+                    }
+                }
+                Message::NewExtendedMiningJob {
+                    job_id,
+                    future_job,
+                    version,
+                    version_rolling_allowed,
+                    merkle_path,
+                    coinbase_tx_prefix,
+                    coinbase_tx_suffix,
+                    ..
+                } => {
+                    let mask = if version_rolling_allowed { vermask } else { 0 };
+                    if state.vermask != mask || pool_vermask.lock().unwrap().is_none() {
+                        info!("=> set vermask: 0x{}!", mask.to_be_bytes().to_hex());
+                        state.vermask = mask;
+                        *pool_vermask.lock().unwrap() = Some(mask);
+                    }
+
+                    let work = Work {
+                        id: job_id.to_string(),
+                        prevhash: Bytes::new(),
+                        coinbase1: coinbase_tx_prefix,
+                        coinbase2: coinbase_tx_suffix,
+                        merkle_branch: merkle_path,
+                        version,
+                        nbits: Bytes::new(),
+                        ntime: Bytes::new(),
+                        clean: false,
+                        merkle_root: None,
+                    };
+                    if future_job {
+                        state.future_jobs.insert(job_id, work);
+                    } else if let Some(work) = state.activate(work, job_id) {
+                        send_work(work);
+                    }
+                }
+                Message::SetNewPrevHash {
+                    job_id,
+                    prev_hash,
+                    min_ntime,
+                    nbits,
+                    ..
+                } => {
+                    let prevhash = Bytes::from(prev_hash.to_vec().flip32());
+                    state.prevhash = Some((prevhash, min_ntime, nbits));
+                    state.versions.clear();
+                    match state.future_jobs.remove(&job_id) {
+                        Some(mut work) => {
+                            work.clean = true;
+                            if let Some(work) = state.activate(work, job_id) {
+                                send_work(work);
+                            }
+                        }
+                        None => warn!("=> set new prevhash for unknown job: {}!", job_id),
+                    }
+                    state.future_jobs.clear();
+                }
+                Message::SubmitSharesSuccess {
+                    last_sequence_number,
+                    ..
+                } => {
+                    while let Some(&(sequence_number, id)) = state.submitted.front() {
+                        if sequence_number.wrapping_sub(last_sequence_number) as i32 > 0 {
+                            break;
+                        }
+                        state.submitted.pop_front();
+                        submit_result(id, None);
+                    }
+                }
+                Message::SubmitSharesError {
+                    sequence_number,
+                    error_code,
+                    ..
+                } => {
+                    let submitted = &mut state.submitted;
+                    match submitted.iter().position(|x| x.0 == sequence_number) {
+                        Some(n) => {
+                            let (_, id) = submitted.remove(n).unwrap();
+                            submit_result(id, Some(&error_code));
+                        }
+                        None => warn!("unknown submit result (sequence: {})!", sequence_number),
+                    }
+                }
+                Message::Reconnect { new_host, new_port } => {
+                    let addr = reconnect_addr(&addr, Some(&new_host), Some(new_port));
+                    return Err(Error::Reconnect(addr, Duration::from_secs(0)));
+                }
+                _ => warn!("=> unknown message: {:?}!", msg),
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{Error, ErrorKind};
+
+use futures::future::{result, Future};
+use snow::{Builder, HandshakeState, TransportState};
+use tokio::io::{read_exact, write_all, AsyncRead, AsyncWrite};
+
+use crate::util::ToHex;
+
+pub const NOISE_PARAMS: &str = "Noise_NX_25519_ChaChaPoly_BLAKE2s";
+pub const MAX_MESSAGE_LEN: usize = 65535;
+
+pub fn noise_err(e: snow::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("noise: {}", e))
+}
+
+fn builder<'a>() -> Builder<'a> {
+    Builder::new(NOISE_PARAMS.parse().unwrap())
+}
+
+pub fn generate_keypair() -> snow::Keypair {
+    builder().generate_keypair().unwrap()
+}
+
+fn write_frame<T: AsyncWrite>(
+    io: T,
+    noise: &mut HandshakeState,
+    payload: &[u8],
+) -> impl Future<Item = T, Error = Error> {
+    let mut buf = vec![0; MAX_MESSAGE_LEN + 2];
+    result(
+        noise
+            .write_message(payload, &mut buf[2..])
+            .map_err(noise_err),
+    )
+    .and_then(move |len| {
+        buf[..2].copy_from_slice(&(len as u16).to_le_bytes());
+        buf.truncate(len + 2);
+        write_all(io, buf)
+    })
+    .map(|(io, _)| io)
+}
+
+fn read_frame<T: AsyncRead>(io: T) -> impl Future<Item = (T, Vec<u8>), Error = Error> {
+    read_exact(io, [0u8; 2]).and_then(|(io, len)| {
+        let len = u16::from_le_bytes(len) as usize;
+        read_exact(io, vec![0; len])
+    })
+}
+
+// -> e
+// <- e, ee, s, es
+pub fn initiator<T: AsyncRead + AsyncWrite>(
+    io: T,
+    pubkey: Option<Vec<u8>>,
+) -> impl Future<Item = (T, TransportState), Error = Error> {
+    result(builder().build_initiator().map_err(noise_err)).and_then(|mut noise| {
+        write_frame(io, &mut noise, &[])
+            .and_then(read_frame)
+            .and_then(move |(io, msg)| {
+                let mut payload = vec![0; msg.len()];
+                noise.read_message(&msg, &mut payload).map_err(noise_err)?;
+
+                let remote = noise.get_remote_static().unwrap_or_default().to_vec();
+                match pubkey {
+                    Some(ref pubkey) if pubkey != &remote => {
+                        return Err(Error::new(
+                            ErrorKind::PermissionDenied,
+                            format!("unexpected server key: {}", remote.to_hex()),
+                        ));
+                    }
+                    Some(_) => debug!("server key verified: {}", remote.to_hex()),
+                    None => warn!("unverified server key: {}!", remote.to_hex()),
+                }
+
+                Ok((io, noise.into_transport_mode().map_err(noise_err)?))
+            })
+    })
+}
+
+pub fn responder<T: AsyncRead + AsyncWrite>(
+    io: T,
+    private_key: &[u8],
+) -> impl Future<Item = (T, TransportState), Error = Error> {
+    result(
+        builder()
+            .local_private_key(private_key)
+            .build_responder()
+            .map_err(noise_err),
+    )
+    .and_then(|mut noise| {
+        read_frame(io).and_then(move |(io, msg)| {
+            let mut payload = vec![0; msg.len()];
+            result(noise.read_message(&msg, &mut payload).map_err(noise_err))
+                .and_then(move |_| write_frame(io, &mut noise, &[]).map(|io| (io, noise)))
+        })
+    })
+    .and_then(|(io, noise)| Ok((io, noise.into_transport_mode().map_err(noise_err)?)))
+}
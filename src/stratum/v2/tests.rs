@@ -0,0 +1,249 @@
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use futures::sync::mpsc::unbounded;
+use tokio::net::TcpListener;
+use tokio::runtime::{current_thread, Runtime};
+
+use crate::util::FromHex;
+
+use super::*;
+
+fn hex(s: &str) -> Bytes {
+    Bytes::from(s.from_hex().unwrap())
+}
+
+fn config(addr: &SocketAddr, pubkey: &[u8]) -> Config {
+    toml::from_str(&format!(
+        r#"
+        [client]
+        user-agent = "stratum/test"
+
+        [client.version-rolling]
+        mask = "1fffe000"
+
+        [board]
+        enabled = []
+        default = {{}}
+
+        [[pool]]
+        addr = "{}"
+        user = "user.0"
+        pass = ""
+        protocol = "v2"
+        pubkey = "{}"
+        "#,
+        addr,
+        pubkey.to_hex()
+    ))
+    .unwrap()
+}
+
+fn respond(msg: &Message) -> Vec<Message> {
+    match *msg {
+        Message::SetupConnection { .. } => vec![Message::SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        }],
+        Message::OpenExtendedMiningChannel { request_id, .. } => {
+            let mut target = [0xff; 32];
+            target[28..].copy_from_slice(&[0; 4]);
+            vec![
+                Message::OpenExtendedMiningChannelSuccess {
+                    request_id,
+                    channel_id: 1,
+                    target: Bytes::from(&target[..]),
+                    extranonce_size: 8,
+                    extranonce_prefix: hex("72e03131"),
+                },
+                Message::NewExtendedMiningJob {
+                    channel_id: 1,
+                    job_id: 7,
+                    future_job: true,
+                    version: 0x2000_0000,
+                    version_rolling_allowed: true,
+                    merkle_path: vec![
+                        hex("0c3c1a888c2b9e521c3c1456414473b712216568c3a69e7eefe6434134f951ed"),
+                        hex("f12de771dc657d5e24c0737c444ab284222997bf3e9c9d298e72effa8cbcde5a"),
+                        hex("1236c0d90296a8be77d6fa1592da80e1b7cc4786dc2c0450bda264585d77c54f"),
+                        hex("59ca1f1c9dcdc854391af53899c261f0e2ffab2dc8378a18b1a9814d08d1e19c"),
+                        hex("4a35546b633381f873d5e45c00c538cbfd315d9caab314b4d6f1c61ee139740f"),
+                        hex("f905d59a405db965a9fa459a7f0b3e8f76eaf8f9b97d71d4a10aa6008bf71e74"),
+                        hex("623276bd848189e535ede317e7736b14f3582ddb5134dbfbf7e6f86fc627e7fe"),
+                        hex("f9207de81abf714acf8b995e34c3e1143ca23464f1003f18896904f44f3732eb"),
+                        hex("4544bb603bd0b635e57dd3514b4c68f1ae232b170213194fc3a8cce087ba4387"),
+                        hex("c60399ab19b9a8bf5600c84419ecaee2f830031164255fe4549f90389239acc8"),
+                        hex("98facdad2e8e9747cc3eccd240112e0e235201083c3f09dcc1989103b7e4640f"),
+                        hex("ae2817984d528bf9174f10ecef74dc673086fbfaa5b61f3f8fdf12b9309a8a34"),
+                    ],
+                    coinbase_tx_prefix: hex("02000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4b03d08d08042d1c505c612f4254432e434f4d2ffabe6d6d54bf3732a3dc252297cf75d4c1cf35878ed99626ef2ffb311cef0cc7c4eff06e0100000000000000"),
+                    coinbase_tx_suffix: hex("ffffffff0329e74d4c0000000016001497cfc76442fe717f2a3f0cc9c175f7561b6619970000000000000000266a24aa21a9ed82b1c33e59cfca82f3af6b51d6094775df97a385f135cabb259ca9fdb63f124b00000000000000002952534b424c4f434b3af5fbe7f0043226e246965f4e7db2c3ff6d5dfedb9b85d0873eed8cca4227c14900000000"),
+                },
+                Message::SetNewPrevHash {
+                    channel_id: 1,
+                    job_id: 7,
+                    prev_hash: hex("845d295385761126e65b29017c5f30a3e0708ac238c016000000000000000000"),
+                    min_ntime: 0x5c50_1c2a,
+                    nbits: 0x1730_6835,
+                },
+            ]
+        }
+        Message::SubmitSharesExtended {
+            channel_id,
+            sequence_number,
+            ..
+        } => vec![Message::SubmitSharesSuccess {
+            channel_id,
+            last_sequence_number: sequence_number,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        }],
+        _ => vec![],
+    }
+}
+
+fn mock_server(private_key: Vec<u8>) -> (SocketAddr, mpsc::Receiver<Message>) {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (report, received) = mpsc::channel();
+
+    thread::spawn(move || {
+        let server = listener
+            .incoming()
+            .take(1)
+            .for_each(move |socket| {
+                let report = report.clone();
+                noise::responder(socket, &private_key).and_then(move |(socket, noise)| {
+                    let (sink, stream) = Codec::new(noise).framed(socket).split();
+                    let (tx, rx) = unbounded();
+
+                    let reader = stream.for_each(move |msg| {
+                        for reply in respond(&msg) {
+                            let _ = tx.unbounded_send(reply);
+                        }
+                        let _ = report.send(msg);
+                        Ok(())
+                    });
+                    let writer = rx
+                        .map_err(|_| Error::new(ErrorKind::BrokenPipe, "closed"))
+                        .forward(sink);
+                    reader.select2(writer).then(|_| Ok(()))
+                })
+            })
+            .map_err(drop);
+        let _ = current_thread::Runtime::new().unwrap().block_on(server);
+    });
+
+    (addr, received)
+}
+
+#[test]
+fn message_roundtrip() {
+    let messages = vec![
+        Message::SetupConnection {
+            protocol: PROTOCOL_MINING,
+            min_version: 2,
+            max_version: 2,
+            flags: 1,
+            endpoint_host: String::from("pool.example.com"),
+            endpoint_port: 3336,
+            vendor: String::from("stratum"),
+            hardware_version: String::new(),
+            firmware: String::from("stratum/0.1.0"),
+            device_id: String::new(),
+        },
+        Message::SubmitSharesExtended {
+            channel_id: 1,
+            sequence_number: 2,
+            job_id: 3,
+            nonce: 0xdead_beef,
+            ntime: 0x5c50_1c2a,
+            version: 0x2000_0000,
+            extranonce: hex("0000000000000001"),
+        },
+        Message::SetTarget {
+            channel_id: 1,
+            maximum_target: Bytes::from(&[0x5a; 32][..]),
+        },
+    ];
+
+    for msg in messages {
+        let payload = Bytes::from(msg.encode());
+        let decoded = Message::decode(msg.ext_type(), msg.msg_type(), payload).unwrap();
+        assert_eq!(msg, decoded);
+    }
+}
+
+#[test]
+fn mock_pool() {
+    let keypair = generate_keypair();
+    let (addr, received) = mock_server(keypair.private);
+    let config = config(&addr, &keypair.public);
+
+    let mut pool = Pool::new(&addr.to_string());
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(pool.connect(&config, 0).map_err(drop));
+
+    let (work, _) = runtime
+        .block_on(pool.workstream().into_future().map_err(drop))
+        .unwrap();
+    let work = work.unwrap();
+    assert_eq!(work.id, "7");
+    assert!(work.clean);
+    assert!(pool.authorized.1.load(Ordering::SeqCst));
+    assert_eq!(*pool.vermask.lock().unwrap(), Some(0x1fff_e000));
+    assert_eq!(pool.xnonce.lock().unwrap().1, 8);
+
+    let xnonce1 = pool.xnonce.lock().unwrap().0.clone();
+    assert_eq!(
+        work.block_header(&(&xnonce1, hex("0000000000000001"))),
+        hex("2000000053295d842611768501295be6a3305f7cc28a70e00016c038000000000000000009a2beeef9c314bfe0c9f839b80bb8724247e630aa6f1efed1e6a483cd1cc8e85c501c2a17306835")
+    );
+
+    let id = pool.ledger.lock().unwrap().submit(Share {
+        pool: 0,
+        job_id: "7".to_string(),
+        nonce: 0x1234_5678,
+        version_bits: 0x2000,
+        difficulty: 1.0,
+        pool_difficulty: 1.0,
+        block: false,
+        submit_time: Instant::now(),
+    });
+    let submit = json!({
+        "id": id,
+        "method": "mining.submit",
+        "params": ["user.0", "7", "0000000000000001", "5c501c2a", "12345678", "00002000"]
+    });
+    pool.sender().send(submit.to_string()).wait().unwrap();
+
+    let timeout = Duration::from_secs(5);
+    let submitted = loop {
+        match received.recv_timeout(timeout).unwrap() {
+            msg @ Message::SubmitSharesExtended { .. } => break msg,
+            _ => continue,
+        }
+    };
+    assert_eq!(
+        submitted,
+        Message::SubmitSharesExtended {
+            channel_id: 1,
+            sequence_number: 0,
+            job_id: 7,
+            nonce: 0x1234_5678,
+            ntime: 0x5c50_1c2a,
+            version: 0x2000_2000,
+            extranonce: hex("0000000000000001"),
+        }
+    );
+
+    let start = Instant::now();
+    while pool.ledger.lock().unwrap().pending() > 0 {
+        assert!(start.elapsed() < timeout, "submit result not received");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(pool.ledger.lock().unwrap().stats().accepted, 1);
+}
@@ -1,20 +1,73 @@
 use std::fs::File;
 use std::io::Read;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+// about 30 years
+const MAX_SECONDS: f64 = 1e9;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
     pub pool: Vec<Pool>,
     pub board: Board,
     pub client: Client,
+    #[serde(default)]
+    pub strategy: Strategy,
+    pub cpu: Option<Cpu>,
+    pub serial: Option<Serial>,
+    pub fan: Option<Fan>,
+    pub tune: Option<Tune>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Pool {
     pub addr: String,
     pub user: String,
     pub pass: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub channel: Channel,
+    pub pubkey: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(default)]
+    pub extranonce_subscribe: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    V1,
+    V2,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Standard,
+    #[default]
+    Extended,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Strategy {
+    pub kind: StrategyKind,
+    // seconds
+    pub quantum: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrategyKind {
+    Failover,
+    RoundRobin,
+    #[default]
+    Weighted,
+    Balance,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -22,6 +75,34 @@ pub struct Pool {
 pub struct Client {
     pub user_agent: Option<String>,
     pub version_rolling: VersionRolling,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    #[serde(default)]
+    pub stale_shares: StalePolicy,
+}
+
+// what to do with the results of the jobs superseded by the pool
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StalePolicy {
+    Drop,
+    #[default]
+    Count,
+}
+
+// all durations in seconds
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Reconnect {
+    pub initial_delay: f64,
+    pub max_delay: f64,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub idle_timeout: f64,
+    // the longest wait asked by a pool with client.reconnect that is honored
+    pub max_wait: f64,
+    // follow client.reconnect to another host than the configured one
+    pub any_host: bool,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -31,11 +112,32 @@ pub struct VersionRolling {
     pub min_bit_count: Option<u8>,
 }
 
+// hash with the cpu instead of the fpga, to run without the hardware
+#[derive(Deserialize, Clone, Debug)]
+pub struct Cpu {
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    // the results easier than this are not reported
+    #[serde(default = "default_cpu_difficulty")]
+    pub difficulty: f64,
+}
+
+// hash with the chains on serial ports instead of the fpga
+#[derive(Deserialize, Clone, Debug)]
+pub struct Serial {
+    pub ports: Vec<String>,
+    // the subwork ids searched back for the subwork of a nonce
+    #[serde(default = "default_serial_window")]
+    pub window: usize,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Board {
     pub enabled: Vec<u16>,
     pub default: BoardSetting,
+    #[serde(default)]
+    pub temp: TempLimits,
     pub _0: Option<BoardSetting>,
     pub _1: Option<BoardSetting>,
     pub _2: Option<BoardSetting>,
@@ -46,12 +148,266 @@ pub struct Board {
     pub _7: Option<BoardSetting>,
 }
 
+// all temperatures in degrees celsius, of the hottest sensor of the board
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct TempLimits {
+    // the voltage is lowered by `voltage-step` above it
+    pub warning: f64,
+    // the board stops hashing and its voltage is turned off above it
+    pub critical: f64,
+    // how far below `warning` the board cools before it is restored
+    pub hysteresis: f64,
+    pub voltage_step: f64,
+    // the hwmon directory of the sensors, `{}` is replaced by the board id
+    pub sensor: Option<String>,
+}
+
+// the fans are driven to keep the hottest chip at `target-temp`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Fan {
+    // the sysfs pwm channel of the fans
+    pub pwm: String,
+    // nanoseconds
+    pub period: u32,
+    // the hwmon tachometers, `{}` is replaced by the fan number from 1
+    pub tach: String,
+    pub count: usize,
+    // degrees celsius
+    pub target_temp: f64,
+    // 0.0 to 1.0
+    pub min_duty: f64,
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    // a fan slower than `min-rpm` for `failure-time` seconds has failed, the boards are stopped
+    pub min_rpm: u32,
+    pub failure_time: f64,
+}
+
+// the boards are stepped through their settings to the best for `target`,
+// which is kept in `profile` and used from boot on
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Tune {
+    pub target: TuneTarget,
+    pub profile: String,
+    // seconds each setting is measured for
+    pub interval: f64,
+    // the settings with more hardware errors are not used, 0.0 to 1.0
+    pub max_hw_error_rate: f64,
+    pub param_step: u32,
+    pub min_param: u32,
+    pub max_param: u32,
+    pub voltage_step: f64,
+    pub min_voltage: f64,
+    pub max_voltage: f64,
+    // the watts of a board are estimated as static-power + power-coefficient * voltage^2 * TH/s
+    pub static_power: f64,
+    pub power_coefficient: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TuneTarget {
+    // the least joules per terahash
+    #[default]
+    Efficiency,
+    Hashrate,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct BoardSetting {
     pub voltage: Option<f32>,
     pub param: Option<u32>,
 }
 
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_threads() -> usize {
+    4
+}
+
+fn default_cpu_difficulty() -> f64 {
+    0.001
+}
+
+fn default_serial_window() -> usize {
+    super::serial::DEFAULT_WINDOW
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Self {
+            kind: StrategyKind::default(),
+            quantum: 10.0,
+        }
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1.0,
+            max_delay: 60.0,
+            multiplier: 2.0,
+            jitter: 0.2,
+            idle_timeout: 60.0,
+            max_wait: 300.0,
+            any_host: false,
+        }
+    }
+}
+
+impl Default for TempLimits {
+    fn default() -> Self {
+        Self {
+            warning: 85.0,
+            critical: 95.0,
+            hysteresis: 5.0,
+            voltage_step: 0.3,
+            sensor: None,
+        }
+    }
+}
+
+impl Default for Fan {
+    fn default() -> Self {
+        Self {
+            pwm: String::from("/sys/class/pwm/pwmchip0/pwm0"),
+            period: 40_000,
+            tach: String::from("/sys/class/hwmon/hwmon0/fan{}_input"),
+            count: 2,
+            target_temp: 75.0,
+            min_duty: 0.2,
+            kp: 0.05,
+            ki: 0.002,
+            kd: 0.0,
+            min_rpm: 1000,
+            failure_time: 10.0,
+        }
+    }
+}
+
+impl Default for Tune {
+    fn default() -> Self {
+        Self {
+            target: TuneTarget::default(),
+            profile: String::from("/etc/stratum/profile.json"),
+            interval: 600.0,
+            max_hw_error_rate: 0.02,
+            param_step: 4,
+            min_param: 92,
+            max_param: 124,
+            voltage_step: 0.1,
+            min_voltage: 8.2,
+            max_voltage: 9.2,
+            static_power: 20.0,
+            power_coefficient: 1.3,
+        }
+    }
+}
+
+impl Config {
+    // the values that would panic or misbehave at runtime are rejected at load time
+    pub fn validate(&self) -> Result<(), String> {
+        self.client.reconnect.validate()?;
+        seconds("strategy quantum", self.strategy.quantum)?;
+        for pool in &self.pool {
+            positive("pool weight", pool.weight)?;
+        }
+        self.board.temp.validate()?;
+        if let Some(ref fan) = self.fan {
+            fan.validate()?;
+        }
+        match self.tune {
+            Some(ref tune) => tune.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Tune {
+    fn validate(&self) -> Result<(), String> {
+        seconds("tune interval", self.interval)?;
+        if self.interval == 0.0 {
+            return Err(String::from("tune interval = 0 measures nothing"));
+        }
+        Ok(())
+    }
+}
+
+impl TempLimits {
+    fn validate(&self) -> Result<(), String> {
+        let finite = self.warning.is_finite() && self.critical.is_finite();
+        if !(finite && self.warning < self.critical) {
+            return Err(format!(
+                "temp warning = {} is not below critical = {}",
+                self.warning, self.critical
+            ));
+        }
+        positive("temp hysteresis", self.hysteresis)?;
+        positive("temp voltage-step", self.voltage_step)
+    }
+}
+
+impl Fan {
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.min_duty) {
+            return Err(format!(
+                "fan min-duty = {} is not in 0.0..=1.0",
+                self.min_duty
+            ));
+        }
+        if !self.target_temp.is_finite() {
+            return Err(format!("fan target-temp = {}", self.target_temp));
+        }
+        positive("fan kp", self.kp)?;
+        positive("fan ki", self.ki)?;
+        positive("fan kd", self.kd)?;
+        seconds("fan failure-time", self.failure_time)
+    }
+}
+
+impl Reconnect {
+    fn validate(&self) -> Result<(), String> {
+        seconds("initial-delay", self.initial_delay)?;
+        seconds("max-delay", self.max_delay)?;
+        seconds("idle-timeout", self.idle_timeout)?;
+        if self.idle_timeout == 0.0 {
+            return Err(String::from("idle-timeout = 0 times out every connection"));
+        }
+        if !(self.multiplier.is_finite() && self.multiplier >= 1.0) {
+            return Err(format!("multiplier = {} is not 1 or more", self.multiplier));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!("jitter = {} is not in 0.0..=1.0", self.jitter));
+        }
+        seconds("max-wait", self.max_wait)
+    }
+}
+
+// a finite value, zero or more
+fn positive(name: &str, value: f64) -> Result<(), String> {
+    if value >= 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} = {} is not zero or more", name, value))
+    }
+}
+
+// a duration in seconds, bounded to keep the deadlines made of it from overflowing
+fn seconds(name: &str, value: f64) -> Result<(), String> {
+    if (0.0..=MAX_SECONDS).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} = {} is not a duration", name, value))
+    }
+}
+
 impl Board {
     pub fn get_setting(&self, id: u16) -> (f32, u32) {
         let mut setting = (8.6, 108);
@@ -88,5 +444,9 @@ pub fn get_config() -> Config {
         .expect("can't open config.toml!")
         .read_to_string(config)
         .expect("can't read config.toml!");
-    toml::from_str(&config).expect("can't parse config.toml!")
+    let config: Config = toml::from_str(&config).expect("can't parse config.toml!");
+    if let Err(e) = config.validate() {
+        panic!("invalid config.toml: {}!", e);
+    }
+    config
 }
@@ -1,5 +1,5 @@
 use std::collections::VecDeque;
-use std::sync::Once;
+use std::io;
 use std::thread;
 use std::time::Duration;
 
@@ -12,72 +12,100 @@ use tokio_uio::Uio;
 
 use crate::work::Subwork2;
 
-use super::{Mmap, ToHex};
+use super::{Interrupts, Mmap, RegisterBlock, RegisterMap, ToHex};
 
-static mut UIO_MMAP: Mmap = unsafe { Mmap::uninit() };
-
-pub fn mmap(offset: usize, size: usize) -> Mmap {
-    static INIT: Once = Once::new();
+// the uio map of the register file, mapped once for all the windows
+fn uio_mmap() -> io::Result<&'static Mmap> {
+    lazy_static! {
+        static ref UIO_MMAP: Result<Mmap, String> = register_map()
+            .map(|map| Mmap::new("/dev/uio0", 0, map.size))
+            .map_err(|e| e.to_string());
+    }
+    UIO_MMAP
+        .as_ref()
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.clone()))
+}
 
-    let uio_mmap = unsafe {
-        INIT.call_once(|| {
-            UIO_MMAP = Mmap::new("/dev/uio0", 0, 161);
-        });
-        &UIO_MMAP
-    };
+pub fn mmap(offset: usize, size: usize) -> io::Result<Mmap> {
+    let uio_mmap = uio_mmap()?;
     assert!(offset + size <= uio_mmap.size());
 
-    unsafe { Mmap::from_raw(uio_mmap.ptr().add(offset), size) }
+    Ok(unsafe { Mmap::from_raw(uio_mmap.ptr().add(offset), size) })
+}
+
+// the registers of the bitstream loaded, v1 for the legacy or an unknown uio device
+pub fn register_map() -> io::Result<&'static RegisterMap> {
+    lazy_static! {
+        static ref REGISTER_MAP: Result<&'static RegisterMap, String> =
+            RegisterMap::detect("/sys/class/uio/uio0")
+                .inspect(|map| info!("=> fpga bitstream: {}!", map.revision));
+    }
+    REGISTER_MAP.clone().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("detect fpga bitstream err: {}", e),
+        )
+    })
+}
+
+// the whole register file of the fpga
+fn registers() -> io::Result<Mmap> {
+    mmap(0, register_map()?.size)
 }
 
 pub struct Csr {
-    mmap: Mmap,
+    block: Box<dyn RegisterBlock>,
 }
 
 pub struct Writer {
-    data: Mmap,
+    data: Box<dyn RegisterBlock>,
     io_select: Csr,
     io_enable: Csr,
     subworks: VecDeque<Subwork2>,
 }
 
 pub struct Reader {
-    data: Mmap,
+    data: Box<dyn RegisterBlock>,
     csr_in: Csr,
+    ack: usize,
+    interrupts: Interrupts,
+}
+
+// a result of the chips, as read from the result registers
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonceResult {
+    pub board: u8,
+    pub chip: u8,
+    pub core: u8,
+    pub nonce: u32,
+    // the version count of the work the nonce was found on
+    pub version_count: u32,
+    pub crc_ok: bool,
 }
 
 pub struct SerialSender {
-    data: Mmap,
+    data: Box<dyn RegisterBlock>,
     csr_in: Csr,
     csr_out: Csr,
     io_select: Csr,
     io_enable: Csr,
+    send_work: usize,
+    notify: usize,
+    ready: usize,
 }
 
-pub fn writer() -> Writer {
-    Writer {
-        data: mmap(0, 80),
-        io_select: Csr::new(mmap(84, 1)),
-        io_enable: Csr::new(mmap(85, 1)),
-        subworks: VecDeque::with_capacity(2),
-    }
+pub fn writer() -> io::Result<Writer> {
+    Ok(Writer::new(register_map()?, &registers()?))
 }
 
-pub fn reader() -> Reader {
-    Reader {
-        data: mmap(148, 13),
-        csr_in: Csr::new(mmap(80, 1)),
-    }
+pub fn reader() -> io::Result<Reader> {
+    let registers = registers()?;
+    let uio = Uio::open("/dev/uio0")?;
+    Ok(Reader::new(register_map()?, &registers, Box::new(uio)))
 }
 
-pub fn serial_sender() -> SerialSender {
-    SerialSender {
-        data: mmap(86, 62),
-        csr_in: Csr::new(mmap(80, 1)),
-        csr_out: Csr::new(mmap(82, 1)),
-        io_select: Csr::new(mmap(84, 1)),
-        io_enable: Csr::new(mmap(85, 1)),
-    }
+pub fn serial_sender() -> io::Result<SerialSender> {
+    Ok(SerialSender::new(register_map()?, &registers()?))
 }
 
 pub fn crc5_false(data: &[u8], offset: usize) -> u8 {
@@ -119,15 +147,39 @@ pub fn version_bits(mut version_mask: u32, mut version_count: u32) -> u32 {
     version_bits.swap_bytes()
 }
 
+impl NonceResult {
+    // 0..4: nonce, 4: chip address, 5: work id of the chip, 6: crc5 (low 5 bits),
+    // 7: work id of the fpga, 8..12: version count of the fpga, 12: board
+    pub fn decode(received: &[u8]) -> Self {
+        assert_eq!(received.len(), 13);
+        let mut nonce = [0; 4];
+        nonce.copy_from_slice(&received[0..4]);
+        let nonce = u32::from_le_bytes(nonce);
+        let mut version_count = [0; 4];
+        version_count.copy_from_slice(&received[8..12]);
+
+        // the chip reports the work it was on, the fpga has moved on since
+        let lag = received[7].wrapping_sub(received[5]) & 0x7f;
+        Self {
+            board: received[12],
+            chip: received[4],
+            // the nonce range of a chip is split among its cores by the top bits
+            core: (nonce >> 25) as u8,
+            nonce,
+            version_count: u32::from_le_bytes(version_count).wrapping_sub(u32::from(lag)),
+            crc_ok: crc5_false(&received[0..7], 5) == received[6] & 0x1f,
+        }
+    }
+}
+
 impl Csr {
-    fn new(mmap: Mmap) -> Self {
-        Self { mmap }
+    fn new(block: Box<dyn RegisterBlock>) -> Self {
+        Self { block }
     }
 
     pub fn set_csr(&mut self, csr: usize, value: bool) {
         assert!(csr < 8);
-        let ptr = self.mmap.ptr();
-        let data = unsafe { ptr.read_volatile() };
+        let data = self.block.read(0);
         let value = if value {
             data | 1 << csr
         } else {
@@ -135,27 +187,23 @@ impl Csr {
         };
 
         if data != value {
-            unsafe {
-                ptr.write_volatile(value);
-            }
+            self.block.write(0, value);
         }
     }
 
     pub fn get_csr(&mut self, csr: usize) -> bool {
         assert!(csr < 8);
 
-        let data = unsafe { self.mmap.ptr().read_volatile() };
+        let data = self.block.read(0);
         let value = 1 << csr;
         data & value == value
     }
 
     pub fn set_all(&mut self, value: bool) {
-        unsafe {
-            if value {
-                self.mmap.ptr().write_volatile(0xff);
-            } else {
-                self.mmap.ptr().write_volatile(0);
-            }
+        if value {
+            self.block.write(0, 0xff);
+        } else {
+            self.block.write(0, 0);
         }
     }
 
@@ -167,27 +215,31 @@ impl Csr {
 }
 
 impl Writer {
-    pub fn writer_subwork2(&mut self, sw2: Subwork2) {
-        unsafe {
-            self.data.write_u32(0, sw2.version.to_be());
-            self.data.write_u32(4, sw2.vermask.to_be());
-            debug_assert_eq!(sw2.prevhash.len(), 32);
-            self.data.write_as_u32(8, &sw2.prevhash);
-            debug_assert_eq!(sw2.merkle_root.len(), 32);
-            self.data.write_as_u32(40, &sw2.merkle_root);
-            debug_assert_eq!(sw2.ntime.len(), 4);
-            self.data.write_as_u32(72, &sw2.ntime);
-            debug_assert_eq!(sw2.nbits.len(), 4);
-            self.data.write_as_u32(76, &sw2.nbits);
+    pub fn new(map: &RegisterMap, registers: &dyn RegisterBlock) -> Self {
+        Self {
+            data: map.work.window(registers),
+            io_select: Csr::new(map.io_select.window(registers)),
+            io_enable: Csr::new(map.io_enable.window(registers)),
+            subworks: VecDeque::with_capacity(2),
         }
+    }
+
+    pub fn writer_subwork2(&mut self, sw2: Subwork2) {
+        self.data.write_u32(0, sw2.version.to_be());
+        self.data.write_u32(4, sw2.vermask.to_be());
+        debug_assert_eq!(sw2.prevhash.len(), 32);
+        self.data.write_as_u32(8, &sw2.prevhash);
+        debug_assert_eq!(sw2.merkle_root.len(), 32);
+        self.data.write_as_u32(40, &sw2.merkle_root);
+        debug_assert_eq!(sw2.ntime.len(), 4);
+        self.data.write_as_u32(72, &sw2.ntime);
+        debug_assert_eq!(sw2.nbits.len(), 4);
+        self.data.write_as_u32(76, &sw2.nbits);
 
         self.subworks.push_front(sw2);
         self.subworks.truncate(2);
 
-        debug!(
-            "written work: {}",
-            self.data.read(0, 80).collect::<Vec<u8>>().to_hex()
-        );
+        debug!("written work: {}", self.data.read_bytes(0, 80).to_hex());
     }
 
     pub fn enable_sender(&mut self, board: usize) {
@@ -195,26 +247,46 @@ impl Writer {
         self.io_enable.set_csr(board, true);
     }
 
+    // the board gets no more work
+    pub fn disable_sender(&mut self, board: usize) {
+        self.io_enable.set_csr(board, false);
+    }
+
     pub fn subworks(&self) -> Vec<Subwork2> {
         self.subworks.iter().cloned().collect()
     }
 }
 
 impl Reader {
-    pub fn read_nonce(mut self) -> (impl Future<Item = (), Error = ()> + Send, Receiver<Bytes>) {
+    // `interrupts` tell the result registers are filled
+    pub fn new(map: &RegisterMap, registers: &dyn RegisterBlock, interrupts: Interrupts) -> Self {
+        Self {
+            data: map.result.window(registers),
+            csr_in: Csr::new(map.csr_in.window(registers)),
+            ack: map.csr_in.bits.ack,
+            interrupts,
+        }
+    }
+
+    pub fn read_nonce(self) -> (impl Future<Item = (), Error = ()> + Send, Receiver<Bytes>) {
         let (sender, receiver) = channel(32);
 
-        let nonce_reader = Uio::open("/dev/uio0")
-            .expect("open /dev/uio0 err!")
+        let Self {
+            data,
+            mut csr_in,
+            ack,
+            interrupts,
+        } = self;
+        let nonce_reader = interrupts
             .map(move |n| {
                 trace!("received interrupt: {}!", n);
 
                 let mut nonce = BytesMut::with_capacity(13);
-                for v in unsafe { self.data.read_u32(0, 12) } {
-                    nonce.put_u32_le(v);
+                for offset in (0..12).step_by(4) {
+                    nonce.put_u32_le(data.read_u32(offset));
                 }
-                nonce.put_u8(unsafe { self.data.ptr().add(12).read_volatile() });
-                self.csr_in.notify(3);
+                nonce.put_u8(data.read(12));
+                csr_in.notify(ack);
 
                 trace!("read from fpga: {}", nonce.to_hex());
                 nonce.freeze()
@@ -228,6 +300,19 @@ impl Reader {
 }
 
 impl SerialSender {
+    pub fn new(map: &RegisterMap, registers: &dyn RegisterBlock) -> Self {
+        Self {
+            data: map.serial.window(registers),
+            csr_in: Csr::new(map.csr_in.window(registers)),
+            csr_out: Csr::new(map.csr_out.window(registers)),
+            io_select: Csr::new(map.io_select.window(registers)),
+            io_enable: Csr::new(map.io_enable.window(registers)),
+            send_work: map.csr_in.bits.send_work,
+            notify: map.csr_in.bits.notify,
+            ready: map.csr_out.bits.ready,
+        }
+    }
+
     pub fn select_board(&mut self, board: usize) {
         self.io_select.set_all(false);
         self.io_select.set_csr(board, true);
@@ -240,11 +325,11 @@ impl SerialSender {
     }
 
     pub fn set_direct(&mut self) {
-        self.csr_in.set_csr(0, false);
+        self.csr_in.set_csr(self.send_work, false);
     }
 
     pub fn set_send_work(&mut self) {
-        self.csr_in.set_csr(0, true);
+        self.csr_in.set_csr(self.send_work, true);
     }
 
     pub fn unselect_all(&mut self) {
@@ -256,11 +341,11 @@ impl SerialSender {
         self.set_send_work();
 
         loop {
-            if self.csr_out.get_csr(0) {
+            if self.csr_out.get_csr(self.ready) {
                 // set interval
-                self.data.write(0, interval.to_le_bytes());
-                self.data.write(2, work);
-                self.csr_in.notify(1);
+                self.data.write_bytes(0, &interval.to_le_bytes());
+                self.data.write_bytes(2, work);
+                self.csr_in.notify(self.notify);
                 break;
             } else {
                 thread::sleep(Duration::from_micros(10))
@@ -269,8 +354,8 @@ impl SerialSender {
     }
 
     pub fn get_count(&mut self) -> u32 {
-        let mut count = Vec::with_capacity(4);
-        count.extend(self.data.read(58, 4));
-        u32::from_le_bytes(unsafe { *(count.as_ptr() as *const [u8; 4]) })
+        let mut count = [0; 4];
+        count.copy_from_slice(&self.data.read_bytes(58, 4));
+        u32::from_le_bytes(count)
     }
 }
@@ -1,8 +1,11 @@
-use std::any::Any;
+use std::fmt;
 use std::fs::File;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Range;
 use std::path::Path;
+use std::str::FromStr;
 
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
 use i2c_linux::{Message, ReadFlags, WriteFlags};
 
 use self::Command::*;
@@ -10,6 +13,7 @@ use self::Command::*;
 pub type I2c = i2c_linux::I2c<File>;
 
 #[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     SET_PIC_FLASH_POINTER = 0x01,
     SEND_DATA_TO_IIC = 0x02,
@@ -36,6 +40,128 @@ pub enum Command {
     RD_TEMP_OFFSET_VALUE = 0x23,
 }
 
+impl Command {
+    pub fn from_u8(cmd: u8) -> Option<Command> {
+        let commands = [
+            SET_PIC_FLASH_POINTER,
+            SEND_DATA_TO_IIC,
+            READ_DATA_FROM_IIC,
+            ERASE_IIC_FLASH,
+            WRITE_DATA_INTO_PIC,
+            JUMP_FROM_LOADER_TO_APP,
+            RESET_PIC,
+            GET_PIC_FLASH_POINTER,
+            ERASE_PIC_APP_PROGRAM,
+            SET_VOLTAGE,
+            SET_VOLTAGE_TIME,
+            SET_HASH_BOARD_ID,
+            GET_HASH_BOARD_ID,
+            SET_HOST_MAC_ADDRESS,
+            ENABLE_VOLTAGE,
+            SEND_HEART_BEAT,
+            GET_PIC_SOFTWARE_VERSION,
+            GET_VOLTAGE,
+            GET_DATE,
+            GET_WHICH_MAC,
+            GET_MAC,
+            WR_TEMP_OFFSET_VALUE,
+            RD_TEMP_OFFSET_VALUE,
+        ];
+        commands.iter().cloned().find(|x| *x as u8 == cmd)
+    }
+}
+
+// the eeprom of the board, the last bytes of the flash of the pic, written by the app only
+pub const EEPROM_POINTER: u16 = 0x0780;
+pub const EEPROM_SIZE: usize = 256;
+
+// where the app keeps the board data in the eeprom, in bytes from its start
+pub const EEPROM_ID: Range<usize> = 0..12;
+pub const EEPROM_DATE: Range<usize> = 12..15;
+pub const EEPROM_WHICH_MAC: usize = 15;
+pub const EEPROM_MAC: Range<usize> = 16..22;
+pub const EEPROM_HOST_MAC: Range<usize> = 22..28;
+pub const EEPROM_TEMP_OFFSET: Range<usize> = 28..36;
+
+// the serial number of a hash board, ascii padded with zeros
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HashBoardId(pub [u8; 12]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for HashBoardId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let len = self.0.iter().position(|x| *x == 0).unwrap_or(12);
+        write!(f, "{}", String::from_utf8_lossy(&self.0[..len]))
+    }
+}
+
+impl FromStr for HashBoardId {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.len() > 12 || !s.is_ascii() {
+            return Err(format!("bad hash board id: {}", s));
+        }
+        let mut id = [0; 12];
+        id[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(HashBoardId(id))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = &self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            m[0], m[1], m[2], m[3], m[4], m[5]
+        )
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("bad mac address: {}", s);
+        let mut mac = [0; 6];
+        let mut parts = s.split(':');
+        for v in mac.iter_mut() {
+            let part = parts.next().filter(|x| x.len() == 2).ok_or_else(err)?;
+            *v = u8::from_str_radix(part, 16).map_err(|_| err())?;
+        }
+        match parts.next() {
+            Some(_) => Err(err()),
+            None => Ok(MacAddress(mac)),
+        }
+    }
+}
+
+// the dates are kept by the pic as years since 2000, month and day
+fn decode_date(date: [u8; 3]) -> Result<NaiveDate> {
+    NaiveDate::from_ymd_opt(2000 + i32::from(date[0]), date[1].into(), date[2].into())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("bad date: {:02x?}", date)))
+}
+
+fn encode_time(time: NaiveDateTime) -> Result<[u8; 6]> {
+    if time.year() < 2000 || time.year() > 2255 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("bad time: {}", time),
+        ));
+    }
+    Ok([
+        (time.year() - 2000) as u8,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    ])
+}
+
 pub fn open<T: AsRef<Path>>(path: T) -> I2c {
     let device = File::open(&path)
         .unwrap_or_else(|_| panic!(format!("can't open {}!", path.as_ref().display())));
@@ -43,7 +169,12 @@ pub fn open<T: AsRef<Path>>(path: T) -> I2c {
     I2c::new(device)
 }
 
-pub trait SendCommand: Any + Sized {
+// the bus the pics are on, /dev/i2c-0 or a simulated one
+pub trait Transport {
+    fn transfer(&mut self, messages: &mut [Message<'_>]) -> Result<()>;
+}
+
+pub trait SendCommand: Transport {
     fn send_command(
         &mut self,
         addr: u16,
@@ -85,10 +216,7 @@ pub trait SendCommand: Any + Sized {
             }
         }
 
-        match Any::downcast_mut::<I2c>(self) {
-            Some(i2c) => i2c.i2c_transfer(&mut massages),
-            None => unreachable!(),
-        }
+        self.transfer(&mut massages)
     }
 }
 
@@ -149,10 +277,73 @@ pub trait BoardConfig: SendCommand {
         Ok(data)
     }
 
+    // the loader only, the app program is erased before it is written
+    fn erase_pic_app_program(&mut self, addr: u16) -> Result<()> {
+        self.send_command(addr, ERASE_PIC_APP_PROGRAM, None, false)
+    }
+
+    // fill the write buffer of the loader
+    fn send_data_to_iic(&mut self, addr: u16, mut data: [u8; 16]) -> Result<()> {
+        self.send_command(addr, SEND_DATA_TO_IIC, Some(&mut data), false)
+    }
+
+    // write the buffer at the flash pointer, which moves past it
+    fn write_data_into_pic(&mut self, addr: u16) -> Result<()> {
+        self.send_command(addr, WRITE_DATA_INTO_PIC, None, false)
+    }
+
     fn send_heart_beat(&mut self, addr: u16) -> Result<()> {
         self.send_command(addr, SEND_HEART_BEAT, None, false)
     }
 
+    // when the voltage was last set, kept by the pic
+    fn set_voltage_time(&mut self, addr: u16, time: NaiveDateTime) -> Result<()> {
+        let mut time = encode_time(time)?;
+        self.send_command(addr, SET_VOLTAGE_TIME, Some(&mut time), false)
+    }
+
+    fn set_hash_board_id(&mut self, addr: u16, id: &HashBoardId) -> Result<()> {
+        let mut id = id.0;
+        self.send_command(addr, SET_HASH_BOARD_ID, Some(&mut id), false)
+    }
+
+    fn get_hash_board_id(&mut self, addr: u16) -> Result<HashBoardId> {
+        let mut id = [0; 12];
+        self.send_command(addr, GET_HASH_BOARD_ID, Some(&mut id), true)?;
+        Ok(HashBoardId(id))
+    }
+
+    // the mac of the control board the hash board runs with
+    fn set_host_mac_address(&mut self, addr: u16, mac: MacAddress) -> Result<()> {
+        let mut mac = mac.0;
+        self.send_command(addr, SET_HOST_MAC_ADDRESS, Some(&mut mac), false)
+    }
+
+    // the date the board was made
+    fn get_date(&mut self, addr: u16) -> Result<NaiveDate> {
+        let mut date = [0; 3];
+        self.send_command(addr, GET_DATE, Some(&mut date), true)?;
+        decode_date(date)
+    }
+
+    // the slot of the mac kept by the pic
+    fn get_which_mac(&mut self, addr: u16) -> Result<u8> {
+        let mut which = [0];
+        self.send_command(addr, GET_WHICH_MAC, Some(&mut which), true)?;
+        Ok(which[0])
+    }
+
+    fn get_mac(&mut self, addr: u16) -> Result<MacAddress> {
+        let mut mac = [0; 6];
+        self.send_command(addr, GET_MAC, Some(&mut mac), true)?;
+        Ok(MacAddress(mac))
+    }
+
+    // the eeprom of the board, the id, the date and the macs included
+    fn erase_iic_flash(&mut self, addr: u16) -> Result<()> {
+        self.send_command(addr, ERASE_IIC_FLASH, None, false)
+    }
+
     fn get_temp_offset(&mut self, addr: u16) -> Result<u64> {
         let mut offset = [0; 8];
         self.send_command(addr, RD_TEMP_OFFSET_VALUE, Some(&mut offset), true)?;
@@ -169,6 +360,12 @@ pub trait BoardConfig: SendCommand {
     }
 }
 
-impl SendCommand for I2c {}
+impl Transport for I2c {
+    fn transfer(&mut self, messages: &mut [Message<'_>]) -> Result<()> {
+        self.i2c_transfer(messages)
+    }
+}
+
+impl<T: Transport> SendCommand for T {}
 
-impl BoardConfig for I2c {}
+impl<T: SendCommand> BoardConfig for T {}
@@ -22,13 +22,6 @@ pub struct ReadMmap<'a, T> {
 }
 
 impl Mmap {
-    pub const unsafe fn uninit() -> Self {
-        Self {
-            ptr: null_mut(),
-            size: 0,
-        }
-    }
-
     pub fn new<T: AsRef<Path>>(path: T, offset: off_t, size: size_t) -> Self {
         let f = OpenOptions::new()
             .read(true)
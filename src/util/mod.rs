@@ -7,11 +7,17 @@ use fern::{Dispatch, InitError};
 use sha256::Sha256;
 
 pub use self::{
-    config::{get_config, Client, Config},
+    config::{
+        get_config, Channel, Client, Config, Cpu, Fan, Protocol, Reconnect, Serial, StalePolicy,
+        Strategy, StrategyKind, TempLimits, Tune, TuneTarget,
+    },
     hex::{FromHex, ToHex},
     i2c::BoardConfig,
     mmap::Mmap,
     notify::Notify,
+    registers::{Interrupts, RegisterBlock, SimRegisters, WriteHook},
+    regmap::{Access, Bitfield, CsrIn, CsrOut, Register, RegisterMap, Typed},
+    simpic::{PicState, SimPic},
     sinkhook::SinkHook,
 };
 
@@ -21,8 +27,13 @@ mod hex;
 pub mod i2c;
 mod mmap;
 mod notify;
+mod registers;
+mod regmap;
 pub mod serial;
+mod simpic;
 mod sinkhook;
+#[cfg(test)]
+mod tests;
 
 trait __Flip32: Sized {
     fn __flip32(&mut self);
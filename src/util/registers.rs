@@ -0,0 +1,186 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::Stream;
+
+use super::Mmap;
+
+// the interrupt counts of the register block, as read from the uio device
+pub type Interrupts = Box<dyn Stream<Item = u32, Error = io::Error> + Send>;
+
+// called after every write with the whole register file and the offset written
+pub type WriteHook = Box<dyn FnMut(&mut [u8], usize) + Send>;
+
+// a block of byte-addressed registers, the u32 accesses are native endian and aligned
+pub trait RegisterBlock: Send {
+    fn size(&self) -> usize;
+
+    fn read(&self, offset: usize) -> u8;
+
+    fn write(&mut self, offset: usize, value: u8);
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        for (i, v) in bytes.iter_mut().enumerate() {
+            *v = self.read(offset + i);
+        }
+        u32::from_ne_bytes(bytes)
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        for (i, v) in value.to_ne_bytes().iter().enumerate() {
+            self.write(offset + i, *v);
+        }
+    }
+
+    // the registers of `offset..offset + size`, sharing the same storage
+    fn window(&self, offset: usize, size: usize) -> Box<dyn RegisterBlock>;
+
+    fn read_bytes(&self, offset: usize, size: usize) -> Vec<u8> {
+        (offset..offset + size).map(|x| self.read(x)).collect()
+    }
+
+    fn write_bytes(&mut self, offset: usize, data: &[u8]) {
+        for (i, v) in data.iter().enumerate() {
+            self.write(offset + i, *v);
+        }
+    }
+
+    // write `data` with u32 accesses, the bytes keep their order
+    fn write_as_u32(&mut self, offset: usize, data: &[u8]) {
+        debug_assert_eq!(data.len() & 0b11, 0);
+        for (i, v) in data.chunks(4).enumerate() {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(v);
+            self.write_u32(offset + i * 4, u32::from_ne_bytes(bytes));
+        }
+    }
+}
+
+impl RegisterBlock for Mmap {
+    fn size(&self) -> usize {
+        Mmap::size(self)
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        assert!(offset < Mmap::size(self));
+        unsafe { self.ptr().add(offset).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        assert!(offset < Mmap::size(self));
+        unsafe { self.ptr().add(offset).write_volatile(value) }
+    }
+
+    #[allow(clippy::cast_ptr_alignment)]
+    fn read_u32(&self, offset: usize) -> u32 {
+        assert!(offset + 4 <= Mmap::size(self));
+        debug_assert_eq!((self.ptr() as usize + offset) & 0b11, 0);
+        unsafe { (self.ptr().add(offset) as *const u32).read_volatile() }
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        assert!(offset + 4 <= Mmap::size(self));
+        unsafe { Mmap::write_u32(self, offset, value) }
+    }
+
+    fn window(&self, offset: usize, size: usize) -> Box<dyn RegisterBlock> {
+        assert!(offset + size <= Mmap::size(self));
+        Box::new(unsafe { Mmap::from_raw(self.ptr().add(offset), size) })
+    }
+}
+
+struct SimState {
+    data: Vec<u8>,
+    hook: Option<WriteHook>,
+    interrupts: Vec<UnboundedSender<u32>>,
+    interrupt_count: u32,
+}
+
+// an in-memory register file, the interrupts are raised by the test or the emulator
+#[derive(Clone)]
+pub struct SimRegisters {
+    state: Arc<Mutex<SimState>>,
+    offset: usize,
+    size: usize,
+}
+
+impl SimRegisters {
+    pub fn new(size: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimState {
+                data: vec![0; size],
+                hook: None,
+                interrupts: Vec::new(),
+                interrupt_count: 0,
+            })),
+            offset: 0,
+            size,
+        }
+    }
+
+    // the emulator of the hardware behind the registers
+    pub fn on_write<F: FnMut(&mut [u8], usize) + Send + 'static>(&self, hook: F) {
+        self.state.lock().unwrap().hook = Some(Box::new(hook));
+    }
+
+    // stands in for the uio device of the registers
+    pub fn interrupts(&self) -> Interrupts {
+        let (sender, receiver) = unbounded();
+        self.state.lock().unwrap().interrupts.push(sender);
+        Box::new(receiver.map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe)))
+    }
+
+    pub fn interrupt(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.interrupt_count = state.interrupt_count.wrapping_add(1);
+        let count = state.interrupt_count;
+        state
+            .interrupts
+            .retain(|sender| sender.unbounded_send(count).is_ok());
+    }
+
+    // set the registers as the hardware does, then interrupt
+    pub fn raise(&self, offset: usize, data: &[u8]) {
+        self.state.lock().unwrap().data[self.offset + offset..][..data.len()].copy_from_slice(data);
+        self.interrupt();
+    }
+
+    // the registers as they are, without going through the hook
+    pub fn snapshot(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        state.data[self.offset..self.offset + self.size].to_vec()
+    }
+}
+
+impl RegisterBlock for SimRegisters {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        assert!(offset < self.size);
+        self.state.lock().unwrap().data[self.offset + offset]
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        assert!(offset < self.size);
+        let offset = self.offset + offset;
+
+        let state = &mut *self.state.lock().unwrap();
+        state.data[offset] = value;
+        if let Some(ref mut hook) = state.hook {
+            hook(&mut state.data, offset);
+        }
+    }
+
+    fn window(&self, offset: usize, size: usize) -> Box<dyn RegisterBlock> {
+        assert!(offset + size <= self.size);
+        Box::new(Self {
+            state: self.state.clone(),
+            offset: self.offset + offset,
+            size,
+        })
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+
+use super::RegisterBlock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug)]
+pub struct Bitfield {
+    pub name: &'static str,
+    pub bit: usize,
+}
+
+#[derive(Debug)]
+pub struct Register {
+    pub name: &'static str,
+    pub offset: usize,
+    // in bytes
+    pub width: usize,
+    // the writes to a read-only register are refused
+    pub access: Access,
+    pub bitfields: &'static [Bitfield],
+}
+
+// a register with the bits of its bitfields by name
+#[derive(Debug)]
+pub struct Typed<B> {
+    pub register: Register,
+    pub bits: B,
+}
+
+// the window of a read-only register, writing it is a bug
+struct ReadOnly {
+    name: &'static str,
+    block: Box<dyn RegisterBlock>,
+}
+
+// the revisions of the register map, each register as `name (offset, width, access)`, followed by
+// `Bits { bitfield: bit, .. }` when it has bitfields, a `Bits` struct is made of the bitfields of
+// the first revision and the register is then a `Typed<Bits>`
+macro_rules! register_map {
+    ($first:ident { $($first_body:tt)* } $($rest:ident { $($rest_body:tt)* })*) => {
+        register_map!(@types $($first_body)*);
+
+        impl RegisterMap {
+            register_map!(@revision $first $($first_body)*);
+            $(register_map!(@revision $rest $($rest_body)*);)*
+
+            // the known revisions
+            pub const REVISIONS: &'static [&'static RegisterMap] =
+                &[&RegisterMap::$first $(, &RegisterMap::$rest)*];
+        }
+    };
+    (
+        @types revision: $revision:expr, uio_name: $uio_name:expr, size: $size:expr,
+        $($name:ident ($offset:expr, $width:expr, $access:ident)
+            $($bits:ident { $($bit:ident: $position:expr),* })?,)*
+    ) => {
+        $($(
+            #[derive(Debug)]
+            pub struct $bits {
+                $(pub $bit: usize,)*
+            }
+        )?)*
+
+        // the registers of a bitstream revision
+        #[derive(Debug)]
+        pub struct RegisterMap {
+            pub revision: &'static str,
+            // the name of the uio device of the bitstream, None for the unnamed legacy one
+            pub uio_name: Option<&'static str>,
+            // the bytes used of the uio map
+            pub size: usize,
+            $(pub $name: register_map!(@type $($bits)?),)*
+        }
+
+        impl RegisterMap {
+            pub fn registers(&self) -> Vec<&Register> {
+                vec![$(register_map!(@register self.$name $(, $bits)?),)*]
+            }
+        }
+    };
+    (
+        @revision $const:ident revision: $revision:expr, uio_name: $uio_name:expr,
+        size: $size:expr,
+        $($name:ident ($offset:expr, $width:expr, $access:ident)
+            $($bits:ident { $($bit:ident: $position:expr),* })?,)*
+    ) => {
+        pub const $const: RegisterMap = RegisterMap {
+            revision: $revision,
+            uio_name: $uio_name,
+            size: $size,
+            $($name: register_map!(
+                @value $name ($offset, $width, $access) $($bits { $($bit: $position),* })?
+            ),)*
+        };
+    };
+    (@type) => { Register };
+    (@type $bits:ident) => { Typed<$bits> };
+    (@register $register:expr) => { &$register };
+    (@register $register:expr, $bits:ident) => { &$register.register };
+    (@value $name:ident ($offset:expr, $width:expr, $access:ident)) => {
+        Register {
+            name: stringify!($name),
+            offset: $offset,
+            width: $width,
+            access: Access::$access,
+            bitfields: &[],
+        }
+    };
+    (
+        @value $name:ident ($offset:expr, $width:expr, $access:ident)
+        $bits:ident { $($bit:ident: $position:expr),* }
+    ) => {
+        Typed {
+            register: Register {
+                name: stringify!($name),
+                offset: $offset,
+                width: $width,
+                access: Access::$access,
+                bitfields: &[$(Bitfield {
+                    name: stringify!($bit),
+                    bit: $position,
+                }),*],
+            },
+            bits: $bits {
+                $($bit: $position,)*
+            },
+        }
+    };
+}
+
+register_map! {
+    V1 {
+        revision: "v1",
+        uio_name: None,
+        size: 161,
+        // the block header to hash
+        work (0, 80, ReadWrite),
+        csr_in (80, 1, ReadWrite) CsrIn { send_work: 0, notify: 1, ack: 3 },
+        csr_out (82, 1, ReadOnly) CsrOut { ready: 0 },
+        // a bit per board
+        io_select (84, 1, ReadWrite),
+        io_enable (85, 1, ReadWrite),
+        // the interval (u16), the serial work (56 bytes) and the sent count (u32)
+        serial (86, 62, ReadWrite),
+        // the nonce found by the chips
+        result (148, 13, ReadOnly),
+    }
+}
+
+impl Register {
+    pub fn window(&self, registers: &dyn RegisterBlock) -> Box<dyn RegisterBlock> {
+        let block = registers.window(self.offset, self.width);
+        match self.access {
+            Access::ReadWrite => block,
+            Access::ReadOnly => Box::new(ReadOnly {
+                name: self.name,
+                block,
+            }),
+        }
+    }
+}
+
+impl<B> Deref for Typed<B> {
+    type Target = Register;
+
+    fn deref(&self) -> &Register {
+        &self.register
+    }
+}
+
+impl RegisterBlock for ReadOnly {
+    fn size(&self) -> usize {
+        self.block.size()
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        self.block.read(offset)
+    }
+
+    fn write(&mut self, offset: usize, _value: u8) {
+        panic!("write to read-only register {} at {}!", self.name, offset);
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        self.block.read_u32(offset)
+    }
+
+    fn window(&self, offset: usize, size: usize) -> Box<dyn RegisterBlock> {
+        Box::new(ReadOnly {
+            name: self.name,
+            block: self.block.window(offset, size),
+        })
+    }
+}
+
+impl RegisterMap {
+    // the revision loaded, told by the uio device in sysfs (e.g. /sys/class/uio/uio0), the
+    // unnamed legacy one if the name is unknown
+    pub fn detect<T: AsRef<Path>>(uio: T) -> Result<&'static RegisterMap, String> {
+        let read = |file: &str| {
+            let path = uio.as_ref().join(file);
+            fs::read_to_string(&path)
+                .map(|x| x.trim().to_string())
+                .map_err(|e| format!("can't read {}: {}", path.display(), e))
+        };
+        let name = read("name")?;
+        let size = read("maps/map0/size")?;
+        let size = usize::from_str_radix(size.trim_start_matches("0x"), 16)
+            .map_err(|e| format!("bad map size {}: {}", size, e))?;
+
+        let named = Self::REVISIONS
+            .iter()
+            .find(|x| x.uio_name == Some(name.as_str()));
+        let map = match named {
+            Some(map) => map,
+            None => {
+                let legacy = Self::REVISIONS
+                    .iter()
+                    .find(|x| x.uio_name.is_none())
+                    .ok_or_else(|| format!("unknown bitstream: {}", name))?;
+                warn!("unknown fpga uio {}, assume {}!", name, legacy.revision);
+                legacy
+            }
+        };
+
+        if map.size > size {
+            return Err(format!(
+                "map of {} is {} bytes, {} needs {}",
+                name, size, map.revision, map.size
+            ));
+        }
+        Ok(map)
+    }
+}
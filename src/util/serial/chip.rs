@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use super::crc5_usb;
+use crate::util::ToHex;
+
+// the registers of the chips
+pub const CHIP_ADDRESS: u8 = 0x00;
+pub const HASH_RATE: u8 = 0x08;
+pub const PLL_PARAMETER: u8 = 0x0c;
+pub const MISC_CONTROL: u8 = 0x1c;
+
+// the chips run off a 25 mhz clock
+pub const CLOCK: f64 = 25.0;
+
+// the frequencies the pll is set to, mhz
+pub const MIN_FREQ: f64 = 100.0;
+pub const MAX_FREQ: f64 = 1200.0;
+
+// as many as the addresses
+pub const MAX_CHIPS: usize = 256;
+
+// the answer to a register read: 0xaa, the value (big endian), the chip, the register, crc5
+pub const RESPONSE_SIZE: usize = 8;
+
+const TYPE_COMMAND: u8 = 0x40;
+const TO_ALL: u8 = 0x10;
+const SET_ADDRESS: u8 = 0x01;
+const READ_REGISTER: u8 = 0x04;
+const CHAIN_INACTIVE: u8 = 0x05;
+const WRITE_REGISTER: u8 = 0x08;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChipCommand {
+    // the chips stop passing on the addresses, the first one not addressed takes the next
+    ChainInactive,
+    SetAddress(u8),
+    // `chip` None is every chip
+    ReadRegister {
+        chip: Option<u8>,
+        register: u8,
+    },
+    WriteRegister {
+        chip: Option<u8>,
+        register: u8,
+        value: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterResponse {
+    pub chip: u8,
+    pub register: u8,
+    pub value: u32,
+}
+
+// the frequency is `CLOCK * fbdiv / (refdiv * postdiv1 * postdiv2)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pll {
+    pub fbdiv: u8,
+    pub refdiv: u8,
+    pub postdiv1: u8,
+    pub postdiv2: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chip {
+    pub address: u8,
+    pub registers: BTreeMap<u8, u32>,
+}
+
+// the chips on a serial port
+pub struct Chain<T> {
+    port: T,
+    chips: Vec<Chip>,
+}
+
+fn invalid_input<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidInput, e.to_string())
+}
+
+fn crc5_ok(frame: &[u8]) -> bool {
+    match frame.split_last() {
+        Some((crc, data)) => crc5_usb(data) == crc & 0x1f,
+        None => false,
+    }
+}
+
+impl ChipCommand {
+    pub fn set_pll(chip: Option<u8>, pll: Pll) -> Self {
+        ChipCommand::WriteRegister {
+            chip,
+            register: PLL_PARAMETER,
+            value: pll.value(),
+        }
+    }
+
+    // every chip, the port has to be set to the same baud rate after
+    pub fn set_baud(baud: u32) -> Result<Self> {
+        Ok(ChipCommand::WriteRegister {
+            chip: None,
+            register: MISC_CONTROL,
+            value: u32::from(baud_divisor(baud)?) << 8,
+        })
+    }
+
+    // the command, the length of the frame, the chip, the register, the value if written, crc5
+    pub fn encode(&self) -> Vec<u8> {
+        let to = |chip: Option<u8>| chip.map_or(TO_ALL, |_| 0);
+        let mut frame = match *self {
+            ChipCommand::ChainInactive => vec![TYPE_COMMAND | TO_ALL | CHAIN_INACTIVE, 5, 0, 0],
+            ChipCommand::SetAddress(address) => vec![TYPE_COMMAND | SET_ADDRESS, 5, address, 0],
+            ChipCommand::ReadRegister { chip, register } => vec![
+                TYPE_COMMAND | to(chip) | READ_REGISTER,
+                5,
+                chip.unwrap_or(0),
+                register,
+            ],
+            ChipCommand::WriteRegister {
+                chip,
+                register,
+                value,
+            } => {
+                let mut frame = vec![
+                    TYPE_COMMAND | to(chip) | WRITE_REGISTER,
+                    9,
+                    chip.unwrap_or(0),
+                    register,
+                ];
+                frame.extend_from_slice(&value.to_be_bytes());
+                frame
+            }
+        };
+        frame.push(crc5_usb(&frame));
+        frame
+    }
+
+    // a whole frame as encoded, None if it is not one
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        if frame.len() < 5 || usize::from(frame[1]) != frame.len() || !crc5_ok(frame) {
+            return None;
+        }
+        let (cmd, chip, register) = (frame[0], frame[2], frame[3]);
+        let chip = if cmd & TO_ALL == 0 { Some(chip) } else { None };
+        let command = match (cmd & !TO_ALL, frame.len()) {
+            (x, 5) if x == TYPE_COMMAND | CHAIN_INACTIVE => ChipCommand::ChainInactive,
+            (x, 5) if x == TYPE_COMMAND | SET_ADDRESS => ChipCommand::SetAddress(frame[2]),
+            (x, 5) if x == TYPE_COMMAND | READ_REGISTER => {
+                ChipCommand::ReadRegister { chip, register }
+            }
+            (x, 9) if x == TYPE_COMMAND | WRITE_REGISTER => ChipCommand::WriteRegister {
+                chip,
+                register,
+                value: u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+            },
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+impl RegisterResponse {
+    pub fn encode(&self) -> [u8; RESPONSE_SIZE] {
+        let mut frame = [0xaa, 0, 0, 0, 0, self.chip, self.register, 0];
+        frame[1..5].copy_from_slice(&self.value.to_be_bytes());
+        frame[7] = crc5_usb(&frame[..7]);
+        frame
+    }
+
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        if frame.len() != RESPONSE_SIZE || frame[0] != 0xaa || !crc5_ok(frame) {
+            return None;
+        }
+        Some(Self {
+            chip: frame[5],
+            register: frame[6],
+            value: u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]),
+        })
+    }
+}
+
+impl Pll {
+    // the nearest to `freq` mhz the pll can do
+    pub fn from_freq(freq: f64) -> Result<Self> {
+        if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+            return Err(invalid_input(format!(
+                "frequency {} mhz out of range",
+                freq
+            )));
+        }
+        let mut best: Option<Pll> = None;
+        for refdiv in 1..=2 {
+            for postdiv1 in 1..=7 {
+                for postdiv2 in 1..=postdiv1 {
+                    let div = f64::from(refdiv * postdiv1 * postdiv2);
+                    let fbdiv = (freq * div / CLOCK).round().clamp(16.0, 255.0) as u8;
+                    let pll = Pll {
+                        fbdiv,
+                        refdiv,
+                        postdiv1,
+                        postdiv2,
+                    };
+                    if best.is_none_or(|x| (pll.freq() - freq).abs() < (x.freq() - freq).abs()) {
+                        best = Some(pll);
+                    }
+                }
+            }
+        }
+        Ok(best.unwrap())
+    }
+
+    pub fn freq(&self) -> f64 {
+        let div = f64::from(self.refdiv) * f64::from(self.postdiv1) * f64::from(self.postdiv2);
+        CLOCK * f64::from(self.fbdiv) / div
+    }
+
+    // as written to `PLL_PARAMETER`
+    pub fn value(&self) -> u32 {
+        u32::from(self.fbdiv) << 16
+            | u32::from(self.refdiv) << 8
+            | u32::from(self.postdiv1) << 4
+            | u32::from(self.postdiv2)
+    }
+
+    pub fn from_value(value: u32) -> Self {
+        Self {
+            fbdiv: (value >> 16) as u8,
+            refdiv: (value >> 8) as u8,
+            postdiv1: (value >> 4) as u8 & 0x0f,
+            postdiv2: value as u8 & 0x0f,
+        }
+    }
+}
+
+// the uart of the chips runs at `CLOCK / (8 * (divisor + 1))`
+pub fn baud_divisor(baud: u32) -> Result<u8> {
+    let divisor = (CLOCK * 1e6 / (8.0 * f64::from(baud))).round() - 1.0;
+    if !(0.0..=31.0).contains(&divisor) {
+        return Err(invalid_input(format!("baud rate {} out of range", baud)));
+    }
+    Ok(divisor as u8)
+}
+
+impl<T: Read + Write> Chain<T> {
+    pub fn new(port: T) -> Self {
+        Self {
+            port,
+            chips: Vec::new(),
+        }
+    }
+
+    // the chips found by `discover`
+    pub fn chips(&self) -> &[Chip] {
+        &self.chips
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.port
+    }
+
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+
+    pub fn send(&mut self, command: ChipCommand) -> Result<()> {
+        let frame = command.encode();
+        debug!("send chip command: 0x{}", frame.to_hex());
+        self.port.write_all(&frame)?;
+        self.port.flush()
+    }
+
+    // the answers until the port times out, the frames broken are dropped
+    pub fn responses(&mut self) -> Result<Vec<RegisterResponse>> {
+        let mut received = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match self.port.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => break,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut responses = Vec::new();
+        let mut rest = &received[..];
+        while rest.len() >= RESPONSE_SIZE {
+            match RegisterResponse::decode(&rest[..RESPONSE_SIZE]) {
+                Some(response) => {
+                    responses.push(response);
+                    rest = &rest[RESPONSE_SIZE..];
+                }
+                None => {
+                    debug!("drop chip response byte: 0x{:02x}", rest[0]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        Ok(responses)
+    }
+
+    pub fn read_register(
+        &mut self,
+        chip: Option<u8>,
+        register: u8,
+    ) -> Result<Vec<RegisterResponse>> {
+        self.send(ChipCommand::ReadRegister { chip, register })?;
+        self.responses()
+    }
+
+    // count the chips, address them evenly over the address space and read `registers` of each
+    pub fn discover(&mut self, registers: &[u8]) -> Result<&[Chip]> {
+        let count = self.read_register(None, CHIP_ADDRESS)?.len();
+        if count == 0 {
+            return Err(Error::new(ErrorKind::NotFound, "no chips on the chain"));
+        }
+        if count > MAX_CHIPS {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} chips on the chain", count),
+            ));
+        }
+
+        self.send(ChipCommand::ChainInactive)?;
+        let interval = MAX_CHIPS / count;
+        self.chips = (0..count)
+            .map(|i| Chip {
+                address: (i * interval) as u8,
+                registers: BTreeMap::new(),
+            })
+            .collect();
+        for i in 0..count {
+            let address = self.chips[i].address;
+            self.send(ChipCommand::SetAddress(address))?;
+        }
+
+        for register in registers {
+            let responses = self.read_register(None, *register)?;
+            if responses.len() != count {
+                warn!(
+                    "register 0x{:02x}: {} of {} chips answered!",
+                    register,
+                    responses.len(),
+                    count
+                );
+            }
+            for response in responses {
+                match self.chips.iter_mut().find(|x| x.address == response.chip) {
+                    Some(chip) => {
+                        chip.registers.insert(response.register, response.value);
+                    }
+                    None => warn!("answer of unknown chip 0x{:02x}!", response.chip),
+                }
+            }
+        }
+        info!("=> {} chips found on the chain!", count);
+        Ok(&self.chips)
+    }
+
+    // `chip` None is every chip, returns the pll set
+    pub fn set_frequency(&mut self, chip: Option<u8>, freq: f64) -> Result<Pll> {
+        let pll = Pll::from_freq(freq)?;
+        self.send(ChipCommand::set_pll(chip, pll))?;
+        Ok(pll)
+    }
+
+    pub fn set_baud(&mut self, baud: u32) -> Result<()> {
+        self.send(ChipCommand::set_baud(baud)?)
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use crc_all::CrcAlgo;
+use lazy_static::lazy_static;
+use tokio::codec::{Decoder, Encoder, Framed};
+use tokio::io;
+use tokio_serial::{Serial, SerialPortSettings};
+
+#[allow(unused_imports)]
+use crate::util::ToHex;
+use crate::work::Subwork;
+
+pub use self::chip::{
+    baud_divisor, Chain, Chip, ChipCommand, Pll, RegisterResponse, CHIP_ADDRESS, HASH_RATE,
+    MISC_CONTROL, PLL_PARAMETER,
+};
+pub use self::sim::{SimChain, SimChip, CHIP_ID};
+
+mod chip;
+mod sim;
+
+fn crc5_usb(data: &[u8]) -> u8 {
+    lazy_static! {
+        static ref CRC5_USB: CrcAlgo<u8> = CrcAlgo::<u8>::new(0x05, 5, 0x1f, 0x1f, true);
+    };
+    let crc = &mut 0u8;
+    CRC5_USB.init_crc(crc);
+    CRC5_USB.update_crc(crc, data)
+}
+
+fn crc5_usb_check(data: &[u8]) -> bool {
+    crc5_usb(data) == 1
+}
+
+fn crc16_ccitt_false(data: &[u8]) -> u16 {
+    lazy_static! {
+        static ref CRC16_CCITT_FALSE: CrcAlgo<u16> =
+            CrcAlgo::<u16>::new(0x1021, 16, 0xffff, 0, false);
+    };
+    let crc = &mut 0u16;
+    CRC16_CCITT_FALSE.init_crc(crc);
+    CRC16_CCITT_FALSE.update_crc(crc, data)
+}
+
+// a nonce as the chips send it back: 0x55, the nonce (little endian), the subwork id, crc5
+const NONCE_HEADER: u8 = 0x55;
+pub const NONCE_SIZE: usize = 7;
+
+// as many as the ids of the subworks
+const SUBWORKS: usize = 256;
+
+// the ids searched back from the one of a nonce, the chips may be on an older subwork
+pub const DEFAULT_WINDOW: usize = 4;
+
+// the nonces remembered to tell the duplicates
+const DUPLICATES: usize = 32;
+
+pub fn nonce_response(nonce: u32, id: u8) -> [u8; NONCE_SIZE] {
+    let mut frame = [NONCE_HEADER, 0, 0, 0, 0, id, 0];
+    frame[1..5].copy_from_slice(&nonce.to_le_bytes());
+    for crc in 0..=0xff {
+        frame[6] = crc;
+        if crc5_usb_check(&frame) {
+            break;
+        }
+    }
+    frame
+}
+
+// the counters of the receiver since the codec was made
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReceiverStats {
+    // of a subwork sent
+    pub nonces: u64,
+    pub crc_errors: u64,
+    pub duplicates: u64,
+    // of no subwork in the search window
+    pub unmatched: u64,
+    // the times bytes were skipped to find the next nonce
+    pub resyncs: u64,
+}
+
+#[derive(Debug)]
+pub struct Codec {
+    subworkid: u8,
+    subworks: Vec<Option<Subwork>>,
+    window: usize,
+    received: VecDeque<[u8; NONCE_SIZE]>,
+    // skipping bytes since the last nonce
+    syncing: bool,
+    stats: Arc<Mutex<ReceiverStats>>,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl Codec {
+    // `window` subwork ids are searched for the subwork of a nonce
+    pub fn new(window: usize) -> Self {
+        Self {
+            subworkid: 0,
+            subworks: vec![None; SUBWORKS],
+            window: window.clamp(1, SUBWORKS),
+            received: VecDeque::with_capacity(DUPLICATES),
+            syncing: false,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<Mutex<ReceiverStats>> {
+        self.stats.clone()
+    }
+
+    fn skip(&mut self, src: &mut BytesMut, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let _skipped = src.split_to(n);
+        debug!("skip received: 0x{}", _skipped.to_hex());
+        if !self.syncing {
+            self.syncing = true;
+            self.stats.lock().unwrap().resyncs += 1;
+        }
+    }
+
+    // a nonce passing crc, remembered for the duplicates
+    fn take(&mut self, src: &mut BytesMut) -> [u8; NONCE_SIZE] {
+        let mut frame = [0; NONCE_SIZE];
+        frame.copy_from_slice(&src.split_to(NONCE_SIZE));
+        self.received.push_front(frame);
+        self.received.truncate(DUPLICATES);
+        self.syncing = false;
+        frame
+    }
+
+    fn duplicate(&self, frame: &[u8]) -> bool {
+        self.received.iter().any(|x| x[1..5] == frame[1..5])
+    }
+
+    // the subwork of a nonce and the version bits of its midstate
+    fn find(&self, frame: &[u8]) -> Option<<Self as Decoder>::Item> {
+        let nonce = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]);
+        let id = frame[5];
+        for i in 0..self.window {
+            let sw = match &self.subworks[usize::from(id.wrapping_sub(i as u8))] {
+                Some(sw) => sw,
+                None => continue,
+            };
+            for (version_bits, _) in &sw.midstates {
+                let target = sw.target(nonce, *version_bits);
+                if target.starts_with(b"\0\0\0\0") {
+                    return Some((sw.clone(), target, nonce, *version_bits));
+                }
+            }
+        }
+        None
+    }
+
+    // a nonce sent for sure, the bytes before it are not one
+    fn known(&self, frame: &[u8]) -> bool {
+        crc5_usb_check(frame) && (self.duplicate(frame) || self.find(frame).is_some())
+    }
+}
+
+impl Decoder for Codec {
+    // (subwork, target, nonce, version bits)
+    type Item = (Subwork, Bytes, u32, u32);
+    type Error = io::Error;
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<<Self as Decoder>::Item>, <Self as Decoder>::Error> {
+        loop {
+            let n = src
+                .iter()
+                .position(|x| *x == NONCE_HEADER)
+                .unwrap_or_else(|| src.len());
+            self.skip(src, n);
+            if src.len() < NONCE_SIZE {
+                return Ok(None);
+            }
+
+            if !crc5_usb_check(&src[..NONCE_SIZE]) {
+                debug!("crc error of received: 0x{}", src[..NONCE_SIZE].to_hex());
+                self.stats.lock().unwrap().crc_errors += 1;
+                self.skip(src, 1);
+                continue;
+            }
+
+            if self.duplicate(&src[..NONCE_SIZE]) {
+                let _frame = self.take(src);
+                debug!("duplicate received: 0x{}!", _frame.to_hex());
+                self.stats.lock().unwrap().duplicates += 1;
+                continue;
+            }
+
+            if let Some(found) = self.find(&src[..NONCE_SIZE]) {
+                let _frame = self.take(src);
+                debug!(
+                    "received: 0x{}, version bits: 0x{:08x}, target: 0x{}",
+                    _frame.to_hex(),
+                    found.3,
+                    found.1.to_hex()
+                );
+                self.stats.lock().unwrap().nonces += 1;
+                return Ok(Some(found));
+            }
+
+            // passing crc by chance if a nonce sent starts within
+            let mut garbage = false;
+            for i in 1..NONCE_SIZE {
+                if src[i] != NONCE_HEADER {
+                    continue;
+                }
+                if src.len() < i + NONCE_SIZE {
+                    return Ok(None);
+                }
+                if self.known(&src[i..i + NONCE_SIZE]) {
+                    garbage = true;
+                    break;
+                }
+            }
+            if garbage {
+                self.skip(src, 1);
+                continue;
+            }
+
+            let _frame = self.take(src);
+            debug!(
+                "lost the subwork of received (id: {}): 0x{}",
+                _frame[5],
+                _frame.to_hex()
+            );
+            self.stats.lock().unwrap().unmatched += 1;
+        }
+    }
+}
+
+impl Encoder for Codec {
+    type Item = Subwork;
+    type Error = io::Error;
+
+    // 0x20, the length, the id, data2 and the midstates reversed, crc16
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let len = 17 + 32 * item.midstates.len();
+        dst.reserve(len);
+        let start = dst.len();
+        dst.put_u8(0x20);
+        dst.put_u8(len as u8);
+        dst.put_u8(self.subworkid);
+        dst.extend(item.data2.iter().rev());
+        for (_, midstate) in &item.midstates {
+            dst.extend(midstate.iter().rev());
+        }
+        let crc = crc16_ccitt_false(&dst[start..]);
+        dst.extend(&crc.to_be_bytes());
+        self.subworks[self.subworkid as usize] = Some(item);
+        self.subworkid = self.subworkid.wrapping_add(1);
+        Ok(())
+    }
+}
+
+pub fn new<T: AsRef<Path>>(path: T) -> Serial {
+    let mut s = SerialPortSettings::default();
+    s.baud_rate = 1_500_000;
+
+    let mut port = Serial::from_path(path, &s).unwrap();
+    #[cfg(unix)]
+    port.set_exclusive(false)
+        .expect("set_exclusive(false) failed!");
+
+    port
+}
+
+pub fn framed(port: Serial) -> Framed<Serial, Codec> {
+    Codec::default().framed(port)
+}
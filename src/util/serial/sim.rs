@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use super::chip::{ChipCommand, RegisterResponse, CHIP_ADDRESS};
+
+// the chip id in the high half of `CHIP_ADDRESS`
+pub const CHIP_ID: u32 = 0x1387;
+
+#[derive(Clone, Debug, Default)]
+pub struct SimChip {
+    pub address: u8,
+    // waiting for its address after the chain is made inactive
+    pub inactive: bool,
+    pub registers: BTreeMap<u8, u32>,
+}
+
+// the chips of a chain behind a simulated serial port, the answers are read until it times out
+#[derive(Default)]
+pub struct SimChain {
+    pub chips: Vec<SimChip>,
+    // the commands understood by the chips
+    pub commands: Vec<ChipCommand>,
+    written: Vec<u8>,
+    answers: VecDeque<u8>,
+}
+
+impl SimChip {
+    fn register(&self, register: u8) -> u32 {
+        match register {
+            CHIP_ADDRESS => CHIP_ID << 16 | u32::from(self.address),
+            _ => self.registers.get(&register).cloned().unwrap_or(0),
+        }
+    }
+}
+
+impl SimChain {
+    pub fn new(chips: usize) -> Self {
+        Self {
+            chips: vec![SimChip::default(); chips],
+            ..Default::default()
+        }
+    }
+
+    fn run(&mut self, command: ChipCommand) {
+        let to = |chip: &SimChip, to: Option<u8>| to.is_none_or(|x| x == chip.address);
+        match command {
+            ChipCommand::ChainInactive => self.chips.iter_mut().for_each(|x| x.inactive = true),
+            ChipCommand::SetAddress(address) => {
+                if let Some(chip) = self.chips.iter_mut().find(|x| x.inactive) {
+                    chip.address = address;
+                    chip.inactive = false;
+                }
+            }
+            ChipCommand::ReadRegister { chip, register } => {
+                for x in self.chips.iter().filter(|x| to(x, chip)) {
+                    let response = RegisterResponse {
+                        chip: x.address,
+                        register,
+                        value: x.register(register),
+                    };
+                    self.answers.extend(response.encode().iter());
+                }
+            }
+            ChipCommand::WriteRegister {
+                chip,
+                register,
+                value,
+            } => {
+                for x in self.chips.iter_mut().filter(|x| to(x, chip)) {
+                    x.registers.insert(register, value);
+                }
+            }
+        }
+        self.commands.push(command);
+    }
+}
+
+impl Write for SimChain {
+    // the frames are taken whole by their length, a broken one is dropped a byte at a time
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.written.extend_from_slice(buf);
+        while self.written.len() >= 2 {
+            // the commands are 5 or 9 bytes long
+            let len = usize::from(self.written[1]);
+            if (len == 5 || len == 9) && self.written.len() < len {
+                break;
+            }
+            match ChipCommand::decode(&self.written[..len.min(self.written.len())]) {
+                Some(command) => {
+                    self.written.drain(..len);
+                    self.run(command);
+                }
+                None => {
+                    self.written.remove(0);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for SimChain {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.answers.is_empty() {
+            return Err(Error::new(ErrorKind::TimedOut, "no answer"));
+        }
+        let n = buf.len().min(self.answers.len());
+        for (x, answer) in buf.iter_mut().zip(self.answers.drain(..n)) {
+            *x = answer;
+        }
+        Ok(n)
+    }
+}
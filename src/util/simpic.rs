@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+use i2c_linux::Message;
+
+use super::i2c::{
+    Command, Transport, EEPROM_DATE, EEPROM_HOST_MAC, EEPROM_ID, EEPROM_MAC, EEPROM_POINTER,
+    EEPROM_SIZE, EEPROM_TEMP_OFFSET, EEPROM_WHICH_MAC,
+};
+
+// the flash of the pic, its eeprom in the same memory
+const FLASH_SIZE: usize = 0x1000;
+const EEPROM: usize = EEPROM_POINTER as usize * 2;
+
+// the state of a simulated pic, set and checked by the test
+#[derive(Clone, Debug)]
+pub struct PicState {
+    // the loader runs after a reset until the app is jumped to
+    pub loader: bool,
+    pub version: u8,
+    pub flash: Vec<u8>,
+    // word address
+    pub pointer: u16,
+    pub buffer: [u8; 16],
+    // a byte of the flash stuck at the erased value
+    pub stuck: Option<usize>,
+    // the raw value written by `set_voltage`
+    pub voltage: u8,
+    pub voltage_enabled: bool,
+    pub voltage_time: [u8; 6],
+    // the voltage is turned off without a heart beat for so long
+    pub heartbeat_timeout: Option<Duration>,
+    pub last_heartbeat: Option<Instant>,
+}
+
+// the pics of the boards on a simulated i2c bus, an address not plugged doesn't ack
+#[derive(Default)]
+pub struct SimPic {
+    pics: BTreeMap<u16, PicState>,
+    // (addr, command, data written) of the commands acked
+    pub commands: Vec<(u16, u8, Vec<u8>)>,
+    // the time the transfers happen at, the real clock if None
+    pub now: Option<Instant>,
+}
+
+impl Default for PicState {
+    fn default() -> Self {
+        Self {
+            loader: false,
+            version: 0x03,
+            flash: vec![0; FLASH_SIZE],
+            pointer: 0,
+            buffer: [0; 16],
+            stuck: None,
+            voltage: 0,
+            voltage_enabled: false,
+            voltage_time: [0; 6],
+            heartbeat_timeout: None,
+            last_heartbeat: None,
+        }
+    }
+}
+
+fn no_ack<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidInput, e.to_string())
+}
+
+// the data written with a command, of the length the command takes
+fn fixed<A: AsMut<[u8]> + Default>(cmd: Command, data: &[u8]) -> Result<A> {
+    let mut fixed = A::default();
+    if fixed.as_mut().len() != data.len() {
+        return Err(no_ack(format!("{:?} with {} bytes", cmd, data.len())));
+    }
+    fixed.as_mut().copy_from_slice(data);
+    Ok(fixed)
+}
+
+impl PicState {
+    // the voltage is cut by the pic when the host stops sending heart beats
+    pub fn tick(&mut self, now: Instant) {
+        if let (Some(timeout), Some(last)) = (self.heartbeat_timeout, self.last_heartbeat) {
+            if self.voltage_enabled && now.duration_since(last) > timeout {
+                self.voltage_enabled = false;
+            }
+        }
+    }
+
+    // the board data, at the end of the flash
+    pub fn eeprom(&mut self) -> &mut [u8] {
+        &mut self.flash[EEPROM..EEPROM + EEPROM_SIZE]
+    }
+
+    // a write by the app to its eeprom
+    fn write_eeprom<A: AsRef<[u8]>>(&mut self, at: usize, data: A) {
+        let data = data.as_ref();
+        self.eeprom()[at..at + data.len()].copy_from_slice(data);
+        if let Some(stuck) = self.stuck {
+            self.flash[stuck] = 0xff;
+        }
+    }
+
+    // run a command, returns the bytes read back
+    fn command(&mut self, cmd: Command, data: &[u8], now: Instant) -> Result<Vec<u8>> {
+        use self::Command::*;
+
+        self.tick(now);
+        let at = usize::from(self.pointer) * 2;
+        let mut answer = Vec::new();
+        match cmd {
+            RESET_PIC => {
+                self.loader = true;
+                self.voltage_enabled = false;
+            }
+            JUMP_FROM_LOADER_TO_APP => self.loader = false,
+            ERASE_PIC_APP_PROGRAM if self.loader => self.flash.iter_mut().for_each(|x| *x = 0xff),
+            ERASE_IIC_FLASH if !self.loader => self.eeprom().iter_mut().for_each(|x| *x = 0xff),
+            SET_PIC_FLASH_POINTER => self.pointer = u16::from_be_bytes(fixed(cmd, data)?),
+            GET_PIC_FLASH_POINTER => answer.extend_from_slice(&self.pointer.to_be_bytes()),
+            SEND_DATA_TO_IIC if self.loader => self.buffer = fixed(cmd, data)?,
+            WRITE_DATA_INTO_PIC if self.loader => {
+                let chunk = self
+                    .flash
+                    .get_mut(at..at + 16)
+                    .ok_or_else(|| no_ack(format!("write at 0x{:04x}", at)))?;
+                chunk.copy_from_slice(&self.buffer);
+                if let Some(stuck) = self.stuck {
+                    self.flash[stuck] = 0xff;
+                }
+                self.pointer += 8;
+            }
+            READ_DATA_FROM_IIC => {
+                let chunk = self
+                    .flash
+                    .get(at..at + 16)
+                    .ok_or_else(|| no_ack(format!("read at 0x{:04x}", at)))?;
+                answer.extend_from_slice(chunk);
+                self.pointer += 8;
+            }
+            _ if self.loader => return Err(no_ack(format!("{:?} in the loader", cmd))),
+            SET_VOLTAGE => self.voltage = fixed::<[u8; 1]>(cmd, data)?[0],
+            GET_VOLTAGE => answer.push(self.voltage),
+            ENABLE_VOLTAGE => {
+                self.voltage_enabled = fixed::<[u8; 1]>(cmd, data)?[0] != 0;
+                // the watchdog starts with the voltage
+                self.last_heartbeat = Some(now);
+            }
+            SEND_HEART_BEAT => self.last_heartbeat = Some(now),
+            GET_PIC_SOFTWARE_VERSION => answer.push(self.version),
+            SET_VOLTAGE_TIME => self.voltage_time = fixed(cmd, data)?,
+            SET_HASH_BOARD_ID => self.write_eeprom(EEPROM_ID.start, fixed::<[u8; 12]>(cmd, data)?),
+            GET_HASH_BOARD_ID => answer.extend_from_slice(&self.eeprom()[EEPROM_ID]),
+            SET_HOST_MAC_ADDRESS => {
+                self.write_eeprom(EEPROM_HOST_MAC.start, fixed::<[u8; 6]>(cmd, data)?)
+            }
+            GET_DATE => answer.extend_from_slice(&self.eeprom()[EEPROM_DATE]),
+            GET_WHICH_MAC => answer.push(self.eeprom()[EEPROM_WHICH_MAC]),
+            GET_MAC => answer.extend_from_slice(&self.eeprom()[EEPROM_MAC]),
+            WR_TEMP_OFFSET_VALUE => {
+                self.write_eeprom(EEPROM_TEMP_OFFSET.start, fixed::<[u8; 8]>(cmd, data)?)
+            }
+            RD_TEMP_OFFSET_VALUE => answer.extend_from_slice(&self.eeprom()[EEPROM_TEMP_OFFSET]),
+            ERASE_PIC_APP_PROGRAM | ERASE_IIC_FLASH | SEND_DATA_TO_IIC | WRITE_DATA_INTO_PIC => {
+                return Err(no_ack(format!("{:?} in the app", cmd)))
+            }
+        }
+        Ok(answer)
+    }
+}
+
+impl SimPic {
+    pub fn new<I: IntoIterator<Item = u16>>(addrs: I) -> Self {
+        let mut sim = Self::default();
+        for addr in addrs {
+            sim.plug(addr);
+        }
+        sim
+    }
+
+    // a board just plugged in, powered off
+    pub fn plug(&mut self, addr: u16) -> &mut PicState {
+        self.pics.insert(addr, PicState::default());
+        self.pic(addr)
+    }
+
+    pub fn unplug(&mut self, addr: u16) {
+        self.pics.remove(&addr);
+    }
+
+    pub fn pic(&mut self, addr: u16) -> &mut PicState {
+        self.pics
+            .get_mut(&addr)
+            .unwrap_or_else(|| panic!("no pic at 0x{:02x}", addr))
+    }
+
+    // the heart beat watchdogs of the pics, run on every transfer
+    pub fn tick(&mut self, now: Instant) {
+        self.pics.values_mut().for_each(|x| x.tick(now));
+    }
+}
+
+impl Transport for SimPic {
+    // the header and the command are written first, then the data written or read
+    fn transfer(&mut self, messages: &mut [Message<'_>]) -> Result<()> {
+        let now = self.now.unwrap_or_else(Instant::now);
+        self.tick(now);
+
+        let mut addr = None;
+        let mut written = Vec::new();
+        let mut reads = 0;
+        for message in messages.iter() {
+            let address = match message {
+                Message::Write { address, data, .. } => {
+                    written.extend_from_slice(data);
+                    address
+                }
+                Message::Read { address, data, .. } => {
+                    reads += data.len();
+                    address
+                }
+            };
+            if *addr.get_or_insert(*address) != *address {
+                return Err(no_ack("a transfer to more than one pic"));
+            }
+        }
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+
+        if written.len() < 3 || written[..2] != [0x55, 0xaa] {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("pic 0x{:02x}: bad header {:02x?}", addr, written),
+            ));
+        }
+        let cmd = Command::from_u8(written[2])
+            .ok_or_else(|| no_ack(format!("bad command 0x{:02x}", written[2])))?;
+        let data = written.split_off(3);
+        let pic = self.pics.get_mut(&addr).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("no ack from 0x{:02x}", addr))
+        })?;
+
+        let answer = pic.command(cmd, &data, now)?;
+        if answer.len() != reads {
+            return Err(no_ack(format!("{:?} reads {} bytes", cmd, answer.len())));
+        }
+        let mut answer = answer.into_iter();
+        for message in messages.iter_mut() {
+            if let Message::Read { data, .. } = message {
+                data.iter_mut().for_each(|x| *x = answer.next().unwrap());
+            }
+        }
+        self.commands.push((addr, cmd as u8, data));
+        Ok(())
+    }
+}
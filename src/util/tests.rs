@@ -0,0 +1,678 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+
+use crate::work::Subwork2;
+
+use super::fpga::{Reader, SerialSender, Writer};
+use super::*;
+
+fn subwork2() -> Subwork2 {
+    Subwork2 {
+        prevhash: Bytes::from(vec![1u8; 32]),
+        merkle_root: Bytes::from(vec![2u8; 32]),
+        ntime: Bytes::from(vec![3u8; 4]),
+        nbits: Bytes::from(vec![4u8; 4]),
+        version: 0x2000_0000,
+        vermask: 0x1fff_e000,
+        ..Default::default()
+    }
+}
+
+// (offset, value) of the writes, as seen by the emulator
+fn record_writes(registers: &SimRegisters) -> Arc<Mutex<Vec<(usize, u8)>>> {
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let writes_clone = writes.clone();
+    registers
+        .on_write(move |data, offset| writes_clone.lock().unwrap().push((offset, data[offset])));
+    writes
+}
+
+#[test]
+fn write_subwork2() {
+    let registers = SimRegisters::new(RegisterMap::V1.size);
+    let mut writer = Writer::new(&RegisterMap::V1, &registers);
+    writer.writer_subwork2(subwork2());
+
+    let data = registers.snapshot();
+    assert_eq!(data[0..8], [0x20, 0, 0, 0, 0x1f, 0xff, 0xe0, 0]);
+    assert_eq!(data[8..40], [1u8; 32]);
+    assert_eq!(data[40..72], [2u8; 32]);
+    assert_eq!(data[72..80], [3, 3, 3, 3, 4, 4, 4, 4]);
+    assert_eq!(writer.subworks().len(), 1);
+
+    writer.enable_sender(2);
+    assert_eq!(registers.read(84), 0);
+    assert_eq!(registers.read(85), 0b100);
+    writer.disable_sender(2);
+    assert_eq!(registers.read(85), 0);
+}
+
+#[test]
+fn read_nonce() {
+    let registers = SimRegisters::new(RegisterMap::V1.size);
+    let writes = record_writes(&registers);
+
+    let reader = Reader::new(&RegisterMap::V1, &registers, registers.interrupts());
+    let (nonce_reader, receiver) = reader.read_nonce();
+    thread::spawn(move || nonce_reader.wait());
+    let mut received = receiver.wait();
+
+    // the next result is raised after the previous one is acked
+    let result: Vec<u8> = (1..=13).collect();
+    registers.raise(148, &result);
+    assert_eq!(received.next().unwrap().unwrap(), result);
+    registers.raise(148, &[0xff; 13]);
+    assert_eq!(received.next().unwrap().unwrap(), [0xff; 13][..]);
+
+    // every result is acked by toggling the bit 3 of csr_in
+    let acks = writes.lock().unwrap().clone();
+    assert_eq!(acks, [(80, 0b1000), (80, 0), (80, 0b1000), (80, 0)]);
+}
+
+#[test]
+fn serial_sender() {
+    let registers = SimRegisters::new(RegisterMap::V1.size);
+    let mut sender = SerialSender::new(&RegisterMap::V1, &registers);
+
+    // csr_out bit 0 tells the fpga is ready for the work
+    registers.raise(82, &[1]);
+    let writes = record_writes(&registers);
+    sender.writer_work(b"\x55\xaa", 0x1234);
+
+    let data = registers.snapshot();
+    assert_eq!(data[86..90], [0x34, 0x12, 0x55, 0xaa]);
+    let writes = writes.lock().unwrap().clone();
+    assert_eq!(writes.first(), Some(&(80, 0b1)));
+    assert_eq!(writes[writes.len() - 2..], [(80, 0b11), (80, 0b1)]);
+
+    registers.raise(144, &0x0102_0304u32.to_le_bytes());
+    assert_eq!(sender.get_count(), 0x0102_0304);
+
+    sender.select_board(3);
+    assert_eq!(registers.read(84), 0b1000);
+}
+
+#[test]
+fn register_map() {
+    for map in RegisterMap::REVISIONS {
+        let mut used = vec![false; map.size];
+        for register in &map.registers() {
+            assert!(register.width > 0);
+            for byte in &mut used[register.offset..register.offset + register.width] {
+                assert!(!*byte, "{} {} overlaps", map.revision, register.name);
+                *byte = true;
+            }
+            for bitfield in register.bitfields {
+                assert!(bitfield.bit < register.width * 8);
+            }
+        }
+    }
+
+    let map = &RegisterMap::V1;
+    assert_eq!(map.registers().len(), 7);
+    assert_eq!(map.csr_in.bits.send_work, 0);
+    assert_eq!(map.csr_in.bits.notify, 1);
+    assert_eq!(map.csr_in.bits.ack, 3);
+    assert_eq!(map.csr_in.bitfields[2].name, "ack");
+    assert_eq!(map.csr_out.bits.ready, 0);
+    assert_eq!(map.csr_out.access, Access::ReadOnly);
+}
+
+#[test]
+#[should_panic(expected = "read-only register csr_out")]
+fn read_only_register() {
+    let registers = SimRegisters::new(RegisterMap::V1.size);
+    let mut csr_out = RegisterMap::V1.csr_out.window(&registers);
+    assert_eq!(csr_out.read(0), 0);
+    csr_out.write(0, 1);
+}
+
+#[test]
+fn validate_config() {
+    let mut config: Config = toml::from_str(
+        r#"
+        [client]
+        [client.version-rolling]
+        mask = "1fffe000"
+
+        [board]
+        enabled = []
+        default = {}
+
+        [fan]
+
+        [[pool]]
+        addr = "127.0.0.1:3333"
+        user = "user.0"
+        pass = ""
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+
+    // the values that would panic or never trip, caught at load time
+    let fan = config.fan.clone().unwrap();
+    for failure_time in &[-1.0, f64::NAN, f64::INFINITY] {
+        config.fan.as_mut().unwrap().failure_time = *failure_time;
+        assert!(config.validate().is_err());
+    }
+    config.fan = Some(Fan {
+        min_duty: 1.5,
+        ..fan.clone()
+    });
+    assert!(config.validate().is_err());
+
+    config.fan = Some(fan);
+    config.board.temp.critical = config.board.temp.warning;
+    assert!(config.validate().is_err());
+    config.board.temp = TempLimits {
+        hysteresis: f64::NAN,
+        ..TempLimits::default()
+    };
+    assert!(config.validate().is_err());
+
+    config.board.temp = TempLimits::default();
+    let reconnect = config.client.reconnect.clone();
+    config.client.reconnect.idle_timeout = 0.0;
+    assert!(config.validate().is_err());
+    for multiplier in &[0.5, f64::NAN, f64::INFINITY] {
+        config.client.reconnect = Reconnect {
+            multiplier: *multiplier,
+            ..reconnect.clone()
+        };
+        assert!(config.validate().is_err());
+    }
+    for jitter in &[-0.1, 1.5, f64::NAN, f64::INFINITY] {
+        config.client.reconnect = Reconnect {
+            jitter: *jitter,
+            ..reconnect.clone()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    config.client.reconnect = reconnect;
+    for quantum in &[-1.0, f64::NAN, 1e20] {
+        config.strategy.quantum = *quantum;
+        assert!(config.validate().is_err());
+    }
+    config.strategy.quantum = 10.0;
+    for weight in &[-1.0, f64::NAN, f64::INFINITY] {
+        config.pool[0].weight = *weight;
+        assert!(config.validate().is_err());
+    }
+
+    config.pool[0].weight = 1.0;
+    for interval in &[0.0, -600.0, f64::NAN] {
+        config.tune = Some(Tune {
+            interval: *interval,
+            ..Tune::default()
+        });
+        assert!(config.validate().is_err());
+    }
+}
+
+#[test]
+fn detect_register_map() {
+    let uio = std::env::temp_dir().join(format!("stratum-uio-{}", process::id()));
+    fs::create_dir_all(uio.join("maps/map0")).unwrap();
+    fs::write(uio.join("name"), "fpga\n").unwrap();
+
+    fs::write(uio.join("maps/map0/size"), "0x00001000\n").unwrap();
+    assert_eq!(RegisterMap::detect(&uio).unwrap().revision, "v1");
+
+    fs::write(uio.join("maps/map0/size"), "0x80\n").unwrap();
+    assert!(RegisterMap::detect(&uio).is_err());
+
+    fs::write(uio.join("maps/map0/size"), "0x00001000\n").unwrap();
+    // the legacy devices may have any name
+    fs::write(uio.join("name"), "fpga-v9\n").unwrap();
+    assert_eq!(RegisterMap::detect(&uio).unwrap().revision, "v1");
+
+    fs::remove_dir_all(&uio).unwrap();
+    assert!(RegisterMap::detect(&uio).is_err());
+}
+
+#[test]
+fn decode_nonce_result() {
+    let mut received = vec![
+        0x78, 0x56, 0x34, 0x12, 0x08, 0x7e, 0, 0x02, 0x10, 0, 0, 0, 5,
+    ];
+    // the crc shares byte 6 with the bits it covers
+    received[6] = (0..=0xff)
+        .find(|x| {
+            received[6] = *x;
+            fpga::NonceResult::decode(&received).crc_ok
+        })
+        .unwrap();
+
+    let result = fpga::NonceResult::decode(&received);
+    assert_eq!(result.board, 5);
+    assert_eq!(result.chip, 8);
+    assert_eq!(result.core, 0x09);
+    assert_eq!(result.nonce, 0x1234_5678);
+    // the fpga is 4 works ahead of the chip (0x02 - 0x7e)
+    assert_eq!(result.version_count, 0x10 - 4);
+    assert!(result.crc_ok);
+
+    received[0] ^= 1;
+    assert!(!fpga::NonceResult::decode(&received).crc_ok);
+}
+
+#[test]
+fn pic_voltage() {
+    let mut pic = SimPic::new(Some(0x50));
+    pic.set_voltage(0x50, 8.6).unwrap();
+    assert_eq!(pic.pic(0x50).voltage, 142);
+    assert_eq!(pic.get_voltage(0x50).unwrap(), 8.6);
+    for vol in &[7.9, 8.2, 8.8, 9.0, 9.4] {
+        pic.set_voltage(0x50, *vol).unwrap();
+        assert!(
+            (pic.get_voltage(0x50).unwrap() - vol).abs() < 0.15,
+            "{}",
+            vol
+        );
+    }
+    // a lower raw value is a higher voltage
+    pic.set_voltage(0x50, 9.0).unwrap();
+    let high = pic.pic(0x50).voltage;
+    pic.set_voltage(0x50, 8.0).unwrap();
+    assert!(pic.pic(0x50).voltage > high);
+}
+
+#[test]
+fn pic_heartbeat() {
+    let mut pic = SimPic::new(Some(0x50));
+    let start = Instant::now();
+    pic.now = Some(start);
+    pic.pic(0x50).heartbeat_timeout = Some(Duration::from_millis(50));
+    pic.enable_voltage(0x50).unwrap();
+    for n in 1..=4 {
+        pic.now = Some(start + Duration::from_millis(40 * n));
+        pic.send_heart_beat(0x50).unwrap();
+    }
+    assert!(pic.pic(0x50).voltage_enabled);
+
+    // the pic turns the voltage off by itself when the heart beats stop
+    pic.tick(start + Duration::from_millis(160 + 50));
+    assert!(pic.pic(0x50).voltage_enabled);
+    pic.tick(start + Duration::from_millis(160 + 51));
+    assert!(!pic.pic(0x50).voltage_enabled);
+}
+
+#[test]
+fn pic_framing() {
+    let mut pic = SimPic::new(Some(0x50));
+    pic.set_flash_pointer(0x50, 0x1234).unwrap();
+    assert_eq!(pic.get_flash_pointer(0x50).unwrap(), 0x1234);
+    pic.set_temp_offset(0x50, 0x0102_0304_0506_0708).unwrap();
+    assert_eq!(pic.get_temp_offset(0x50).unwrap(), 0x0102_0304_0506_0708);
+    assert_eq!(
+        pic.commands,
+        [
+            (
+                0x50,
+                i2c::Command::SET_PIC_FLASH_POINTER as u8,
+                vec![0x12, 0x34]
+            ),
+            (0x50, i2c::Command::GET_PIC_FLASH_POINTER as u8, vec![]),
+            (
+                0x50,
+                i2c::Command::WR_TEMP_OFFSET_VALUE as u8,
+                (1..=8).collect()
+            ),
+            (0x50, i2c::Command::RD_TEMP_OFFSET_VALUE as u8, vec![]),
+        ]
+    );
+
+    // nothing answers at an address not plugged, the loader knows no app command
+    assert_eq!(
+        pic.get_software_version(0x51).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    pic.reset_pic(0x50).unwrap();
+    assert!(pic.get_software_version(0x50).is_err());
+    pic.jump_to_app(0x50).unwrap();
+    assert_eq!(pic.get_software_version(0x50).unwrap(), 0x03);
+}
+
+#[test]
+fn chip_commands() {
+    use super::serial::{ChipCommand, RegisterResponse, MISC_CONTROL, PLL_PARAMETER};
+
+    let frame = ChipCommand::ChainInactive.encode();
+    assert_eq!(frame[..4], [0x55, 0x05, 0x00, 0x00]);
+    let frame = ChipCommand::SetAddress(0x04).encode();
+    assert_eq!(frame[..4], [0x41, 0x05, 0x04, 0x00]);
+    assert_eq!(
+        ChipCommand::set_baud(115_200).unwrap(),
+        ChipCommand::WriteRegister {
+            chip: None,
+            register: MISC_CONTROL,
+            value: 26 << 8,
+        }
+    );
+
+    let commands = [
+        ChipCommand::ChainInactive,
+        ChipCommand::SetAddress(0xfc),
+        ChipCommand::ReadRegister {
+            chip: None,
+            register: PLL_PARAMETER,
+        },
+        ChipCommand::ReadRegister {
+            chip: Some(0x08),
+            register: PLL_PARAMETER,
+        },
+        ChipCommand::WriteRegister {
+            chip: Some(0x08),
+            register: PLL_PARAMETER,
+            value: 0x0068_0141,
+        },
+        ChipCommand::set_baud(1_500_000).unwrap(),
+    ];
+    for command in commands.iter() {
+        let mut frame = command.encode();
+        assert_eq!(frame.len(), usize::from(frame[1]));
+        assert_eq!(ChipCommand::decode(&frame), Some(*command));
+        frame[2] ^= 0x01;
+        assert_eq!(ChipCommand::decode(&frame), None, "{:?}", command);
+    }
+
+    let response = RegisterResponse {
+        chip: 0x08,
+        register: PLL_PARAMETER,
+        value: 0x0068_0141,
+    };
+    let mut frame = response.encode();
+    assert_eq!(RegisterResponse::decode(&frame), Some(response));
+    frame[1] ^= 0x80;
+    assert_eq!(RegisterResponse::decode(&frame), None);
+}
+
+#[test]
+fn pll() {
+    use super::serial::{baud_divisor, Pll};
+
+    let pll = Pll::from_freq(650.0).unwrap();
+    assert_eq!(pll.freq(), 650.0);
+    assert_eq!(Pll::from_value(pll.value()), pll);
+    for freq in &[100.0, 237.5, 384.0, 550.0, 700.0, 1200.0] {
+        let pll = Pll::from_freq(*freq).unwrap();
+        assert!(
+            (pll.freq() - freq).abs() / freq < 0.01,
+            "{} {:?}",
+            freq,
+            pll
+        );
+    }
+    assert!(Pll::from_freq(50.0).is_err());
+    assert!(Pll::from_freq(2000.0).is_err());
+
+    assert_eq!(baud_divisor(115_200).unwrap(), 26);
+    assert_eq!(baud_divisor(1_500_000).unwrap(), 1);
+    assert!(baud_divisor(9600).is_err());
+}
+
+#[test]
+fn discover_chain() {
+    use super::serial::{Chain, Pll, SimChain, CHIP_ADDRESS, CHIP_ID, PLL_PARAMETER};
+
+    let mut chain = Chain::new(SimChain::new(63));
+    let chips = chain.discover(&[CHIP_ADDRESS, PLL_PARAMETER]).unwrap();
+    assert_eq!(chips.len(), 63);
+    for (i, chip) in chips.iter().enumerate() {
+        assert_eq!(chip.address, (i * 4) as u8);
+        assert_eq!(
+            chip.registers[&CHIP_ADDRESS],
+            CHIP_ID << 16 | u32::from(chip.address)
+        );
+        assert_eq!(chip.registers[&PLL_PARAMETER], 0);
+    }
+
+    // every chip or one of them
+    let pll = chain.set_frequency(None, 650.0).unwrap();
+    let slow = chain.set_frequency(Some(0x08), 400.0).unwrap();
+    let responses = chain.read_register(None, PLL_PARAMETER).unwrap();
+    assert_eq!(responses.len(), 63);
+    for response in responses {
+        let expected = if response.chip == 0x08 { slow } else { pll };
+        assert_eq!(Pll::from_value(response.value), expected);
+    }
+    assert_eq!(
+        chain.read_register(Some(0x0c), CHIP_ADDRESS).unwrap().len(),
+        1
+    );
+    assert!(chain
+        .read_register(Some(0x0d), CHIP_ADDRESS)
+        .unwrap()
+        .is_empty());
+
+    // the garbage on the line is skipped
+    chain.get_mut().write_all(&[0x00, 0x01, 0xff]).unwrap();
+    assert_eq!(chain.read_register(None, CHIP_ADDRESS).unwrap().len(), 63);
+
+    let mut empty = Chain::new(SimChain::new(0));
+    let e = empty.discover(&[]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+}
+
+// block 125552 with the versions rolled by `vermask`, its nonce found on the version 1
+fn block_subwork(vermask: u32) -> (crate::work::Subwork, u32) {
+    let header = Bytes::from(
+        concat!(
+            "01000000",
+            "81cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a308000000000000",
+            "e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0f1fc122b",
+            "c7f5d74d",
+            "f2b9441a",
+        )
+        .from_hex()
+        .unwrap(),
+    )
+    .flip32();
+    let subwork = crate::work::Subwork::new(
+        String::from("1"),
+        header,
+        Bytes::from(vec![0; 4]),
+        vermask,
+        0,
+        8,
+    );
+    (subwork, 0x9546_a142)
+}
+
+#[test]
+fn serial_midstates() {
+    use tokio::codec::{Decoder, Encoder};
+
+    use super::serial::{nonce_response, Codec};
+    use crate::stratum::Params;
+
+    // the version 1 is the second of the versions rolled
+    let (subwork, nonce) = block_subwork(0x03);
+    let versions: Vec<_> = subwork.midstates.iter().map(|x| x.0).collect();
+    assert_eq!(versions, [0, 1, 2, 3]);
+
+    let mut codec = Codec::default();
+    let mut frame = BytesMut::new();
+    codec.encode(subwork.clone(), &mut frame).unwrap();
+    assert_eq!(frame.len(), 145);
+    assert_eq!(frame[..3], [0x20, 145, 0]);
+    assert_eq!(
+        frame[3..15],
+        subwork.data2.iter().rev().cloned().collect::<Vec<_>>()[..]
+    );
+    assert_eq!(
+        frame[15 + 32..15 + 64],
+        subwork.midstates[1]
+            .1
+            .iter()
+            .rev()
+            .cloned()
+            .collect::<Vec<_>>()[..]
+    );
+
+    let mut src = BytesMut::from(&nonce_response(nonce, 0)[..]);
+    let (found, target, found_nonce, version_bits) = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(found.workid, "1");
+    assert_eq!((found_nonce, version_bits), (nonce, 1));
+    assert_eq!(
+        target.to_hex(),
+        "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d"
+    );
+    assert!(src.is_empty());
+
+    match found.into_params("user", nonce, version_bits) {
+        Params::Submit2(params) => assert_eq!(params[5], "00000001"),
+        params => panic!("{:?}", params),
+    }
+
+    // a single version, submitted without
+    let mut single = subwork.clone();
+    single.vermask = 0;
+    match single.into_params("user", nonce, 0) {
+        Params::Submit(params) => assert_eq!(params[4], "9546a142"),
+        params => panic!("{:?}", params),
+    }
+}
+
+// xorshift, the same bytes on every run
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, max: u64) -> Vec<u8> {
+        let len = self.next() % (max + 1);
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    // a header of a nonce in every 4 bytes or so
+    fn noise(&mut self, max: u64) -> Vec<u8> {
+        let mut bytes = self.bytes(max);
+        for x in bytes.iter_mut().filter(|x| **x < 0x40) {
+            *x = 0x55;
+        }
+        bytes
+    }
+}
+
+// feed `stream` to the codec in chunks of random length, the nonces decoded
+fn receive(
+    codec: &mut super::serial::Codec,
+    stream: &[u8],
+    random: &mut Random,
+) -> Vec<(u32, u32)> {
+    use super::serial::NONCE_SIZE;
+    use tokio::codec::Decoder;
+
+    let mut found = Vec::new();
+    let mut src = BytesMut::new();
+    let mut rest = stream;
+    while !rest.is_empty() {
+        let n = (random.next() % 16 + 1).min(rest.len() as u64) as usize;
+        src.extend_from_slice(&rest[..n]);
+        rest = &rest[n..];
+        while let Some((_, _, nonce, version_bits)) = codec.decode(&mut src).unwrap() {
+            found.push((nonce, version_bits));
+        }
+        // never more kept than to tell a nonce from the bytes before it
+        assert!(src.len() < 2 * NONCE_SIZE - 1, "{} bytes kept", src.len());
+    }
+    found
+}
+
+#[test]
+fn serial_receiver() {
+    use tokio::codec::Encoder;
+
+    use super::serial::{nonce_response, Codec, ReceiverStats};
+
+    let (subwork, nonce) = block_subwork(0x03);
+    let mut codec = Codec::default();
+    codec.encode(subwork.clone(), &mut BytesMut::new()).unwrap();
+    let stats = codec.stats();
+
+    let response = nonce_response(nonce, 0);
+    let mut broken = response;
+    broken[5] ^= 0x01;
+    let mut stream = vec![0x00, 0x01];
+    stream.extend_from_slice(&response);
+    stream.extend_from_slice(&response);
+    stream.extend_from_slice(&broken);
+    stream.extend_from_slice(&nonce_response(0x1234_5678, 0));
+    stream.extend_from_slice(&[0x00; 7]);
+
+    let found = receive(&mut codec, &stream, &mut Random(1));
+    assert_eq!(found, [(nonce, 1)]);
+    assert_eq!(
+        *stats.lock().unwrap(),
+        ReceiverStats {
+            nonces: 1,
+            crc_errors: 1,
+            duplicates: 1,
+            unmatched: 1,
+            resyncs: 3,
+        }
+    );
+
+    // the chips on a subwork older than the window
+    let mut codec = Codec::new(4);
+    let mut newer = Codec::new(8);
+    for codec in [&mut codec, &mut newer].iter_mut() {
+        codec.encode(subwork.clone(), &mut BytesMut::new()).unwrap();
+        for _ in 0..5 {
+            codec
+                .encode(subwork2().subwork(0, 1), &mut BytesMut::new())
+                .unwrap();
+        }
+    }
+    let stream = nonce_response(nonce, 5);
+    assert!(receive(&mut codec, &stream, &mut Random(1)).is_empty());
+    assert_eq!(codec.stats().lock().unwrap().unmatched, 1);
+    assert_eq!(receive(&mut newer, &stream, &mut Random(1)), [(nonce, 1)]);
+}
+
+#[test]
+fn serial_receiver_fuzz() {
+    use tokio::codec::Encoder;
+
+    use super::serial::{nonce_response, Codec};
+
+    let (subwork, nonce) = block_subwork(0x03);
+    let response = nonce_response(nonce, 0);
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    for _ in 0..200 {
+        let mut codec = Codec::default();
+        codec.encode(subwork.clone(), &mut BytesMut::new()).unwrap();
+
+        // random bytes alone
+        let stream = random.noise(256);
+        assert!(receive(&mut codec, &stream, &mut random).is_empty());
+
+        // every nonce sent is received among the random bytes, as it or as a duplicate
+        let mut stream = Vec::new();
+        let sent = random.next() % 8 + 1;
+        for _ in 0..sent {
+            stream.extend(random.noise(32));
+            stream.extend_from_slice(&response);
+        }
+        stream.extend(random.noise(32));
+        let stats = codec.stats();
+        let found = receive(&mut codec, &stream, &mut random);
+        let stats = *stats.lock().unwrap();
+        assert!(found.iter().all(|x| *x == (nonce, 1)));
+        assert_eq!(stats.nonces + stats.duplicates, sent, "{:?}", stats);
+    }
+}
@@ -4,36 +4,47 @@ use serde::{Deserialize, Serialize};
 
 use super::util::*;
 
+pub use self::strategy::*;
 pub use self::subwork::*;
 pub use self::subwork2::*;
+pub use self::target::Target;
 
+mod strategy;
 mod subwork;
 mod subwork2;
+mod target;
 #[cfg(test)]
 mod tests;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Work {
     pub id: String,
     #[serde(deserialize_with = "hex_to::bytes")]
-    prevhash: Bytes,
+    pub(crate) prevhash: Bytes,
     #[serde(deserialize_with = "hex_to::bytes")]
-    coinbase1: Bytes,
+    pub(crate) coinbase1: Bytes,
     #[serde(deserialize_with = "hex_to::bytes")]
-    coinbase2: Bytes,
+    pub(crate) coinbase2: Bytes,
     #[serde(deserialize_with = "hex_to::bytes_vec")]
-    merkle_branch: Vec<Bytes>,
+    pub(crate) merkle_branch: Vec<Bytes>,
     #[serde(deserialize_with = "hex_to::u32")]
-    version: u32,
+    pub(crate) version: u32,
     #[serde(deserialize_with = "hex_to::bytes")]
-    nbits: Bytes,
+    pub(crate) nbits: Bytes,
     #[serde(deserialize_with = "hex_to::bytes")]
-    ntime: Bytes,
+    pub(crate) ntime: Bytes,
     pub clean: bool,
+    // header-only work (stratum v2 standard channel), already flipped
+    #[serde(skip)]
+    pub(crate) merkle_root: Option<Bytes>,
 }
 
 impl Work {
     fn merkle_root(&self, xnonce: &(&Bytes, Bytes)) -> Bytes {
+        if let Some(ref root) = self.merkle_root {
+            return root.clone();
+        }
+
         let mut coinbase = Bytes::with_capacity(250);
         coinbase.extend(&self.coinbase1);
         coinbase.extend(xnonce.0);
@@ -59,20 +70,23 @@ impl Work {
         header
     }
 
-    pub fn subwork(&self, xnonce: (&Bytes, Bytes)) -> Subwork {
+    // the midstates of as many versions rolled by `vermask` as the chips take
+    pub fn subwork(&self, xnonce: (&Bytes, Bytes), vermask: u32) -> Subwork {
         let block_header = self.block_header(&xnonce);
-        Subwork {
-            workid: self.id.clone(),
-            midstate: sha256_midstate(&block_header[..64]),
-            data2: Bytes::from(&block_header[64..]),
+        Subwork::new(
+            self.id.clone(),
             block_header,
-            xnonce2: xnonce.1,
-        }
+            xnonce.1,
+            vermask,
+            0,
+            MAX_MIDSTATES,
+        )
     }
 
     pub fn subwork2(&self, xnonce: (&Bytes, Bytes), vermask: u32) -> Subwork2 {
         Subwork2 {
             pool: 0,
+            session: 0,
             workid: self.id.clone(),
             prevhash: self.prevhash.clone(),
             merkle_root: self.merkle_root(&xnonce),
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use crate::util::{Strategy, StrategyKind};
+
+#[derive(Clone, Debug)]
+pub struct PoolStatus {
+    pub alive: bool,
+    pub weight: f64,
+    pub accepted_diff: f64,
+}
+
+// decides which pool the hashpower goes to, consulted whenever the
+// current slice has run out or the current pool died
+pub trait PoolStrategy: Send {
+    fn select(&mut self, current: usize, pools: &[PoolStatus]) -> (usize, Duration);
+}
+
+pub fn pool_strategy(config: &Strategy) -> Box<dyn PoolStrategy> {
+    let quantum = Duration::from_secs_f64(config.quantum);
+    match config.kind {
+        StrategyKind::Failover => Box::new(Failover { quantum }),
+        StrategyKind::RoundRobin => Box::new(RoundRobin { quantum }),
+        StrategyKind::Weighted => Box::new(Weighted::new(quantum)),
+        StrategyKind::Balance => Box::new(Balance { quantum }),
+    }
+}
+
+fn fallback(current: usize, pools: &[PoolStatus]) -> usize {
+    if current < pools.len() {
+        current
+    } else {
+        0
+    }
+}
+
+// the first alive pool in the configured order
+pub struct Failover {
+    pub quantum: Duration,
+}
+
+impl PoolStrategy for Failover {
+    fn select(&mut self, current: usize, pools: &[PoolStatus]) -> (usize, Duration) {
+        let next = pools
+            .iter()
+            .position(|x| x.alive)
+            .unwrap_or_else(|| fallback(current, pools));
+        (next, self.quantum)
+    }
+}
+
+pub struct RoundRobin {
+    pub quantum: Duration,
+}
+
+impl PoolStrategy for RoundRobin {
+    fn select(&mut self, current: usize, pools: &[PoolStatus]) -> (usize, Duration) {
+        let next = (1..=pools.len())
+            .map(|i| (current + i) % pools.len())
+            .find(|&i| pools[i].alive)
+            .unwrap_or_else(|| fallback(current, pools));
+        (next, self.quantum)
+    }
+}
+
+// split the hashing time by weight
+pub struct Weighted {
+    pub quantum: Duration,
+    spent: Vec<f64>,
+    alive: Vec<bool>,
+}
+
+impl Weighted {
+    pub fn new(quantum: Duration) -> Self {
+        Self {
+            quantum,
+            spent: Vec::new(),
+            alive: Vec::new(),
+        }
+    }
+}
+
+impl PoolStrategy for Weighted {
+    fn select(&mut self, current: usize, pools: &[PoolStatus]) -> (usize, Duration) {
+        self.spent.resize(pools.len(), 0.0);
+        self.alive.resize(pools.len(), false);
+        // a pool back from the dead would take all the time until it caught up, start over
+        let revived = pools
+            .iter()
+            .zip(&self.alive)
+            .any(|(x, &alive)| x.alive && !alive);
+        if revived {
+            self.spent.iter_mut().for_each(|x| *x = 0.0);
+        }
+        self.alive = pools.iter().map(|x| x.alive).collect();
+
+        let next = least_served(pools, |i, x| self.spent[i] / x.weight)
+            .unwrap_or_else(|| fallback(current, pools));
+
+        if pools.get(next).is_some_and(|x| x.alive) {
+            self.spent[next] += self.quantum.as_secs_f64();
+        }
+        (next, self.quantum)
+    }
+}
+
+// split the accepted difficulty by weight
+pub struct Balance {
+    pub quantum: Duration,
+}
+
+impl PoolStrategy for Balance {
+    fn select(&mut self, current: usize, pools: &[PoolStatus]) -> (usize, Duration) {
+        let next = least_served(pools, |_, x| x.accepted_diff / x.weight)
+            .unwrap_or_else(|| fallback(current, pools));
+        (next, self.quantum)
+    }
+}
+
+fn least_served<F: Fn(usize, &PoolStatus) -> f64>(
+    pools: &[PoolStatus],
+    served: F,
+) -> Option<usize> {
+    pools
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.alive && x.weight > 0.0)
+        .map(|(i, x)| (i, served(i, x)))
+        .fold(None, |min: Option<(usize, f64)>, (i, v)| match min {
+            Some((_, m)) if m <= v => min,
+            _ => Some((i, v)),
+        })
+        .map(|(i, _)| i)
+}
@@ -1,28 +1,75 @@
 use bytes::{BufMut, BytesMut};
-use futures::stream::Stream;
-use futures::{Async, Poll};
-use num_traits::cast::ToPrimitive;
-use tokio_serial::{ClearBuffer, SerialPort};
 
 use crate::stratum::Params;
-use crate::util::ToHex;
+use crate::util::{fpga, ToHex};
 
 use super::*;
 
+// the chips hash up to so many midstates of a subwork, each of its own version
+pub const MAX_MIDSTATES: usize = 4;
+
 #[derive(Clone, Debug, Default)]
 pub struct Subwork {
     pub workid: String,
-    pub midstate: Bytes,
+    // (version bits, midstate) of each version rolled
+    pub midstates: Vec<(u32, Bytes)>,
     pub data2: Bytes,
+    // of the version of the work, the bits of `vermask` are rolled
     pub block_header: Bytes,
     pub xnonce2: Bytes,
+    pub vermask: u32,
 }
 
 impl Subwork {
-    pub fn target(&self, nonce: u32) -> Bytes {
-        let mut target = BytesMut::with_capacity(80);
-        target.extend(&self.block_header);
-        debug_assert_eq!(target.len(), 76);
+    // the midstates of `count` versions rolled by `vermask` from the `first`, as many as there are
+    pub fn new(
+        workid: String,
+        block_header: Bytes,
+        xnonce2: Bytes,
+        vermask: u32,
+        first: u32,
+        count: usize,
+    ) -> Self {
+        let rest = Self::versions(vermask).saturating_sub(u64::from(first));
+        let count = (count.clamp(1, MAX_MIDSTATES) as u64).min(rest) as u32;
+        let mut subwork = Self {
+            workid,
+            midstates: Vec::new(),
+            data2: Bytes::from(&block_header[64..]),
+            block_header,
+            xnonce2,
+            vermask,
+        };
+        subwork.midstates = (first..first + count)
+            .map(|i| {
+                let version_bits = fpga::version_bits(vermask, i);
+                let header = subwork.header(version_bits);
+                (version_bits, sha256_midstate(&header[..64]))
+            })
+            .collect();
+        subwork
+    }
+
+    // the versions there are to roll
+    pub fn versions(vermask: u32) -> u64 {
+        1 << vermask.count_ones()
+    }
+
+    // the block header of the version with `version_bits` rolled in
+    pub fn header(&self, version_bits: u32) -> BytesMut {
+        let mut header = BytesMut::with_capacity(80);
+        let mut version = [0; 4];
+        version.copy_from_slice(&self.block_header[..4]);
+        let version = u32::from_be_bytes(version);
+        header.put_u32_be((version & !self.vermask) | (version_bits & self.vermask));
+        header.extend(&self.block_header[4..]);
+        debug_assert_eq!(header.len(), 76);
+
+        header
+    }
+
+    pub fn target(&self, nonce: u32, version_bits: u32) -> Bytes {
+        let mut target = self.header(version_bits);
         target.put_u32_be(nonce);
 
         target = target.flip32().sha256d();
@@ -32,82 +79,33 @@ impl Subwork {
     }
 
     pub fn target_diff(target: &Bytes) -> f64 {
-        2.695_994_666_715_064e67 / BigUint::from_bytes_be(target).to_f64().unwrap()
+        Target::from_be_bytes(target).difficulty()
     }
 
-    pub fn diff(&self, nonce: u32) -> f64 {
-        let target = self.target(nonce);
+    pub fn diff(&self, nonce: u32, version_bits: u32) -> f64 {
+        let target = self.target(nonce, version_bits);
         Self::target_diff(&target)
     }
 
-    pub fn into_params(self, name: &str, nonce: u32) -> Params {
-        Params::Submit([
+    // with the version bits when the version is rolled
+    pub fn into_params(self, name: &str, nonce: u32, version_bits: u32) -> Params {
+        let ntime = self.block_header[68..72].to_hex();
+        if self.vermask == 0 {
+            return Params::Submit([
+                String::from(name),
+                self.workid,
+                self.xnonce2.to_hex(),
+                ntime,
+                nonce.to_be_bytes().to_hex(),
+            ]);
+        }
+        Params::Submit2([
             String::from(name),
             self.workid,
             self.xnonce2.to_hex(),
-            self.block_header[68..72].to_hex(),
+            ntime,
             nonce.to_be_bytes().to_hex(),
+            (version_bits & self.vermask).to_be_bytes().to_hex(),
         ])
     }
 }
-
-pub struct SubworkMaker {
-    work: Work,
-    xnonce1: Bytes,
-    xnonce2_size: usize,
-    counter: BigUint,
-    serial_cloned: Box<dyn SerialPort>,
-    work_notify: Notify,
-}
-
-impl SubworkMaker {
-    pub fn new(
-        work: Work,
-        xnonce: &(Bytes, usize),
-        work_notify: Notify,
-        serial_cloned: Box<dyn SerialPort>,
-    ) -> Self {
-        work_notify.notified();
-        Self {
-            work,
-            xnonce1: Bytes::from(xnonce.0.as_ref()),
-            xnonce2_size: xnonce.1,
-            counter: BigUint::from(0u32),
-            serial_cloned,
-            work_notify,
-        }
-    }
-
-    fn next(&mut self) -> Option<Subwork> {
-        if self.work_notify.notified() {
-            if self.serial_cloned.clear(ClearBuffer::Output).is_ok() {
-                debug!("serial buffer cleared!");
-            };
-            return None;
-        }
-
-        if self.xnonce2_size * 8 < self.counter.bits() {
-            return None;
-        }
-
-        let xnonce2_tail = self.counter.to_bytes_be();
-        let size_diff = self.xnonce2_size - xnonce2_tail.len();
-
-        let mut xnonce2 = Bytes::with_capacity(self.xnonce2_size);
-        xnonce2.extend(vec![0u8; size_diff]);
-        xnonce2.extend(xnonce2_tail);
-
-        self.counter += 1u8;
-
-        Some(self.work.subwork((&self.xnonce1, xnonce2)))
-    }
-}
-
-impl Stream for SubworkMaker {
-    type Item = Subwork;
-    type Error = ();
-
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        Ok(Async::Ready(self.next()))
-    }
-}
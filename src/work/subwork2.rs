@@ -1,11 +1,12 @@
 use std::cmp::min;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use bytes::{BufMut, BytesMut};
 use futures::stream::Stream;
 use futures::{Async, Poll};
-use num_traits::cast::ToPrimitive;
 
 use crate::stratum::*;
 use crate::util::ToHex;
@@ -15,6 +16,7 @@ use super::*;
 #[derive(Clone, Debug, Default)]
 pub struct Subwork2 {
     pub pool: usize,
+    pub session: usize,
     pub workid: String,
     pub prevhash: Bytes,
     pub merkle_root: Bytes,
@@ -26,81 +28,133 @@ pub struct Subwork2 {
 }
 
 pub struct PoolData {
-    pub duration: Duration,
+    pub weight: f64,
+    pub connected: Arc<AtomicBool>,
+    pub authorized: Arc<AtomicBool>,
+    pub ledger: Arc<Mutex<Ledger>>,
     pub works: WorkStream,
     pub xnonce: Arc<Mutex<(Bytes, usize)>>,
     pub vermask: Arc<Mutex<Option<u32>>>,
     pub notify: Notify,
-    pub maker: Option<Subwork2Maker>,
+    pub session: Arc<AtomicUsize>,
+    pub maker: Option<(usize, Subwork2Maker)>,
+    pub jobs: Arc<Mutex<Jobs>>,
+}
+
+// the jobs of a pool the results can still be submitted for
+#[derive(Debug, Default)]
+pub struct Jobs {
+    session: usize,
+    prevhash: Bytes,
+    ids: HashSet<String>,
 }
 
 pub struct Subwork2Stream {
     pub pools: Arc<Mutex<Vec<PoolData>>>,
+    pub strategy: Box<dyn PoolStrategy>,
     pub current: usize,
     pub timeout: Instant,
 }
 
 impl PoolData {
-    pub fn from_pool(pool: &mut Pool, duration: Duration) -> Self {
+    pub fn from_pool(pool: &mut Pool, weight: f64) -> Self {
         Self {
-            duration,
+            weight,
+            connected: pool.connected.clone(),
+            authorized: pool.authorized.1.clone(),
+            ledger: pool.ledger.clone(),
             works: pool.workstream(),
             xnonce: pool.xnonce.clone(),
             vermask: pool.vermask.clone(),
             notify: pool.work_notify.clone(),
+            session: pool.session.clone(),
             maker: None,
+            jobs: pool.jobs.clone(),
+        }
+    }
+
+    pub fn is_valid(&self, sw2: &Subwork2) -> bool {
+        self.jobs.lock().unwrap().is_valid(sw2.session, &sw2.workid)
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            alive: self.connected.load(Ordering::SeqCst) && self.authorized.load(Ordering::SeqCst),
+            weight: self.weight,
+            accepted_diff: self.ledger.lock().unwrap().stats().accepted_diff,
         }
     }
 }
 
 impl Default for Subwork2Stream {
     fn default() -> Self {
+        Self::new(Box::new(Weighted::new(Duration::from_secs(10))))
+    }
+}
+
+impl Subwork2Stream {
+    pub fn new(strategy: Box<dyn PoolStrategy>) -> Self {
         Self {
             pools: Arc::new(Mutex::new(Vec::new())),
+            strategy,
             current: 0,
             timeout: Instant::now(),
         }
     }
-}
 
-impl Subwork2Stream {
     fn current(&mut self) -> usize {
         let pool = self.pools.lock().unwrap();
 
-        if pool.len() == 2 {
-            let now = Instant::now();
-            if now > self.timeout {
-                self.current ^= 1;
-                self.timeout = now + pool[self.current].duration;
-                debug!("switch to pool {}", self.current);
+        let now = Instant::now();
+        let alive = pool.get(self.current).is_some_and(|x| x.status().alive);
+        if now > self.timeout || !alive {
+            let status: Vec<_> = pool.iter().map(PoolData::status).collect();
+            let (next, duration) = self.strategy.select(self.current, &status);
+            if next != self.current {
+                debug!("switch to pool {}", next);
             }
-            self.current
-        } else {
-            0
+            self.current = next;
+            self.timeout = now + duration;
         }
+        self.current
     }
 }
 
 impl Stream for Subwork2Stream {
     type Item = (Subwork2, Notify, Duration);
-    type Error = ();
+    type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         let current = self.current();
         let pool = &mut self.pools.lock().unwrap()[current];
 
-        if let Ok(Async::Ready(Some(work))) = pool.works.poll() {
+        let session = pool.session.load(Ordering::SeqCst);
+        if let Async::Ready(Some(work)) = pool.works.poll()? {
             pool.notify.notified();
             let subwork2maker = Subwork2Maker::new(
                 work,
                 &pool.xnonce.lock().unwrap(),
                 pool.vermask.lock().unwrap().unwrap(),
             );
-            pool.maker = Some(subwork2maker);
+            pool.maker = Some((session, subwork2maker));
         }
 
         let subwork2 = match pool.maker {
-            Some(ref mut maker) => maker.next(),
+            // the pool has reconnected, wait for the next work
+            Some((s, _)) if s != session => {
+                pool.maker = None;
+                return Ok(Async::NotReady);
+            }
+            // the work goes on with the xnonce set by the pool
+            Some((_, ref mut maker)) if maker.is_stale(&pool.xnonce.lock().unwrap()) => {
+                *maker = Subwork2Maker::new(
+                    maker.work.clone(),
+                    &pool.xnonce.lock().unwrap(),
+                    maker.vermask,
+                );
+                maker.next()
+            }
+            Some((_, ref mut maker)) => maker.next(),
             None => return Ok(Async::NotReady),
         };
 
@@ -114,6 +168,7 @@ impl Stream for Subwork2Stream {
                     Duration::from_secs(10)
                 };
                 subwork2.pool = current;
+                subwork2.session = session;
                 Ok(Async::Ready(Some((subwork2, notify, timeout))))
             }
             None => Ok(Async::NotReady),
@@ -121,6 +176,25 @@ impl Stream for Subwork2Stream {
     }
 }
 
+impl Jobs {
+    // a new session, a new prevhash or clean_jobs make all the older jobs stale
+    pub fn update(&mut self, session: usize, work: &Work) {
+        if work.clean || session != self.session || work.prevhash != self.prevhash {
+            if !self.ids.is_empty() {
+                debug!("jobs {:?} are stale now", self.ids);
+            }
+            self.ids.clear();
+            self.session = session;
+            self.prevhash = work.prevhash.clone();
+        }
+        self.ids.insert(work.id.clone());
+    }
+
+    pub fn is_valid(&self, session: usize, id: &str) -> bool {
+        session == self.session && self.ids.contains(id)
+    }
+}
+
 impl Subwork2 {
     pub fn block_header(&self, version_bits: u32) -> BytesMut {
         let mut header = BytesMut::with_capacity(80);
@@ -136,6 +210,19 @@ impl Subwork2 {
         header
     }
 
+    // the subwork of `midstates` versions from the `first`, for the chips hashing from the midstates
+    pub fn subwork(&self, first: u32, midstates: usize) -> Subwork {
+        let block_header = self.block_header(self.version & self.vermask).freeze();
+        Subwork::new(
+            self.workid.clone(),
+            block_header,
+            self.xnonce2.clone(),
+            self.vermask,
+            first,
+            midstates,
+        )
+    }
+
     pub fn target(&self, nonce: u32, version_bits: u32) -> Bytes {
         let mut target = self.block_header(version_bits);
         target.put_u32_be(nonce);
@@ -147,7 +234,14 @@ impl Subwork2 {
     }
 
     pub fn target_diff(target: &Bytes) -> f64 {
-        2.695_994_666_715_064e67 / BigUint::from_bytes_be(target).to_f64().unwrap()
+        Target::from_be_bytes(target).difficulty()
+    }
+
+    // the target of the network, a share meeting it solves the block
+    pub fn network_target(&self) -> Target {
+        let mut nbits = [0; 4];
+        nbits.copy_from_slice(&self.nbits);
+        Target::from_nbits(u32::from_be_bytes(nbits))
     }
 
     pub fn into_params(self, name: &str, nonce: u32, version_bits: u32) -> Params {
@@ -181,6 +275,10 @@ impl Subwork2Maker {
         }
     }
 
+    fn is_stale(&self, xnonce: &(Bytes, usize)) -> bool {
+        self.xnonce1 != xnonce.0 || self.xnonce2_size != xnonce.1
+    }
+
     fn next(&mut self) -> Option<Subwork2> {
         if self.xnonce2_size * 8 < self.counter.bits() {
             return None;
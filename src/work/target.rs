@@ -0,0 +1,100 @@
+use std::fmt;
+
+use lazy_static::lazy_static;
+use num_bigint::BigUint;
+use num_traits::cast::ToPrimitive;
+use num_traits::{One, Zero};
+
+lazy_static! {
+    // the target of difficulty 1 (nbits 0x1d00ffff)
+    static ref DIFF1: BigUint = BigUint::from(0xffffu32) << 208;
+    static ref MAX: BigUint = (BigUint::one() << 256) - 1u32;
+}
+
+// 256-bit target, a hash meets the target when it is not greater than it
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target(BigUint);
+
+impl Target {
+    // big-endian, as the hash is displayed
+    pub fn from_be_bytes(bytes: &[u8]) -> Self {
+        Target(BigUint::from_bytes_be(bytes))
+    }
+
+    // little-endian, as the hash is computed and stratum v2 sends targets
+    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+        Target(BigUint::from_bytes_le(bytes))
+    }
+
+    // the compact form in the block header, negative targets are zero
+    pub fn from_nbits(nbits: u32) -> Self {
+        let exponent = nbits >> 24;
+        let mantissa = nbits & 0x007f_ffff;
+        if nbits & 0x0080_0000 != 0 {
+            return Target(BigUint::zero());
+        }
+
+        let mantissa = BigUint::from(mantissa);
+        let target = if exponent <= 3 {
+            mantissa >> (8 * (3 - exponent) as usize)
+        } else {
+            mantissa << (8 * (exponent - 3) as usize)
+        };
+        Target(target.min(MAX.clone()))
+    }
+
+    // floor(diff1 / difficulty), exact for any finite difficulty,
+    // a difficulty not greater than zero is met by every hash
+    pub fn from_difficulty(difficulty: f64) -> Self {
+        if difficulty.is_nan() || difficulty <= 0.0 {
+            return Target(MAX.clone());
+        }
+        if difficulty.is_infinite() {
+            return Target(BigUint::zero());
+        }
+
+        // difficulty = mantissa * 2^exponent
+        let (mantissa, exponent) = integer_decode(difficulty);
+        let target = if exponent >= 0 {
+            &*DIFF1 / (BigUint::from(mantissa) << exponent as usize)
+        } else {
+            (&*DIFF1 << (-exponent) as usize) / mantissa
+        };
+        Target(target.min(MAX.clone()))
+    }
+
+    pub fn is_met_by(&self, hash: &Target) -> bool {
+        hash <= self
+    }
+
+    pub fn difficulty(&self) -> f64 {
+        match self.0.to_f64() {
+            Some(x) if x > 0.0 => DIFF1.to_f64().unwrap() / x,
+            _ => f64::INFINITY,
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let bytes = self.0.to_bytes_be();
+        let mut be = [0; 32];
+        be[32 - bytes.len()..].copy_from_slice(&bytes);
+        be
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:064x}", self.0)
+    }
+}
+
+fn integer_decode(x: f64) -> (u64, i16) {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i16;
+    let mantissa = if exponent == 0 {
+        (bits & 0x000f_ffff_ffff_ffff) << 1
+    } else {
+        (bits & 0x000f_ffff_ffff_ffff) | 0x0010_0000_0000_0000
+    };
+    (mantissa, exponent - 1075)
+}
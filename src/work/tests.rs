@@ -1,3 +1,11 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use futures::future::lazy;
+use futures::{Async, Future, Sink, Stream};
+
+use crate::stratum::Pool;
+
 use super::*;
 
 #[test]
@@ -40,7 +48,273 @@ fn get_subwork() {
             .unwrap(),
     );
 
-    let subwork = work.subwork(xnonce.clone());
+    let subwork = work.subwork(xnonce.clone(), 0);
     assert_eq!(block_header, &subwork.block_header);
-    assert_eq!(midstate, &subwork.midstate);
+    assert_eq!(vec![(0, midstate)], subwork.midstates);
+}
+
+#[test]
+fn subwork_midstates() {
+    let work = work("1", 0, false);
+    let xnonce = (&Bytes::from(vec![0; 4]), Bytes::from(vec![0; 4]));
+
+    let subwork = work.subwork(xnonce.clone(), 0x1fff_e000);
+    assert_eq!(subwork.midstates.len(), MAX_MIDSTATES);
+    for (i, (version_bits, midstate)) in subwork.midstates.iter().enumerate() {
+        assert_eq!(*version_bits, fpga::version_bits(0x1fff_e000, i as u32));
+        let header = subwork.header(*version_bits);
+        assert_eq!(&header[4..], &subwork.block_header[4..]);
+        assert_eq!(midstate, &sha256_midstate(&header[..64]));
+    }
+
+    // as many as the versions there are
+    assert_eq!(work.subwork(xnonce.clone(), 0x0000_2000).midstates.len(), 2);
+    assert_eq!(work.subwork(xnonce, 0).midstates.len(), 1);
+}
+
+fn status(alive: bool, weight: f64, accepted_diff: f64) -> PoolStatus {
+    PoolStatus {
+        alive,
+        weight,
+        accepted_diff,
+    }
+}
+
+#[test]
+fn failover_strategy() {
+    let mut strategy = Failover {
+        quantum: Duration::from_secs(1),
+    };
+    let pools = [
+        status(false, 1.0, 0.0),
+        status(true, 1.0, 0.0),
+        status(true, 1.0, 0.0),
+    ];
+    assert_eq!(strategy.select(0, &pools).0, 1);
+    assert_eq!(strategy.select(2, &pools).0, 1);
+
+    let pools = [status(false, 1.0, 0.0), status(false, 1.0, 0.0)];
+    assert_eq!(strategy.select(1, &pools).0, 1);
+}
+
+#[test]
+fn round_robin_strategy() {
+    let mut strategy = RoundRobin {
+        quantum: Duration::from_secs(1),
+    };
+    let pools = [
+        status(true, 1.0, 0.0),
+        status(false, 1.0, 0.0),
+        status(true, 1.0, 0.0),
+    ];
+    let picks: Vec<_> = (0..4)
+        .scan(0, |current, _| {
+            *current = strategy.select(*current, &pools).0;
+            Some(*current)
+        })
+        .collect();
+    assert_eq!(picks, vec![2, 0, 2, 0]);
+}
+
+#[test]
+fn weighted_strategy() {
+    let mut strategy = Weighted::new(Duration::from_secs(1));
+    let pools = [status(true, 3.0, 0.0), status(true, 1.0, 0.0)];
+    let mut count = [0; 2];
+    let mut current = 0;
+    for _ in 0..40 {
+        current = strategy.select(current, &pools).0;
+        count[current] += 1;
+    }
+    assert_eq!(count, [30, 10]);
+
+    // the pool back after a long outage gets its share, not all the time
+    let dead = [status(true, 3.0, 0.0), status(false, 1.0, 0.0)];
+    for _ in 0..100 {
+        current = strategy.select(current, &dead).0;
+    }
+    let mut count = [0; 2];
+    for _ in 0..40 {
+        current = strategy.select(current, &pools).0;
+        count[current] += 1;
+    }
+    assert_eq!(count, [30, 10]);
+}
+
+#[test]
+fn balance_strategy() {
+    let mut strategy = Balance {
+        quantum: Duration::from_secs(1),
+    };
+    let pools = [status(true, 2.0, 300.0), status(true, 1.0, 200.0)];
+    assert_eq!(strategy.select(1, &pools).0, 0);
+    let pools = [status(true, 2.0, 500.0), status(true, 1.0, 200.0)];
+    assert_eq!(strategy.select(0, &pools).0, 1);
+}
+
+fn hash(header: &str) -> Target {
+    let mut hash = Bytes::from(header.from_hex().unwrap()).sha256d().to_vec();
+    hash.reverse();
+    Target::from_be_bytes(&hash)
+}
+
+#[test]
+fn genesis_block() {
+    let hash = hash(concat!(
+        "01000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a",
+        "29ab5f49",
+        "ffff001d",
+        "1dac2b7c"
+    ));
+    assert_eq!(
+        hash.to_string(),
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+    );
+
+    let network = Target::from_nbits(0x1d00_ffff);
+    assert!(network.is_met_by(&hash));
+    assert_eq!(network, Target::from_difficulty(1.0));
+    assert_eq!(network.difficulty(), 1.0);
+}
+
+#[test]
+fn block_125552() {
+    let hash = hash(concat!(
+        "01000000",
+        "81cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a308000000000000",
+        "e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0f1fc122b",
+        "c7f5d74d",
+        "f2b9441a",
+        "42a14695"
+    ));
+    assert_eq!(
+        hash.to_string(),
+        "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d"
+    );
+
+    let network = Target::from_nbits(0x1a44_b9f2);
+    assert_eq!(
+        network.to_string(),
+        "00000000000044b9f20000000000000000000000000000000000000000000000"
+    );
+    assert!(network.is_met_by(&hash));
+    assert!((network.difficulty() - 244_112.487_774_34).abs() < 1e-6);
+
+    // a share of the pool at its own difficulty, but not at twice of it
+    let difficulty = hash.difficulty();
+    assert!(Target::from_difficulty(difficulty.floor()).is_met_by(&hash));
+    assert!(!Target::from_difficulty(difficulty * 2.0).is_met_by(&hash));
+}
+
+#[test]
+fn target_from_difficulty() {
+    let diff1 = Target::from_nbits(0x1d00_ffff);
+    assert!(diff1.is_met_by(&diff1));
+
+    assert_eq!(
+        Target::from_difficulty(3.0).to_string(),
+        "0000000055550000000000000000000000000000000000000000000000000000"
+    );
+    assert_eq!(
+        Target::from_difficulty(0.5).to_string(),
+        "00000001fffe0000000000000000000000000000000000000000000000000000"
+    );
+    assert_eq!(
+        Target::from_difficulty(65536.0).to_string(),
+        "000000000000ffff000000000000000000000000000000000000000000000000"
+    );
+    assert_eq!(
+        Target::from_difficulty(0.0),
+        Target::from_be_bytes(&[0xff; 32])
+    );
+    assert!(Target::from_difficulty(1e-12) > Target::from_difficulty(1e-6));
+    assert!(Target::from_difficulty(1024.5) < Target::from_difficulty(1024.0));
+}
+
+fn work(id: &str, prevhash: u8, clean: bool) -> Work {
+    Work {
+        id: id.to_string(),
+        prevhash: Bytes::from(&[prevhash; 32][..]),
+        coinbase1: Bytes::new(),
+        coinbase2: Bytes::new(),
+        merkle_branch: Vec::new(),
+        version: 0x2000_0000,
+        nbits: Bytes::from(&[0x17, 0x30, 0x68, 0x35][..]),
+        ntime: Bytes::from(&[0x5c, 0x50, 0x1c, 0x2a][..]),
+        clean,
+        merkle_root: None,
+    }
+}
+
+#[test]
+fn stale_jobs() {
+    let mut jobs = Jobs::default();
+    jobs.update(1, &work("1", 0, true));
+    jobs.update(1, &work("2", 0, false));
+    assert!(jobs.is_valid(1, "1"));
+    assert!(jobs.is_valid(1, "2"));
+    assert!(!jobs.is_valid(1, "3"));
+
+    // clean_jobs
+    jobs.update(1, &work("3", 0, true));
+    assert!(!jobs.is_valid(1, "1"));
+    assert!(!jobs.is_valid(1, "2"));
+    assert!(jobs.is_valid(1, "3"));
+
+    // new prevhash without clean_jobs
+    jobs.update(1, &work("4", 1, false));
+    assert!(!jobs.is_valid(1, "3"));
+    assert!(jobs.is_valid(1, "4"));
+
+    // reconnected, the job ids may be reused
+    assert!(!jobs.is_valid(2, "4"));
+    jobs.update(2, &work("5", 1, false));
+    assert!(!jobs.is_valid(1, "4"));
+    assert!(!jobs.is_valid(2, "4"));
+    assert!(jobs.is_valid(2, "5"));
+}
+
+// the subwork2 the stream has at once, if any
+fn poll_subwork2(stream: &mut Subwork2Stream) -> Option<Subwork2> {
+    match lazy(|| Ok::<_, ()>(stream.poll())).wait().unwrap() {
+        Ok(Async::Ready(Some((sw2, _, _)))) => Some(sw2),
+        _ => None,
+    }
+}
+
+#[test]
+fn set_extranonce() {
+    let mut pool = Pool::new("127.0.0.1:3333");
+    pool.connected.store(true, Ordering::SeqCst);
+    pool.authorized.1.store(true, Ordering::SeqCst);
+    *pool.xnonce.lock().unwrap() = (Bytes::from(vec![1u8; 4]), 4);
+    *pool.vermask.lock().unwrap() = Some(0x1fff_e000);
+
+    let mut stream = Subwork2Stream::default();
+    stream
+        .pools
+        .lock()
+        .unwrap()
+        .push(PoolData::from_pool(&mut pool, 1.0));
+    pool.work_channel
+        .0
+        .clone()
+        .send(work("1", 0, true))
+        .wait()
+        .unwrap();
+    let first = poll_subwork2(&mut stream).unwrap();
+    assert_eq!(first.xnonce2, Bytes::from(vec![0u8; 4]));
+
+    // the work goes on with the new xnonce, without waiting for the next one
+    *pool.xnonce.lock().unwrap() = (Bytes::from(vec![2u8; 4]), 2);
+    let sw2 = poll_subwork2(&mut stream).unwrap();
+    assert_eq!(sw2.workid, "1");
+    assert_eq!(sw2.xnonce2, Bytes::from(vec![0u8; 2]));
+    assert_ne!(sw2.merkle_root, first.merkle_root);
+    assert_eq!(
+        poll_subwork2(&mut stream).unwrap().xnonce2,
+        Bytes::from(vec![0, 1])
+    );
 }